@@ -1,43 +1,61 @@
 extern crate raytracer;
 use raytracer::math::*;
 use raytracer::primitive::Sphere;
-use raytracer::{Camera, Screen, World, Material};
+use raytracer::image::{self, PngOptions};
+use raytracer::{Camera, Screen, World, Material, Light, Color3, Background, EnvironmentMap, Sky, ColorSpace, TextureCache, PostEffect};
 use nalgebra_glm as glm;
 
-use std::{path::Path, fs::File, io::BufWriter, error::Error, sync::Arc as Shared};
-use png::HasParameters;
+use std::{error::Error, sync::Arc as Shared};
 
-fn slice_bytes<T>(slice: &[T]) -> &[u8] {
-    unsafe {
-        std::slice::from_raw_parts(
-            slice.as_ptr() as *const u8,
-            slice.len() * std::mem::size_of::<T>())
+fn main() -> Result<(), Box<dyn Error>> {
+    let sphere = Sphere::new(glm::zero(), 1.0, &Shared::new(Material::default()));
+    let ground = Sphere::new(Vec3::new(0.0, -101.0, 0.0), 100.0, &Shared::new(Material {
+        albedo: Color3::gray(0.5),
+        ..Material::default()
+    }));
+    // Each "--post effect:key=value,..." adds a post-processing effect, e.g. "--post bloom:threshold=2 --post vignette"
+    let mut args = std::env::args().skip(1);
+    let mut post = vec![];
+    let mut positional = vec![];
+    while let Some(arg) = args.next() {
+        if arg == "--post" {
+            let spec = args.next().ok_or("--post needs an effect")?;
+            post.push(spec.parse::<PostEffect>()?);
+        } else {
+            positional.push(arg);
+        }
     }
-}
-
-fn write_png<T>(bytes: &[u8], w: u32, h: u32, path: &T) -> Result<(), Box<Error>>
-    where T: AsRef<Path> {
-    let file = File::create(path)?;
-    let ref mut bw = BufWriter::new(file);
 
-    let mut e = png::Encoder::new(bw, w.into(), h.into());
-    e.set(png::ColorType::RGB).set(png::BitDepth::Eight);
-    let mut writer = e.write_header()?;
-    writer.write_image_data(&bytes)?;
-
-    Ok(())
-}
-
-fn main() -> Result<(), Box<Error>> {
-    let sphere = Sphere::new(glm::zero(), 1.0, &Shared::new(Material::default()));
-    let world = World { primitives: vec![Box::new(sphere)] };
+    // "sky" or an equirectangular .hdr map given on the command line lights the scene; otherwise a plain gradient does
+    let background = match positional.into_iter().next() {
+        Some(ref arg) if arg == "sky" => {
+            let mut sky = Sky::new(0.5, 0.8, 3.0);
+            sky.intensity = 0.05;
+            Background::Sky(Shared::new(sky))
+        }
+        Some(path) => Background::Environment(Shared::new(EnvironmentMap::load(path)?)),
+        None => Background::Gradient { horizon: Color3::new(0.6, 0.65, 0.7), zenith: Color3::new(0.15, 0.3, 0.6) },
+    };
+    let world = World {
+        primitives: vec![Box::new(sphere), Box::new(ground)],
+        lights: vec![
+            Light::directional(Vec3::new(1.0, 1.0, 1.0), Color3::gray(2.0)),
+            Light::sphere(Vec3::new(-2.0, 2.0, 1.0), 0.5, Color3::new(8.0, 6.0, 4.0)),
+        ],
+        background,
+        medium: None,
+        color_space: ColorSpace::LinearSrgb,
+        textures: Shared::new(TextureCache::default()),
+    };
     let camera = Camera::new(Vec3::new(0.0, 0.0, 2.0), glm::quat_identity(), consts::FRAC_PI_3, 16.0/9.0, None);
-    let screen = Screen::new(1920, 1080);
-    let image = screen.render(&camera, &world);
-    let image_bytes = slice_bytes(&image);
+    let mut screen = Screen::new(1920, 1080);
+    screen.samples_per_pixel = 16;
+    screen.post = post;
+    let image = screen.render_hdr(&camera, &world);
 
-    const PATH: &'static str = r"out/spherecast.png";
-    write_png(&image_bytes, screen.width as u32, screen.height as u32, &PATH)?;
+    const PATH: &str = r"out/spherecast.png";
+    let options = PngOptions { tone_mapper: screen.tone_mapper, ..PngOptions::default() };
+    image::write_png(PATH, &image, &options)?;
 
     Ok(())
-}
\ No newline at end of file
+}
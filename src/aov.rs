@@ -0,0 +1,315 @@
+use crate::math::*;
+use crate::Color3;
+use crate::image::{Layer, LayerData};
+use crate::integrator::PathSample;
+
+/// Which arbitrary output variables (render passes) to produce alongside the beauty image
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct AovSelection {
+    /// Distance along the camera ray to the first hit
+    pub depth: bool,
+    /// World-space normal at the first hit
+    pub normal: bool,
+    /// Base color of the first hit's material
+    pub albedo: bool,
+    pub object_id: bool,
+    pub material_id: bool,
+    pub uv: bool,
+    /// Beauty split into direct and indirect lighting
+    pub direct: bool,
+    pub indirect: bool,
+    /// Number of samples taken in each pixel, mostly useful with adaptive sampling
+    pub sample_count: bool,
+}
+impl AovSelection {
+    pub fn none() -> Self {
+        AovSelection::default()
+    }
+    pub fn all() -> Self {
+        AovSelection {
+            depth: true,
+            normal: true,
+            albedo: true,
+            object_id: true,
+            material_id: true,
+            uv: true,
+            direct: true,
+            indirect: true,
+            sample_count: true,
+        }
+    }
+}
+
+/**
+ * Per-pixel buffers for each selected AOV, in the same order as `Framebuffer::pixels`
+ *
+ * Pixels whose camera ray missed everything get infinite depth, zero normal/albedo/uv, and ID 0.
+ * Object and material IDs are offset by one so that 0 is left for the background. Albedo and the direct and
+ * indirect light are means over all of a pixel's samples, misses counting as zero, so that albedo matches the color
+ * the denoiser divides it out of and direct plus indirect adds up to the image; the others come from the first
+ * sample.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct AovBuffers {
+    pub depth: Option<Vec<f32>>,
+    pub normal: Option<Vec<Vec3>>,
+    pub albedo: Option<Vec<Color3>>,
+    pub object_id: Option<Vec<u32>>,
+    pub material_id: Option<Vec<u32>>,
+    pub uv: Option<Vec<Vec2>>,
+    pub direct: Option<Vec<Color3>>,
+    pub indirect: Option<Vec<Color3>>,
+    pub sample_count: Option<Vec<u32>>,
+}
+impl AovBuffers {
+    pub fn new(selection: &AovSelection, len: usize) -> Self {
+        fn buffer<T: Clone>(enabled: bool, len: usize, value: T) -> Option<Vec<T>> {
+            if enabled {
+                Some(vec![value; len])
+            } else {
+                None
+            }
+        }
+        let black = Color3::gray(0.0);
+        AovBuffers {
+            depth: buffer(selection.depth, len, f32::INFINITY),
+            normal: buffer(selection.normal, len, Vec3::new(0.0, 0.0, 0.0)),
+            albedo: buffer(selection.albedo, len, black),
+            object_id: buffer(selection.object_id, len, 0),
+            material_id: buffer(selection.material_id, len, 0),
+            uv: buffer(selection.uv, len, Vec2::new(0.0, 0.0)),
+            direct: buffer(selection.direct, len, black),
+            indirect: buffer(selection.indirect, len, black),
+            sample_count: buffer(selection.sample_count, len, 0),
+        }
+    }
+
+    /// Stores the AOVs of the first camera ray at pixel index i, other than those averaged by `add_means`
+    pub(crate) fn record(&mut self, i: usize, sample: &PathSample, material_id: Option<usize>) {
+        let hit = match &sample.hit {
+            Some(hit) => hit,
+            None => return,
+        };
+        if let Some(v) = &mut self.depth {
+            v[i] = hit.distance as f32;
+        }
+        if let Some(v) = &mut self.normal {
+            v[i] = hit.normal;
+        }
+        if let Some(v) = &mut self.object_id {
+            v[i] = hit.object_id as u32 + 1;
+        }
+        if let (Some(v), Some(id)) = (&mut self.material_id, material_id) {
+            v[i] = id as u32 + 1;
+        }
+        if let Some(v) = &mut self.uv {
+            v[i] = hit.uv;
+        }
+    }
+
+    /// Adds a sample's albedo, direct and indirect light to the means of pixel i, which now has `count` samples
+    pub(crate) fn add_means(&mut self, i: usize, sample: &PathSample, count: u32) {
+        fn add(v: &mut Option<Vec<Color3>>, i: usize, x: Color3, count: u32) {
+            if let Some(v) = v {
+                let mean = &mut v[i];
+                *mean += (x - *mean) / count as f32;
+            }
+        }
+        add(&mut self.albedo, i, sample.albedo, count);
+        add(&mut self.direct, i, sample.direct, count);
+        add(&mut self.indirect, i, sample.indirect, count);
+    }
+
+    /// Blends the means of pixel src of another set of buffers into pixel dst, weighed by their sample counts
+    pub(crate) fn merge_means(&mut self, from: &AovBuffers, src: usize, dst: usize, count: u32, from_count: u32) {
+        let weight = from_count as f32 / (count + from_count) as f32;
+        let merge = |to: &mut Option<Vec<Color3>>, from: &Option<Vec<Color3>>| {
+            if let (Some(to), Some(from)) = (to, from) {
+                let mean = &mut to[dst];
+                *mean += (from[src] - *mean) * weight;
+            }
+        };
+        merge(&mut self.albedo, &from.albedo);
+        merge(&mut self.direct, &from.direct);
+        merge(&mut self.indirect, &from.indirect);
+    }
+
+    /// Copies pixel src of another set of buffers, with the same selection, into pixel dst
+    pub(crate) fn copy_pixel(&mut self, from: &AovBuffers, src: usize, dst: usize) {
+        fn copy<T: Copy>(to: &mut Option<Vec<T>>, from: &Option<Vec<T>>, src: usize, dst: usize) {
+            if let (Some(to), Some(from)) = (to, from) {
+                to[dst] = from[src];
+            }
+        }
+        copy(&mut self.depth, &from.depth, src, dst);
+        copy(&mut self.normal, &from.normal, src, dst);
+        copy(&mut self.albedo, &from.albedo, src, dst);
+        copy(&mut self.object_id, &from.object_id, src, dst);
+        copy(&mut self.material_id, &from.material_id, src, dst);
+        copy(&mut self.uv, &from.uv, src, dst);
+        copy(&mut self.direct, &from.direct, src, dst);
+        copy(&mut self.indirect, &from.indirect, src, dst);
+    }
+
+    /// The enabled buffers as named EXR layers
+    pub fn layers(&self) -> Vec<Layer<'_>> {
+        let mut layers = vec![];
+        if let Some(v) = &self.depth {
+            layers.push(Layer::new("depth", LayerData::Gray(v)));
+        }
+        if let Some(v) = &self.normal {
+            layers.push(Layer::new("normal", LayerData::Xyz(v)));
+        }
+        if let Some(v) = &self.albedo {
+            layers.push(Layer::new("albedo", LayerData::Rgb(v)));
+        }
+        if let Some(v) = &self.object_id {
+            layers.push(Layer::new("object_id", LayerData::Id(v)));
+        }
+        if let Some(v) = &self.material_id {
+            layers.push(Layer::new("material_id", LayerData::Id(v)));
+        }
+        if let Some(v) = &self.uv {
+            layers.push(Layer::new("uv", LayerData::Uv(v)));
+        }
+        if let Some(v) = &self.direct {
+            layers.push(Layer::new("direct", LayerData::Rgb(v)));
+        }
+        if let Some(v) = &self.indirect {
+            layers.push(Layer::new("indirect", LayerData::Rgb(v)));
+        }
+        if let Some(v) = &self.sample_count {
+            layers.push(Layer::new("sample_count", LayerData::Id(v)));
+        }
+        layers
+    }
+
+    /// Sample counts as a heatmap: blue for the fewest samples through green to red for the most
+    pub fn sample_heatmap(&self) -> Option<Vec<Color3>> {
+        let counts = self.sample_count.as_ref()?;
+        let min = counts.iter().cloned().min().unwrap_or(0);
+        let max = counts.iter().cloned().max().unwrap_or(0);
+        let range = (max - min).max(1) as f32;
+        Some(counts.iter().map(|&c| {
+            let t = (c - min) as f32 / range;
+            if t < 0.5 {
+                Color3::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+            } else {
+                Color3::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+            }
+        }).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AovSelection, AovBuffers};
+    use crate::integrator::PathSample;
+    use crate::math::*;
+    use crate::primitive::Sphere;
+    use crate::sampler::Sampler;
+    use crate::{Camera, Screen, World, Material, Color3, Light, Background, Framebuffer, StopCondition};
+    use nalgebra_glm as glm;
+    use std::sync::Arc as Shared;
+
+    /// Puts every camera ray through the middle of its pixel
+    struct PixelCenters;
+    impl Sampler for PixelCenters {
+        fn sample(&self, _pixel: (usize, usize), _index: usize, _dimension: usize) -> Scalar {
+            0.5
+        }
+    }
+
+    #[test]
+    fn center_and_corner_passes() {
+        let red = Shared::new(Material::default());
+        let world = World {
+            primitives: vec![
+                Box::new(Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, &red)),
+                Box::new(Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0, &red)),
+            ],
+            ..World::default()
+        };
+        let camera = Camera::new(glm::zero(), glm::quat_identity(), consts::FRAC_PI_3, 1.0, None);
+        let mut screen = Screen::new(5, 5);
+        screen.aovs = AovSelection::all();
+        screen.sampler = Shared::new(PixelCenters);
+        let fb = screen.render_hdr(&camera, &world);
+
+        let center = fb.index(2, 2);
+        let corner = fb.index(0, 0);
+        let depth = fb.aovs.depth.as_ref().unwrap();
+        assert!((depth[center] - 2.0).abs() <= 1.0e-4);
+        assert!(depth[corner].is_infinite());
+
+        let normal = fb.aovs.normal.as_ref().unwrap();
+        assert!(glm::distance(&normal[center], &*consts::FORWARD) <= 1.0e-4);
+
+        // The nearer sphere is first in the world, and both share the first material
+        assert_eq!(fb.aovs.object_id.as_ref().unwrap()[center], 1);
+        assert_eq!(fb.aovs.material_id.as_ref().unwrap()[center], 1);
+        assert_eq!(fb.aovs.object_id.as_ref().unwrap()[corner], 0);
+
+        assert_eq!(fb.alpha.as_ref().unwrap()[corner], 0.0);
+        assert_eq!(fb.aovs.layers().len(), 9);
+    }
+
+    fn path(albedo: f32, direct: f32, indirect: f32) -> PathSample {
+        PathSample {
+            direct: Color3::gray(direct),
+            indirect: Color3::gray(indirect),
+            hit: None,
+            albedo: Color3::gray(albedo),
+            #[cfg(feature="spectral")]
+            wavelengths: crate::spectrum::Wavelengths::sample(0.5),
+        }
+    }
+
+    #[test]
+    fn means_are_taken_over_every_sample() {
+        let selection = AovSelection { albedo: true, direct: true, indirect: true, ..AovSelection::none() };
+        let values = [0.8, 0.0, 0.2, 0.6, 0.4];
+        let (mut a, mut b) = (AovBuffers::new(&selection, 1), AovBuffers::new(&selection, 1));
+        for (i, &v) in values.iter().enumerate() {
+            if i < 2 {
+                a.add_means(0, &path(v, 2.0 * v, 1.0 - v), i as u32 + 1);
+            } else {
+                b.add_means(0, &path(v, 2.0 * v, 1.0 - v), i as u32 - 1);
+            }
+        }
+        a.merge_means(&b, 0, 0, 2, 3);
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        assert!((a.albedo.unwrap()[0].g - mean).abs() <= 1.0e-6);
+        assert!((a.direct.unwrap()[0].g - 2.0 * mean).abs() <= 1.0e-6);
+        assert!((a.indirect.unwrap()[0].g - (1.0 - mean)).abs() <= 1.0e-6);
+    }
+
+    #[test]
+    fn direct_and_indirect_add_up_to_the_image() {
+        let red = Shared::new(Material::default());
+        let world = World {
+            primitives: vec![Box::new(Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, &red))],
+            lights: vec![Light::directional(Vec3::new(1.0, 1.0, 1.0), Color3::gray(3.0))],
+            background: Background::Constant(Color3::gray(0.5)),
+            ..World::default()
+        };
+        let camera = Camera::new(glm::zero(), glm::quat_identity(), consts::FRAC_PI_3, 1.0, None);
+        let mut screen = Screen::new(8, 8);
+        screen.aovs = AovSelection { direct: true, indirect: true, ..AovSelection::none() };
+        let check = |fb: &Framebuffer| {
+            let (direct, indirect) = (fb.aovs.direct.as_ref().unwrap(), fb.aovs.indirect.as_ref().unwrap());
+            for (i, p) in fb.pixels.iter().enumerate() {
+                let sum = direct[i] + indirect[i];
+                for &(a, b) in [(sum.r, p.r), (sum.g, p.g), (sum.b, p.b)].iter() {
+                    assert!((a - b).abs() <= 1.0e-3 * (1.0 + b.abs()), "{:?} {:?}", sum, p);
+                }
+            }
+        };
+        for &spp in [1, 4, 16].iter() {
+            screen.samples_per_pixel = spp;
+            check(&screen.render_hdr(&camera, &world));
+        }
+        // Passes are merged into the image one after another
+        check(&screen.render_progressive(&camera, &world, &StopCondition::samples(4), |_, _| {}));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::math::*;
+use crate::{Color3, ColorSpace, Framebuffer, LightSample, Sky};
+use crate::distribution::Distribution2d;
+use std::{io, path::Path};
+use std::sync::Arc as Shared;
+
+/**
+ * What rays see when they leave the scene without hitting anything
+ *
+ * Backgrounds light the scene like an infinitely large sphere around it, so they are sampled along with the
+ * lights. The y axis is up.
+ */
+#[derive(Debug, Clone)]
+pub enum Background {
+    Constant(Color3),
+    /// Blends from the horizon up to the zenith. Below the horizon is the horizon color.
+    Gradient { horizon: Color3, zenith: Color3 },
+    Environment(Shared<EnvironmentMap>),
+    /// A daylight sky with the sun in it
+    Sky(Shared<Sky>),
+}
+impl Background {
+    /// Radiance arriving from far away, seen looking in `direction`, in `color_space`. Constant and gradient colors
+    /// are taken to be in that space already.
+    pub fn radiance(&self, direction: &Vec3, color_space: ColorSpace) -> Color3 {
+        match self {
+            Background::Constant(c) => *c,
+            Background::Gradient { horizon, zenith } => horizon.mix(zenith, direction.y.max(0.0) as f32),
+            Background::Environment(map) => map.radiance(direction, color_space),
+            Background::Sky(sky) => sky.radiance(direction, color_space),
+        }
+    }
+
+    /// Whether the background gives off any light at all, and so is worth sampling
+    pub fn is_emissive(&self) -> bool {
+        let bright = |c: &Color3| c.r > 0.0 || c.g > 0.0 || c.b > 0.0;
+        match self {
+            Background::Constant(c) => bright(c),
+            Background::Gradient { horizon, zenith } => bright(horizon) || bright(zenith),
+            Background::Environment(map) => map.intensity > 0.0 && map.distribution.integral() > 0.0,
+            Background::Sky(sky) => sky.intensity > 0.0,
+        }
+    }
+
+    /// Chooses a direction towards the background. `u` is a uniformly distributed point in the unit square, and the
+    /// radiance is in `color_space`.
+    pub fn sample(&self, u: (Scalar, Scalar), color_space: ColorSpace) -> Option<LightSample> {
+        let (direction, pdf) = match self {
+            Background::Environment(map) => map.sample(u)?,
+            Background::Sky(sky) => sky.sample(u)?,
+            _ => (uniform_sphere(u), 1.0 / (4.0 * consts::PI)),
+        };
+        Some(LightSample {
+            direction,
+            distance: Scalar::INFINITY,
+            radiance: self.radiance(&direction, color_space),
+            pdf,
+        })
+    }
+
+    /// Density with which `sample` would choose `direction`
+    pub fn pdf(&self, direction: &Vec3) -> Scalar {
+        match self {
+            Background::Environment(map) => map.pdf(direction),
+            Background::Sky(sky) => sky.pdf(direction),
+            _ => 1.0 / (4.0 * consts::PI),
+        }
+    }
+}
+impl Default for Background {
+    fn default() -> Self {
+        Background::Constant(Color3::gray(0.0))
+    }
+}
+
+/**
+ * An equirectangular (latitude-longitude) image of the radiance arriving from every direction
+ *
+ * The top row looks straight up and the middle of the image looks down -z. Directions are sampled in proportion
+ * to the luminance of the image, so small bright features like the sun are found quickly.
+ */
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    image: Framebuffer,
+    /// Turns the map about the y axis, in radians
+    pub rotation: Scalar,
+    /// Scales the radiance of the whole map
+    pub intensity: f32,
+    distribution: Distribution2d,
+}
+impl EnvironmentMap {
+    pub fn new(image: Framebuffer) -> Self {
+        let (w, h) = (image.width, image.height);
+        // Rows near the poles cover less solid angle
+        let func: Vec<Scalar> = image.pixels.iter().enumerate().map(|(i, c)| {
+            let theta = ((i / w.max(1)) as Scalar + 0.5) / h as Scalar * consts::PI;
+            image.color_space.luminance(*c).max(0.0) as Scalar * theta.sin()
+        }).collect();
+        let distribution = Distribution2d::new(&func, w, h);
+        EnvironmentMap {
+            image,
+            rotation: 0.0,
+            intensity: 1.0,
+            distribution,
+        }
+    }
+
+    /// Loads a Radiance .hdr image
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(EnvironmentMap::new(crate::image::read_hdr(path)?))
+    }
+
+    pub fn image(&self) -> &Framebuffer {
+        &self.image
+    }
+
+    pub fn with_rotation(mut self, rotation: Scalar) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Rotates a world direction into the map's frame, or back out again
+    fn rotate(&self, d: &Vec3, angle: Scalar) -> Vec3 {
+        let (s, c) = angle.sin_cos();
+        Vec3::new(c * d.x + s * d.z, d.y, -s * d.x + c * d.z)
+    }
+
+    /// Image coordinates in [0, 1] of a direction
+    fn uv_of(&self, direction: &Vec3) -> Vec2 {
+        let d = self.rotate(direction, -self.rotation);
+        let u = 0.5 + d.x.atan2(-d.z) / (2.0 * consts::PI);
+        let v = d.y.clamp(-1.0, 1.0).acos() / consts::PI;
+        Vec2::new(u.clamp(0.0, 1.0), v)
+    }
+
+    pub(crate) fn direction_at(&self, uv: &Vec2) -> Vec3 {
+        let theta = uv.y * consts::PI;
+        let phi = (uv.x - 0.5) * 2.0 * consts::PI;
+        let d = Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+        self.rotate(&d, self.rotation)
+    }
+
+    /// Radiance seen looking in `direction`, converted from the image's color space to `color_space`
+    pub fn radiance(&self, direction: &Vec3, color_space: ColorSpace) -> Color3 {
+        let (w, h) = (self.image.width, self.image.height);
+        if w == 0 || h == 0 {
+            return Color3::gray(0.0);
+        }
+        let uv = self.uv_of(direction);
+        let x = ((uv.x * w as Scalar) as usize).min(w - 1);
+        let y = ((uv.y * h as Scalar) as usize).min(h - 1);
+        self.image.color_space.convert(self.image.get(x, y), color_space) * self.intensity
+    }
+
+    pub(crate) fn sample(&self, u: (Scalar, Scalar)) -> Option<(Vec3, Scalar)> {
+        let (uv, pdf) = self.distribution.sample(u);
+        let sin_theta = (uv.y * consts::PI).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        // From density over the image to density over the sphere
+        Some((self.direction_at(&uv), pdf / (2.0 * consts::PI * consts::PI * sin_theta)))
+    }
+
+    pub(crate) fn pdf(&self, direction: &Vec3) -> Scalar {
+        let uv = self.uv_of(direction);
+        let sin_theta = (uv.y * consts::PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(&uv) / (2.0 * consts::PI * consts::PI * sin_theta)
+    }
+}
+
+/// A direction drawn uniformly from the whole sphere
+fn uniform_sphere(u: (Scalar, Scalar)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * consts::PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{Sampler, Sobol};
+    use nalgebra_glm as glm;
+
+    /// A dark map with one bright pixel
+    fn spot_map() -> EnvironmentMap {
+        let mut image = Framebuffer::new(16, 8);
+        for p in image.pixels.iter_mut() {
+            *p = Color3::gray(0.1);
+        }
+        image.set(3, 2, Color3::gray(100.0));
+        EnvironmentMap::new(image)
+    }
+
+    #[test]
+    fn directions_round_trip() {
+        let map = spot_map().with_rotation(0.7);
+        for &d in [Vec3::new(0.0, 0.0, -1.0), glm::normalize(&Vec3::new(0.3, 0.5, 0.8)), glm::normalize(&Vec3::new(-1.0, -0.2, 0.1))].iter() {
+            assert!((map.direction_at(&map.uv_of(&d)) - d).norm() <= 1.0e-4);
+        }
+        // Straight ahead is the middle of the image
+        let uv = EnvironmentMap::new(Framebuffer::new(4, 4)).uv_of(&Vec3::new(0.0, 0.0, -1.0));
+        assert!((uv - Vec2::new(0.5, 0.5)).norm() <= 1.0e-6);
+    }
+
+    #[test]
+    fn sampling_finds_the_bright_spot() {
+        let background = Background::Environment(Shared::new(spot_map()));
+        let sampler = Sobol::new(1);
+        let n = 256;
+        let mut bright = 0;
+        for i in 0..n {
+            let s = background.sample(sampler.sample_2d((0, 0), i, 0), ColorSpace::LinearSrgb).unwrap();
+            assert!((background.pdf(&s.direction) - s.pdf).abs() <= 1.0e-3 * s.pdf);
+            if s.radiance.r > 1.0 {
+                bright += 1;
+            }
+        }
+        assert!(bright > n / 2, "{}", bright);
+
+        // The pdf integrates to 1 over the sphere
+        let m = 1 << 14;
+        let integral = (0..m).map(|i| background.pdf(&uniform_sphere(sampler.sample_2d((1, 0), i, 0)))).sum::<Scalar>()
+            * 4.0 * consts::PI / m as Scalar;
+        assert!((integral - 1.0).abs() <= 0.05, "{}", integral);
+    }
+
+    #[test]
+    fn radiance_is_converted_to_the_working_space() {
+        let mut image = Framebuffer::new(2, 1);
+        image.pixels = vec![Color3::new(1.0, 0.0, 0.0); 2];
+        image.color_space = ColorSpace::AcesCg;
+        let background = Background::Environment(Shared::new(EnvironmentMap::new(image)));
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let aces = background.radiance(&forward, ColorSpace::AcesCg);
+        assert_eq!((aces.r, aces.g, aces.b), (1.0, 0.0, 0.0));
+        let srgb = background.radiance(&forward, ColorSpace::LinearSrgb);
+        let expected = ColorSpace::AcesCg.convert(aces, ColorSpace::LinearSrgb);
+        assert!((srgb.r - expected.r).abs() <= 1.0e-6 && (srgb.g - expected.g).abs() <= 1.0e-6);
+        assert!(srgb.r > 1.0 && srgb.g < 0.0);
+    }
+}
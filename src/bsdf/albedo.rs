@@ -0,0 +1,66 @@
+use crate::math::*;
+
+/// Resolution of the tables, along both roughness and angle
+const TABLE_SIZE: usize = 32;
+
+/**
+ * A precomputed directional albedo, tabulated over roughness and the cosine of the view angle, both in [0, 1]
+ *
+ * Layers use it to work out how much light they keep from what's underneath.
+ */
+pub(crate) struct AlbedoTable {
+    values: Vec<Scalar>,
+    /// Cosine-weighted averages over the hemisphere, per roughness
+    averages: Vec<Scalar>,
+}
+impl AlbedoTable {
+    /// Fills the table from a function of roughness and cosine
+    pub fn compute<F: Fn(Scalar, Scalar) -> Scalar>(albedo: F) -> Self {
+        let n = TABLE_SIZE;
+        let mut values = vec![0.0; n * n];
+        let mut averages = vec![0.0; n];
+        for i in 0..n {
+            let row = &mut values[i * n..(i + 1) * n];
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = albedo(Self::roughness(i), Self::cosine(j));
+            }
+            // 2 * integral of E(mu) mu over [0, 1], by the trapezoid rule
+            averages[i] = (1..n).map(|j| {
+                let (m0, m1) = (Self::cosine(j - 1), Self::cosine(j));
+                (row[j - 1] * m0 + row[j] * m1) * (m1 - m0)
+            }).sum::<Scalar>();
+        }
+        AlbedoTable {
+            values,
+            averages,
+        }
+    }
+
+    fn roughness(i: usize) -> Scalar {
+        i as Scalar / (TABLE_SIZE - 1) as Scalar
+    }
+    fn cosine(j: usize) -> Scalar {
+        (j as Scalar / (TABLE_SIZE - 1) as Scalar).max(1.0e-3)
+    }
+
+    /// Position of x in [0, 1] between table entries: the lower index and the fraction of the way to the next
+    fn locate(x: Scalar) -> (usize, Scalar) {
+        let x = x.clamp(0.0, 1.0) * (TABLE_SIZE - 1) as Scalar;
+        let i = (x as usize).min(TABLE_SIZE - 2);
+        (i, x - i as Scalar)
+    }
+
+    pub fn lookup(&self, roughness: Scalar, cos_theta: Scalar) -> Scalar {
+        let (i, fi) = Self::locate(roughness);
+        let (j, fj) = Self::locate(cos_theta);
+        let at = |i: usize, j: usize| self.values[i * TABLE_SIZE + j];
+        let lower = at(i, j) * (1.0 - fj) + at(i, j + 1) * fj;
+        let upper = at(i + 1, j) * (1.0 - fj) + at(i + 1, j + 1) * fj;
+        lower * (1.0 - fi) + upper * fi
+    }
+
+    pub fn average(&self, roughness: Scalar) -> Scalar {
+        let (i, fi) = Self::locate(roughness);
+        self.averages[i] * (1.0 - fi) + self.averages[i + 1] * fi
+    }
+}
@@ -0,0 +1,120 @@
+use crate::math::*;
+use crate::Color3;
+use super::{Bsdf, BsdfSample};
+
+/**
+ * The sum of several lobes, such as a diffuse base under a specular coat
+ *
+ * Each lobe's own weighting is part of its `eval`, optionally times a scale given to `push_scaled`.
+ * The sampling weight given with it only sets how often `sample` picks that lobe, so it should roughly
+ * follow how much light the lobe reflects. Samples from perfectly specular lobes are returned as they are,
+ * since no other lobe could have chosen the same direction.
+ */
+#[derive(Default)]
+pub struct Composite {
+    lobes: Vec<(Scalar, f32, Box<dyn Bsdf>)>,
+}
+impl Composite {
+    pub fn new() -> Self {
+        Composite::default()
+    }
+
+    pub fn push<B: Bsdf + 'static>(&mut self, sampling_weight: f32, lobe: B) {
+        self.push_scaled(1.0, sampling_weight, lobe);
+    }
+
+    /// Adds a lobe whose contribution, and sampling weight, are multiplied by `scale`, for blending between whole lobes
+    pub fn push_scaled<B: Bsdf + 'static>(&mut self, scale: f32, sampling_weight: f32, lobe: B) {
+        if scale > 0.0 {
+            self.lobes.push(((sampling_weight * scale).max(0.0) as Scalar, scale, Box::new(lobe)));
+        }
+    }
+
+    /// How often `sample` picks a lobe for `wo`: never, for a one-sided lobe seen from below
+    fn weight(wo: &Vec3, w: Scalar, lobe: &dyn Bsdf) -> Scalar {
+        if wo.z < 0.0 && !lobe.is_two_sided() {
+            0.0
+        } else {
+            w
+        }
+    }
+
+    fn total_weight(&self, wo: &Vec3) -> Scalar {
+        self.lobes.iter().map(|(w, _, lobe)| Self::weight(wo, *w, lobe.as_ref())).sum()
+    }
+}
+impl Bsdf for Composite {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color3 {
+        self.lobes.iter().fold(Color3::gray(0.0), |sum, (_, scale, lobe)| sum + lobe.eval(wo, wi) * *scale)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        let total = self.total_weight(wo);
+        if total <= 0.0 {
+            return 0.0;
+        }
+        self.lobes.iter().map(|(w, _, lobe)| Self::weight(wo, *w, lobe.as_ref()) * lobe.pdf(wo, wi)).sum::<Scalar>() / total
+    }
+
+    fn sample(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Option<BsdfSample> {
+        let total = self.total_weight(wo);
+        if total <= 0.0 {
+            return None;
+        }
+        // Pick a lobe with the first coordinate, then stretch what's left of it back over [0, 1)
+        let mut x = u.0 * total;
+        let mut chosen = None;
+        for (w, scale, lobe) in self.lobes.iter() {
+            let w = Self::weight(wo, *w, lobe.as_ref());
+            if w > 0.0 {
+                chosen = Some((w, scale, lobe));
+                if x < w {
+                    x /= w;
+                    break;
+                }
+                x -= w;
+            }
+        }
+        let (w, scale, lobe) = chosen?;
+        let s = lobe.sample(wo, (x.min(1.0 - consts::EPSILON), u.1))?;
+        if s.delta {
+            // The other lobes have no say in a direction only this one can pick
+            return Some(BsdfSample {
+                value: s.value * *scale,
+                pdf: s.pdf * w / total,
+                ..s
+            });
+        }
+        let pdf = self.pdf(wo, &s.wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi: s.wi,
+            value: self.eval(wo, &s.wi),
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn is_two_sided(&self) -> bool {
+        self.lobes.iter().any(|(_, _, lobe)| lobe.is_two_sided())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf::{Lambert, Ggx};
+    use crate::bsdf::tests::check_bsdf;
+    use nalgebra_glm as glm;
+
+    #[test]
+    fn mixture_pdf_is_consistent() {
+        let mut bsdf = Composite::new();
+        bsdf.push(0.7, Lambert::new(Color3::gray(0.5)));
+        bsdf.push(0.3, Ggx::new(0.2, Color3::gray(0.04)));
+        let integral = check_bsdf(&bsdf, &glm::normalize(&Vec3::new(0.2, 0.0, 1.0)));
+        assert!((integral - 1.0).abs() <= 2.0e-2, "{}", integral);
+    }
+}
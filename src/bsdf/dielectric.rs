@@ -0,0 +1,236 @@
+use crate::math::*;
+use crate::Color3;
+use super::{Bsdf, BsdfSample, Ggx, fresnel_dielectric, reflect, refract};
+use nalgebra_glm as glm;
+
+/// Below this roughness, glass is treated as perfectly smooth
+const SMOOTH_ALPHA: Scalar = 1.0e-3;
+
+/**
+ * Reflection and refraction at the boundary of a transparent material such as glass or water
+ *
+ * Smooth boundaries reflect and refract in exactly one direction each, split by the exact Fresnel equations.
+ * Rough boundaries use GGX microfacets for both, as in Walter et al., "Microfacet Models for Refraction through
+ * Rough Surfaces". The normal points out of the material, so `wo.z < 0` when looking from the inside.
+ * Refracted radiance is scaled by the squared ratio of IORs, as light is compressed into a smaller solid angle.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct Dielectric {
+    /// IOR of the material relative to the outside
+    pub eta: Scalar,
+    /// Width of the GGX distribution of microfacets: the square of perceptual roughness
+    pub alpha: Scalar,
+}
+impl Dielectric {
+    pub fn new(eta: Scalar, alpha: Scalar) -> Self {
+        Dielectric {
+            eta,
+            alpha,
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    fn distribution(&self) -> Ggx {
+        Ggx::new(self.alpha, Color3::gray(1.0))
+    }
+
+    /// How often `sample` reflects rather than refracts. Rough surfaces go by the macroscopic normal, and always keep
+    /// some of each, since microfacets may reflect or refract either way.
+    fn reflect_probability(&self, wo: &Vec3) -> Scalar {
+        let f = fresnel_dielectric(wo.z, self.eta);
+        if self.is_smooth() {
+            f
+        } else {
+            f.clamp(0.1, 0.9)
+        }
+    }
+
+    /// The ratio of IORs across the boundary from `wo` to `wi`, and the microfacet normal that connects them, facing +z
+    fn half_vector(&self, wo: &Vec3, wi: &Vec3) -> Option<(Scalar, Vec3)> {
+        if wo.z == 0.0 || wi.z == 0.0 {
+            return None;
+        }
+        let etap = if wo.z * wi.z > 0.0 {
+            1.0
+        } else if wo.z > 0.0 {
+            self.eta
+        } else {
+            1.0 / self.eta
+        };
+        let wm = wi * etap + wo;
+        if glm::length2(&wm) == 0.0 {
+            return None;
+        }
+        let wm = glm::normalize(&wm);
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+        // Microfacets seen from behind don't contribute
+        if glm::dot(&wm, wi) * wi.z < 0.0 || glm::dot(&wm, wo) * wo.z < 0.0 {
+            return None;
+        }
+        Some((etap, wm))
+    }
+
+    /// Density of visible microfacet normals from `wo`, which may be below the surface
+    fn visible_normal_pdf(ggx: &Ggx, wo: &Vec3, wm: &Vec3) -> Scalar {
+        ggx.g1(wo) / wo.z.abs() * ggx.d(wm) * glm::dot(wo, wm).abs()
+    }
+
+    fn sample_smooth(&self, wo: &Vec3, u: Scalar) -> Option<BsdfSample> {
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        let r = fresnel_dielectric(wo.z, self.eta);
+        if u < r {
+            let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+            return Some(BsdfSample {
+                wi,
+                value: Color3::gray((r / wi.z.abs()) as f32),
+                pdf: r,
+                delta: true,
+            });
+        }
+        let wi = refract(wo, &n, self.eta)?;
+        let etap = if wo.z > 0.0 { self.eta } else { 1.0 / self.eta };
+        let t = (1.0 - r) / (etap * etap);
+        Some(BsdfSample {
+            wi,
+            value: Color3::gray((t / wi.z.abs()) as f32),
+            pdf: 1.0 - r,
+            delta: true,
+        })
+    }
+}
+impl Bsdf for Dielectric {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color3 {
+        if self.is_smooth() {
+            return Color3::gray(0.0);
+        }
+        let (etap, wm) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return Color3::gray(0.0),
+        };
+        let ggx = self.distribution();
+        let f = fresnel_dielectric(glm::dot(wo, &wm), self.eta);
+        let value = if etap == 1.0 {
+            ggx.d(&wm) * ggx.g2(wo, wi) * f / (4.0 * wo.z * wi.z).abs()
+        } else {
+            let denom = glm::dot(wi, &wm) + glm::dot(wo, &wm) / etap;
+            let denom = denom * denom * wi.z * wo.z;
+            ggx.d(&wm) * (1.0 - f) * ggx.g2(wo, wi) * (glm::dot(wi, &wm) * glm::dot(wo, &wm) / denom).abs() / (etap * etap)
+        };
+        Color3::gray(value as f32)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        if self.is_smooth() {
+            return 0.0;
+        }
+        let (etap, wm) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return 0.0,
+        };
+        let ggx = self.distribution();
+        let pr = self.reflect_probability(wo);
+        let visible = Self::visible_normal_pdf(&ggx, wo, &wm);
+        if etap == 1.0 {
+            // Microfacets that can't refract reflect every sample
+            let chosen = if refract(wo, &wm, self.eta).is_none() { 1.0 } else { pr };
+            visible / (4.0 * glm::dot(wo, &wm).abs()) * chosen
+        } else {
+            let denom = glm::dot(wi, &wm) + glm::dot(wo, &wm) / etap;
+            visible * glm::dot(wi, &wm).abs() / (denom * denom) * (1.0 - pr)
+        }
+    }
+
+    fn sample(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        if self.is_smooth() {
+            return self.sample_smooth(wo, u.0);
+        }
+        // Split the first coordinate between choosing reflection and choosing the microfacet
+        let pr = self.reflect_probability(wo);
+        let (reflecting, u0) = if u.0 < pr {
+            (true, u.0 / pr)
+        } else {
+            (false, (u.0 - pr) / (1.0 - pr))
+        };
+        let ggx = self.distribution();
+        let upper = if wo.z > 0.0 { *wo } else { -wo };
+        let wm = ggx.sample_visible_normal(&upper, (u0.min(1.0 - consts::EPSILON), u.1));
+        let wi = match refract(wo, &wm, self.eta).filter(|_| !reflecting) {
+            Some(wi) => {
+                if wi.z * wo.z >= 0.0 {
+                    return None;
+                }
+                wi
+            }
+            None => {
+                let wi = reflect(wo, &wm);
+                if wi.z * wo.z <= 0.0 {
+                    return None;
+                }
+                wi
+            }
+        };
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            value: self.eval(wo, &wi),
+            pdf,
+            delta: false,
+        })
+    }
+
+    fn is_two_sided(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf::tests::check_bsdf;
+    use crate::sampler::{Sampler, Sobol};
+
+    /// Fraction of the light arriving towards `wo` that the boundary passes on, weighting refraction by eta^2
+    /// to undo the change in solid angle
+    fn albedo(bsdf: &Dielectric, wo: &Vec3) -> Scalar {
+        let sampler = Sobol::new(9);
+        let n = 1 << 14;
+        (0..n).filter_map(|i| bsdf.sample(wo, sampler.sample_2d((0, 0), i, 0))).map(|s| {
+            let etap: Scalar = if s.wi.z * wo.z > 0.0 { 1.0 } else if wo.z > 0.0 { bsdf.eta } else { 1.0 / bsdf.eta };
+            s.value.g as Scalar * s.wi.z.abs() / s.pdf * etap * etap
+        }).sum::<Scalar>() / n as Scalar
+    }
+
+    #[test]
+    fn smooth_glass_conserves_energy() {
+        let glass = Dielectric::new(1.5, 0.0);
+        for &wo in [Vec3::new(0.0, 0.0, 1.0), glm::normalize(&Vec3::new(1.0, 0.0, 0.3)), glm::normalize(&Vec3::new(0.3, 0.0, -1.0))].iter() {
+            assert!((albedo(&glass, &wo) - 1.0).abs() <= 1.0e-3);
+        }
+        // Beyond the critical angle, everything is reflected
+        let s = glass.sample(&glm::normalize(&Vec3::new(1.0, 0.0, -0.2)), (0.99, 0.5)).unwrap();
+        assert!(s.delta && s.wi.z < 0.0);
+    }
+
+    #[test]
+    fn rough_glass_sampling_matches_pdf() {
+        for &alpha in [0.1, 0.5].iter() {
+            let glass = Dielectric::new(1.5, alpha);
+            for &wo in [glm::normalize(&Vec3::new(0.3, 0.0, 1.0)), glm::normalize(&Vec3::new(0.2, 0.1, -1.0))].iter() {
+                // Steep microfacets send some samples out the wrong side, mostly by total internal reflection
+                let integral = check_bsdf(&glass, &wo);
+                assert!((0.7..=1.02).contains(&integral), "alpha {}: {}", alpha, integral);
+                let e = albedo(&glass, &wo);
+                assert!((0.7..=1.01).contains(&e), "alpha {}: {}", alpha, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::math::*;
+use crate::Color3;
+use super::{Bsdf, BsdfSample, Fresnel, schlick_weight, reflect};
+use super::albedo::AlbedoTable;
+use crate::sampler::{Sampler, Sobol};
+use nalgebra_glm as glm;
+use lazy_static::lazy_static;
+
+/// Smallest roughness used, so that perfectly smooth surfaces don't need a separate delta lobe
+const MIN_ALPHA: Scalar = 1.0e-3;
+
+/**
+ * Cook-Torrance microfacet reflection with the GGX (Trowbridge-Reitz) normal distribution
+ *
+ * Uses the height-correlated Smith masking-shadowing term. Light that would bounce between microfacets
+ * more than once is lost, so rough surfaces come out a little darker than they should.
+ * Directions are sampled from the distribution of normals visible from `wo` (Heitz, "Sampling the GGX
+ * Distribution of Visible Normals"), so fewer samples are wasted on facets facing away from the viewer.
+ * The distribution may be wider along the tangent (x) than the bitangent (y), as for brushed metal.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct Ggx {
+    /// Width of the distribution along the tangent: the square of perceptual roughness
+    pub alpha_x: Scalar,
+    /// Width of the distribution along the bitangent
+    pub alpha_y: Scalar,
+    pub fresnel: Fresnel,
+}
+impl Ggx {
+    /// A dielectric lobe with Schlick's Fresnel, given the reflectance at normal incidence
+    pub fn new(alpha: Scalar, f0: Color3) -> Self {
+        Ggx::with_fresnel(alpha, Fresnel::Schlick(f0))
+    }
+
+    pub fn with_fresnel(alpha: Scalar, fresnel: Fresnel) -> Self {
+        Ggx::anisotropic(alpha, alpha, fresnel)
+    }
+
+    pub fn anisotropic(alpha_x: Scalar, alpha_y: Scalar, fresnel: Fresnel) -> Self {
+        Ggx {
+            alpha_x: alpha_x.max(MIN_ALPHA),
+            alpha_y: alpha_y.max(MIN_ALPHA),
+            fresnel,
+        }
+    }
+
+    /// Roughnesses along the tangent and bitangent for a given `anisotropy` in [-1, 1], as in glTF's
+    /// KHR_materials_anisotropy: positive values stretch the highlight along the tangent, negative along the bitangent
+    pub fn anisotropic_alphas(alpha: Scalar, anisotropy: Scalar) -> (Scalar, Scalar) {
+        let a = anisotropy.clamp(-1.0, 1.0);
+        let stretched = alpha + (1.0 - alpha) * a * a;
+        if a >= 0.0 {
+            (stretched, alpha)
+        } else {
+            (alpha, stretched)
+        }
+    }
+
+    /// Density of microfacet normals
+    pub fn d(&self, h: &Vec3) -> Scalar {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = (h.x / self.alpha_x, h.y / self.alpha_y);
+        let t = x * x + y * y + h.z * h.z;
+        1.0 / (consts::PI * self.alpha_x * self.alpha_y * t * t)
+    }
+
+    /// Smith's auxiliary function: the ratio of hidden to visible microfacet area in direction w
+    fn lambda(&self, w: &Vec3) -> Scalar {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return Scalar::INFINITY;
+        }
+        let (x, y) = (self.alpha_x * w.x, self.alpha_y * w.y);
+        ((1.0 + (x * x + y * y) / cos2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of microfacets visible from w
+    pub fn g1(&self, w: &Vec3) -> Scalar {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both directions
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// A microfacet normal drawn from those visible from `wo`
+    pub fn sample_visible_normal(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Vec3 {
+        // Stretch the view direction so the distribution becomes a hemisphere
+        let vh = glm::normalize(&Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z));
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / len2.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2: Vec3 = vh.cross(&t1);
+
+        // A point on the projected disk, squashed towards the part of the hemisphere visible from vh
+        let r = u.0.sqrt();
+        let phi = 2.0 * consts::PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // Unstretch
+        glm::normalize(&Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(0.0)))
+    }
+}
+impl Bsdf for Ggx {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color3::gray(0.0);
+        }
+        let h = glm::normalize(&(wo + wi));
+        let f = self.fresnel.eval(glm::dot(wi, &h));
+        f * (self.d(&h) * self.g2(wo, wi) / (4.0 * wo.z * wi.z)) as f32
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = glm::normalize(&(wo + wi));
+        // Visible normal density D_wo(h) = G1(wo) D(h) (wo.h) / wo.z, times the Jacobian of reflection 1 / (4 wo.h)
+        self.g1(wo) * self.d(&h) / (4.0 * wo.z)
+    }
+
+    fn sample(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let h = self.sample_visible_normal(wo, u);
+        let wi = reflect(wo, &h);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            value: self.eval(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            delta: false,
+        })
+    }
+}
+
+lazy_static! {
+    /// Directional albedo of GGX reflection with Schlick's Fresnel. Schlick's approximation is linear in f0,
+    /// so the albedo is `f0 * scale + bias` (the "split sum" of Karis).
+    static ref ALBEDO_SCALE: AlbedoTable = AlbedoTable::compute(|alpha, mu| split_albedo(alpha, mu).0);
+    static ref ALBEDO_BIAS: AlbedoTable = AlbedoTable::compute(|alpha, mu| split_albedo(alpha, mu).1);
+}
+
+/// Estimates the scale and bias terms of the directional albedo for one roughness and view angle
+fn split_albedo(alpha: Scalar, mu: Scalar) -> (Scalar, Scalar) {
+    const SAMPLES: usize = 512;
+    let sampler = Sobol::new(0);
+    let ggx = Ggx::new(alpha, Color3::gray(1.0));
+    let wo = Vec3::new((1.0 - mu * mu).sqrt(), 0.0, mu);
+    let (mut scale, mut bias) = (0.0, 0.0);
+    for s in 0..SAMPLES {
+        let h = ggx.sample_visible_normal(&wo, sampler.sample_2d((0, 0), s, 0));
+        let wi = reflect(&wo, &h);
+        if wi.z <= 0.0 {
+            continue;
+        }
+        // f cos / pdf reduces to G2 / G1 with visible normal sampling
+        let weight = ggx.g2(&wo, &wi) / ggx.g1(&wo);
+        let m5 = schlick_weight(glm::dot(&wo, &h));
+        scale += weight * (1.0 - m5);
+        bias += weight * m5;
+    }
+    (scale / SAMPLES as Scalar, bias / SAMPLES as Scalar)
+}
+
+/// Fraction of the light arriving from a direction with the given cosine that a GGX lobe with Schlick's Fresnel reflects
+pub fn directional_albedo(alpha: Scalar, f0: Color3, cos_theta: Scalar) -> Color3 {
+    let scale = ALBEDO_SCALE.lookup(alpha, cos_theta) as f32;
+    let bias = ALBEDO_BIAS.lookup(alpha, cos_theta) as f32;
+    f0 * scale + Color3::gray(bias)
+}
+
+/// Cosine-weighted average of `directional_albedo` over the hemisphere
+pub fn average_albedo(alpha: Scalar, f0: Color3) -> Color3 {
+    let scale = ALBEDO_SCALE.average(alpha) as f32;
+    let bias = ALBEDO_BIAS.average(alpha) as f32;
+    f0 * scale + Color3::gray(bias)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf::tests::check_bsdf;
+
+    #[test]
+    fn smooth_mirror_reflects_everything() {
+        let e = directional_albedo(0.0, Color3::gray(1.0), 0.7);
+        assert!((e.r - 1.0).abs() <= 1.0e-2, "{:?}", e);
+        // Rough surfaces lose much of the light that would scatter between microfacets more than once
+        let rough = directional_albedo(1.0, Color3::gray(1.0), 0.7);
+        assert!(rough.r < 0.5 && rough.r > 0.3, "{:?}", rough);
+    }
+
+    #[test]
+    fn visible_normal_sampling_matches_pdf() {
+        for &alpha in [0.05, 0.3, 0.8].iter() {
+            let bsdf = Ggx::new(alpha, Color3::gray(0.9));
+            for &wo in [Vec3::new(0.0, 0.0, 1.0), glm::normalize(&Vec3::new(1.0, 0.5, 0.4))].iter() {
+                let integral = check_bsdf(&bsdf, &wo);
+                // Rough surfaces reflect some of their visible normals below the horizon; those samples are discarded
+                assert!((0.5..=1.02).contains(&integral), "alpha {}: {}", alpha, integral);
+            }
+        }
+    }
+
+    #[test]
+    fn anisotropic_sampling_matches_pdf() {
+        let (ax, ay) = Ggx::anisotropic_alphas(0.1, 0.8);
+        assert!(ax > 0.5 && ay == 0.1);
+        let bsdf = Ggx::anisotropic(ax, ay, Fresnel::Schlick(Color3::gray(0.9)));
+        for &wo in [Vec3::new(0.0, 0.0, 1.0), glm::normalize(&Vec3::new(0.3, 0.5, 0.6))].iter() {
+            let integral = check_bsdf(&bsdf, &wo);
+            assert!((0.7..=1.02).contains(&integral), "{}", integral);
+        }
+        // Rougher along the tangent spreads the highlight that way
+        let wo = Vec3::new(0.0, 0.0, 1.0);
+        let along = bsdf.eval(&wo, &glm::normalize(&Vec3::new(0.4, 0.0, 1.0)));
+        let across = bsdf.eval(&wo, &glm::normalize(&Vec3::new(0.0, 0.4, 1.0)));
+        assert!(along.r > across.r);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::math::*;
+use crate::Color3;
+use super::{Bsdf, BsdfSample, same_hemisphere};
+
+/// Ideal diffuse reflection, sampled with a cosine-weighted hemisphere
+#[derive(Debug, Copy, Clone)]
+pub struct Lambert {
+    pub albedo: Color3,
+}
+impl Lambert {
+    pub fn new(albedo: Color3) -> Self {
+        Lambert {
+            albedo,
+        }
+    }
+}
+impl Bsdf for Lambert {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color3 {
+        if wo.z <= 0.0 || !same_hemisphere(wo, wi) {
+            return Color3::gray(0.0);
+        }
+        self.albedo / consts::PI as f32
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        if wo.z <= 0.0 || !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z / consts::PI
+    }
+
+    fn sample(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let (x, y) = concentric_disk(u);
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+        if z <= 0.0 {
+            return None;
+        }
+        let wi = Vec3::new(x, y, z);
+        Some(BsdfSample {
+            wi,
+            value: self.eval(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            delta: false,
+        })
+    }
+}
+
+/// Ideal diffuse transmission through a thin or scattering surface, from either side to the other
+#[derive(Debug, Copy, Clone)]
+pub struct LambertTransmission {
+    pub color: Color3,
+}
+impl LambertTransmission {
+    pub fn new(color: Color3) -> Self {
+        LambertTransmission {
+            color,
+        }
+    }
+}
+impl Bsdf for LambertTransmission {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color3 {
+        if same_hemisphere(wo, wi) {
+            return Color3::gray(0.0);
+        }
+        self.color / consts::PI as f32
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        if same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi.z.abs() / consts::PI
+    }
+
+    fn sample(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Option<BsdfSample> {
+        let (x, y) = concentric_disk(u);
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+        if z <= 0.0 || wo.z == 0.0 {
+            return None;
+        }
+        // On the other side from wo
+        let wi = Vec3::new(x, y, -z * wo.z.signum());
+        Some(BsdfSample {
+            wi,
+            value: self.eval(wo, &wi),
+            pdf: self.pdf(wo, &wi),
+            delta: false,
+        })
+    }
+
+    fn is_two_sided(&self) -> bool {
+        true
+    }
+}
+
+/// Maps the unit square onto the unit disk, keeping areas and most of the square's stratification (Shirley and Chiu)
+pub(crate) fn concentric_disk(u: (Scalar, Scalar)) -> (Scalar, Scalar) {
+    let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, consts::FRAC_PI_2 - consts::FRAC_PI_4 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf::tests::check_bsdf;
+    use nalgebra_glm as glm;
+
+    #[test]
+    fn cosine_sampling_is_normalized() {
+        let bsdf = Lambert::new(Color3::new(0.8, 0.5, 0.2));
+        let integral = check_bsdf(&bsdf, &glm::normalize(&Vec3::new(0.3, 0.1, 1.0)));
+        assert!((integral - 1.0).abs() <= 1.0e-2, "{}", integral);
+    }
+
+    #[test]
+    fn transmission_crosses_to_the_other_side() {
+        let bsdf = LambertTransmission::new(Color3::gray(1.0));
+        let wo = glm::normalize(&Vec3::new(0.3, 0.1, -1.0));
+        let s = bsdf.sample(&wo, (0.3, 0.7)).unwrap();
+        assert!(s.wi.z > 0.0 && (s.pdf - bsdf.pdf(&wo, &s.wi)).abs() <= 1.0e-9);
+        let integral = check_bsdf(&bsdf, &glm::normalize(&Vec3::new(0.3, 0.1, 1.0)));
+        assert!((integral - 1.0).abs() <= 1.0e-2, "{}", integral);
+    }
+}
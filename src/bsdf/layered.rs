@@ -0,0 +1,95 @@
+use crate::math::*;
+use crate::Color3;
+use super::{Bsdf, BsdfSample};
+use super::ggx::{directional_albedo, average_albedo};
+use super::sheen::{sheen_albedo, average_sheen_albedo};
+
+/// A layer that reflects some of the light before it reaches whatever lies underneath
+#[derive(Debug, Copy, Clone)]
+pub enum Coat {
+    /// GGX reflection with Schlick's Fresnel, covering `weight` of the surface
+    Specular { alpha: Scalar, f0: Color3, weight: f32 },
+    /// Fabric fibers, as in `Sheen`
+    Sheen { alpha: Scalar, color: Color3 },
+}
+impl Coat {
+    /// Fraction of the light arriving from a direction with the given cosine that the coat reflects
+    pub fn albedo(&self, cos_theta: Scalar) -> Color3 {
+        match *self {
+            Coat::Specular { alpha, f0, weight } => directional_albedo(alpha, f0, cos_theta) * weight,
+            Coat::Sheen { alpha, color } => color * sheen_albedo(alpha, cos_theta) as f32,
+        }
+    }
+
+    /// Cosine-weighted average of `albedo` over the hemisphere
+    pub fn average_albedo(&self) -> Color3 {
+        match *self {
+            Coat::Specular { alpha, f0, weight } => average_albedo(alpha, f0) * weight,
+            Coat::Sheen { alpha, color } => color * average_sheen_albedo(alpha) as f32,
+        }
+    }
+
+    /// Fraction of the light the coat lets through on one side of the surface. Light on the other side is left alone.
+    fn transmitted(&self, w: &Vec3) -> Color3 {
+        if w.z <= 0.0 {
+            return Color3::gray(1.0);
+        }
+        let t = Color3::gray(1.0) - self.albedo(w.z);
+        Color3::new(t.r.max(0.0), t.g.max(0.0), t.b.max(0.0))
+    }
+}
+
+/**
+ * A lobe under a coat, receiving only the light the coat lets through
+ *
+ * Light that the coat reflects in either direction never reaches the base, so the base is scaled by
+ * (1 - E(wo)) (1 - E(wi)), where E is the coat's directional albedo (Kelemen and Szirmay-Kalos). The coat's own
+ * reflection is a separate lobe. With `compensate`, the base is also divided by 1 - E_avg, which keeps a white
+ * Lambertian base under a colorless coat reflecting all of the light; it is only meant for diffuse bases.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct Coated<B> {
+    pub base: B,
+    pub coat: Coat,
+    pub compensate: bool,
+}
+impl<B: Bsdf> Coated<B> {
+    pub fn new(base: B, coat: Coat, compensate: bool) -> Self {
+        Coated {
+            base,
+            coat,
+            compensate,
+        }
+    }
+
+    fn scale(&self, wo: &Vec3, wi: &Vec3) -> Color3 {
+        let scale = self.coat.transmitted(wo) * self.coat.transmitted(wi);
+        if !self.compensate {
+            return scale;
+        }
+        let average = Color3::gray(1.0) - self.coat.average_albedo();
+        let channel = |scale: f32, average: f32| if average <= 0.0 { 0.0 } else { scale / average };
+        Color3::new(channel(scale.r, average.r), channel(scale.g, average.g), channel(scale.b, average.b))
+    }
+}
+impl<B: Bsdf> Bsdf for Coated<B> {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color3 {
+        self.base.eval(wo, wi) * self.scale(wo, wi)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        self.base.pdf(wo, wi)
+    }
+
+    fn sample(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Option<BsdfSample> {
+        let s = self.base.sample(wo, u)?;
+        Some(BsdfSample {
+            value: s.value * self.scale(wo, &s.wi),
+            ..s
+        })
+    }
+
+    fn is_two_sided(&self) -> bool {
+        self.base.is_two_sided()
+    }
+}
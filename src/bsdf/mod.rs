@@ -0,0 +1,330 @@
+mod albedo;
+mod lambert;
+mod oren_nayar;
+mod ggx;
+mod sheen;
+mod composite;
+mod layered;
+mod dielectric;
+pub use lambert::{Lambert, LambertTransmission};
+pub use oren_nayar::OrenNayar;
+pub use ggx::Ggx;
+pub use sheen::Sheen;
+pub use composite::Composite;
+pub use layered::{Coat, Coated};
+pub use dielectric::Dielectric;
+
+use crate::math::*;
+use crate::Color3;
+use nalgebra_glm as glm;
+
+/// A direction chosen by `Bsdf::sample`
+#[derive(Debug, Copy, Clone)]
+pub struct BsdfSample {
+    /// Direction towards the incoming light, in the shading frame
+    pub wi: Vec3,
+    /// The BSDF for the sampled pair of directions, without the cosine term
+    pub value: Color3,
+    /// Probability density of having chosen `wi`, per unit solid angle
+    pub pdf: Scalar,
+    /// Whether `wi` came from a perfectly specular lobe, which `eval` and `pdf` never see.
+    /// `value` and `pdf` are then relative to a Dirac delta, and only their ratio is meaningful.
+    pub delta: bool,
+}
+
+/**
+ * A bidirectional scattering distribution function
+ *
+ * Directions are unit vectors in the shading frame, where the surface normal is +z.
+ * `wo` points towards the viewer and `wi` towards the light, both away from the surface.
+ */
+pub trait Bsdf {
+    /// Fraction of the light arriving from `wi` that leaves towards `wo`, per unit solid angle
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color3;
+
+    /// Density with which `sample` would choose `wi`
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar;
+
+    /// Chooses an incoming direction for `wo`, roughly in proportion to the BSDF.
+    /// `u` is a uniformly distributed point in the unit square.
+    fn sample(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Option<BsdfSample>;
+
+    /// Whether the lobe also scatters light for a `wo` below the surface, as transmissive lobes do. One-sided lobes
+    /// are left out when `Composite` picks a lobe from below.
+    fn is_two_sided(&self) -> bool {
+        false
+    }
+}
+
+/// An orthonormal basis around a surface normal, for moving directions in and out of the shading frame
+#[derive(Debug, Copy, Clone)]
+pub struct Frame {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+impl Frame {
+    /// Builds a basis with no branches on the normal's direction, from Duff et al., "Building an Orthonormal Basis, Revisited"
+    pub fn from_normal(n: &Vec3) -> Self {
+        let sign = if n.z >= 0.0 { 1.0 } else { -1.0 };
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+        Frame {
+            tangent: Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+            bitangent: Vec3::new(b, sign + n.y * n.y * a, -n.y),
+            normal: *n,
+        }
+    }
+
+    /// A basis whose tangent follows `tangent` as closely as possible, for anisotropic lobes.
+    /// Falls back to `from_normal` when the tangent is missing or parallel to the normal.
+    pub fn from_normal_tangent(n: &Vec3, tangent: Option<&Vec3>) -> Self {
+        let t = match tangent {
+            Some(t) => t - n * glm::dot(t, n),
+            None => return Frame::from_normal(n),
+        };
+        let len = glm::length(&t);
+        if len <= 1.0e-6 {
+            return Frame::from_normal(n);
+        }
+        let tangent = t / len;
+        Frame {
+            tangent,
+            bitangent: n.cross(&tangent),
+            normal: *n,
+        }
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(glm::dot(v, &self.tangent), glm::dot(v, &self.bitangent), glm::dot(v, &self.normal))
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+/// Schlick's approximation of Fresnel reflectance, given the reflectance at normal incidence
+pub fn schlick(f0: Color3, cos_theta: Scalar) -> Color3 {
+    let m5 = schlick_weight(cos_theta) as f32;
+    f0 + (Color3::gray(1.0) - f0) * m5
+}
+
+/// The (1 - cos)^5 term of Schlick's approximation
+#[inline]
+pub(crate) fn schlick_weight(cos_theta: Scalar) -> Scalar {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0);
+    m * m * m * m * m
+}
+
+/**
+ * The complex index of refraction of a conductor, per color channel, relative to the outside medium
+ *
+ * Metals absorb light that enters them within a tiny distance, which `k` describes.
+ * The presets are for the middle of each channel's wavelength range.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct ComplexIor {
+    pub eta: Color3,
+    pub k: Color3,
+}
+impl ComplexIor {
+    pub fn new(eta: Color3, k: Color3) -> Self {
+        ComplexIor {
+            eta,
+            k,
+        }
+    }
+
+    pub fn gold() -> Self {
+        ComplexIor::new(Color3::new(0.143, 0.374, 1.442), Color3::new(3.983, 2.385, 1.603))
+    }
+    pub fn silver() -> Self {
+        ComplexIor::new(Color3::new(0.155, 0.117, 0.138), Color3::new(4.827, 3.122, 2.147))
+    }
+    pub fn copper() -> Self {
+        ComplexIor::new(Color3::new(0.200, 0.924, 1.102), Color3::new(3.912, 2.452, 2.142))
+    }
+    pub fn aluminium() -> Self {
+        ComplexIor::new(Color3::new(1.657, 0.880, 0.521), Color3::new(9.224, 6.270, 4.837))
+    }
+
+    /**
+     * Fits a complex IOR to artist-friendly colors, from Gulbrandsen's "Artist Friendly Metallic Fresnel"
+     *
+     * `reflectivity` is the color at normal incidence, and `edge_tint` the color towards grazing angles.
+     */
+    pub fn from_reflectance(reflectivity: Color3, edge_tint: Color3) -> Self {
+        let fit = |r: f32, g: f32| {
+            let r = r.clamp(0.0, 0.99);
+            let g = g.clamp(0.0, 1.0);
+            let n_min = (1.0 - r) / (1.0 + r);
+            let n_max = (1.0 + r.sqrt()) / (1.0 - r.sqrt());
+            let n = g * n_min + (1.0 - g) * n_max;
+            let k2 = ((n + 1.0) * (n + 1.0) * r - (n - 1.0) * (n - 1.0)) / (1.0 - r);
+            (n, k2.max(0.0).sqrt())
+        };
+        let (r, g, b) = (fit(reflectivity.r, edge_tint.r), fit(reflectivity.g, edge_tint.g), fit(reflectivity.b, edge_tint.b));
+        ComplexIor::new(Color3::new(r.0, g.0, b.0), Color3::new(r.1, g.1, b.1))
+    }
+}
+
+/// How much light a smooth interface reflects, depending on the angle to its normal
+#[derive(Debug, Copy, Clone)]
+pub enum Fresnel {
+    /// Schlick's approximation from the reflectance at normal incidence, for dielectrics
+    Schlick(Color3),
+    /// Exact reflectance of a conductor
+    Conductor(ComplexIor),
+}
+impl Fresnel {
+    pub fn eval(&self, cos_theta: Scalar) -> Color3 {
+        match self {
+            Fresnel::Schlick(f0) => schlick(*f0, cos_theta),
+            Fresnel::Conductor(ior) => {
+                let c = cos_theta.clamp(0.0, 1.0) as f32;
+                Color3::new(
+                    fresnel_conductor(c, ior.eta.r, ior.k.r),
+                    fresnel_conductor(c, ior.eta.g, ior.k.g),
+                    fresnel_conductor(c, ior.eta.b, ior.k.b),
+                )
+            }
+        }
+    }
+}
+
+/// Unpolarized reflectance of a conductor with complex IOR eta + ik, as in Pharr et al., "Physically Based Rendering"
+pub fn fresnel_conductor(cos_theta: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+}
+
+/// Fraction of unpolarized light reflected by a smooth dielectric, with `eta` the IOR of the side opposite the normal
+/// relative to the normal's side. `cos_theta` is negative on the opposite side.
+pub fn fresnel_dielectric(cos_theta: Scalar, eta: Scalar) -> Scalar {
+    let (cos_i, eta) = if cos_theta < 0.0 { (-cos_theta, 1.0 / eta) } else { (cos_theta, eta) };
+    let cos_i = cos_i.min(1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/// Mirrors `w` about `n`
+#[inline]
+pub(crate) fn reflect(w: &Vec3, n: &Vec3) -> Vec3 {
+    n * (2.0 * glm::dot(w, n)) - w
+}
+
+/// Bends `w` through an interface with normal `n` and relative IOR `eta` as in `fresnel_dielectric`,
+/// or None on total internal reflection. Both directions point away from the interface.
+pub(crate) fn refract(w: &Vec3, n: &Vec3, eta: Scalar) -> Option<Vec3> {
+    let cos_i = glm::dot(n, w);
+    let (cos_i, eta, n) = if cos_i < 0.0 { (-cos_i, 1.0 / eta, -n) } else { (cos_i, eta, *n) };
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-w / eta + n * (cos_i / eta - cos_t))
+}
+
+/// Whether two directions in the shading frame are on the same side of the surface
+#[inline]
+pub(crate) fn same_hemisphere(a: &Vec3, b: &Vec3) -> bool {
+    a.z * b.z > 0.0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::sampler::{Sampler, Sobol};
+
+    /// Evenly spread directions over the whole sphere
+    fn uniform_sphere(n: usize) -> impl Iterator<Item = Vec3> {
+        let sampler = Sobol::new(11);
+        (0..n).map(move |i| {
+            let (u, v) = sampler.sample_2d((0, 0), i, 0);
+            let z = 1.0 - 2.0 * u;
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let phi = 2.0 * consts::PI * v;
+            Vec3::new(r * phi.cos(), r * phi.sin(), z)
+        })
+    }
+
+    /// Checks that `sample` agrees with `pdf` and `eval`, and returns the integral of the pdf over the sphere
+    pub fn check_bsdf(bsdf: &dyn Bsdf, wo: &Vec3) -> Scalar {
+        let sampler = Sobol::new(3);
+        for i in 0..256 {
+            if let Some(s) = bsdf.sample(wo, sampler.sample_2d((1, 1), i, 0)).filter(|s| !s.delta) {
+                assert!((glm::length(&s.wi) - 1.0).abs() <= 1.0e-4);
+                let pdf = bsdf.pdf(wo, &s.wi);
+                assert!((s.pdf - pdf).abs() <= 1.0e-3 * pdf.max(1.0), "{} != {}", s.pdf, pdf);
+                let value = bsdf.eval(wo, &s.wi);
+                assert!((s.value.g - value.g).abs() <= 1.0e-3 * value.g.max(1.0));
+            }
+        }
+        let n = 1 << 16;
+        uniform_sphere(n).map(|wi| bsdf.pdf(wo, &wi)).sum::<Scalar>() * 4.0 * consts::PI / n as Scalar
+    }
+
+    #[test]
+    fn conductor_fresnel() {
+        // Normal incidence has a closed form: ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2)
+        let (n, k) = (0.2f32, 3.9f32);
+        let expected = ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+        assert!((fresnel_conductor(1.0, n, k) - expected).abs() <= 1.0e-5);
+        assert!((fresnel_conductor(0.0, n, k) - 1.0).abs() <= 1.0e-5);
+
+        let fit = ComplexIor::from_reflectance(Color3::new(0.9, 0.6, 0.3), Color3::gray(0.5));
+        let f0 = Fresnel::Conductor(fit).eval(1.0);
+        assert!((f0.r - 0.9).abs() <= 1.0e-3 && (f0.b - 0.3).abs() <= 1.0e-3);
+    }
+
+    #[test]
+    fn refraction_follows_snell() {
+        let w = glm::normalize(&Vec3::new(0.6, 0.0, 0.8));
+        let n = Vec3::new(0.0, 0.0, 1.0);
+        let t = refract(&w, &n, 1.5).unwrap();
+        assert!((t.x + 0.6 / 1.5).abs() <= 1.0e-6 && t.z < 0.0);
+        // And back out again
+        let back = refract(&t, &n, 1.5).unwrap();
+        assert!((back - w).norm() <= 1.0e-6);
+        assert!(refract(&glm::normalize(&Vec3::new(0.9, 0.0, -0.2)), &n, 1.5).is_none());
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() <= 1.0e-6);
+        assert_eq!(fresnel_dielectric(-0.2, 1.5), 1.0);
+    }
+
+    #[test]
+    fn frame_round_trips() {
+        for &n in [Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), glm::normalize(&Vec3::new(1.0, -2.0, 0.5))].iter() {
+            let frame = Frame::from_normal(&n);
+            assert!((frame.to_local(&n) - Vec3::new(0.0, 0.0, 1.0)).norm() <= 1.0e-6);
+            assert!(glm::dot(&frame.tangent, &frame.bitangent).abs() <= 1.0e-6);
+            let v = Vec3::new(0.3, 0.4, -0.5);
+            assert!((frame.to_world(&frame.to_local(&v)) - v).norm() <= 1.0e-6);
+        }
+        let n = Vec3::new(0.0, 1.0, 0.0);
+        let frame = Frame::from_normal_tangent(&n, Some(&Vec3::new(1.0, 0.5, 0.0)));
+        assert!((frame.tangent - Vec3::new(1.0, 0.0, 0.0)).norm() <= 1.0e-6);
+        assert!((frame.to_local(&n) - Vec3::new(0.0, 0.0, 1.0)).norm() <= 1.0e-6);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::math::*;
+use crate::Color3;
+use super::{Bsdf, BsdfSample, Lambert, same_hemisphere};
+
+/**
+ * Diffuse reflection from a rough surface of tiny Lambertian V-cavities, such as clay, plaster or cloth
+ *
+ * Uses the qualitative model from Oren and Nayar, "Generalization of Lambert's Reflectance Model".
+ * Rough surfaces look flatter than Lambertian ones, brightening towards the edges when lit from behind the viewer.
+ * Sampled like a Lambertian surface.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct OrenNayar {
+    pub albedo: Color3,
+    /// Standard deviation of the facet angles, in radians. 0 is Lambertian.
+    pub sigma: Scalar,
+}
+impl OrenNayar {
+    pub fn new(albedo: Color3, sigma: Scalar) -> Self {
+        OrenNayar {
+            albedo,
+            sigma,
+        }
+    }
+}
+impl Bsdf for OrenNayar {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color3 {
+        if wo.z <= 0.0 || !same_hemisphere(wo, wi) {
+            return Color3::gray(0.0);
+        }
+        let s2 = self.sigma * self.sigma;
+        let a = 1.0 - 0.5 * s2 / (s2 + 0.33);
+        let b = 0.45 * s2 / (s2 + 0.09);
+
+        let sin_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        let sin_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+        // cos(phi_i - phi_o), from the projections onto the surface
+        let cos_phi = if sin_i > 1.0e-4 && sin_o > 1.0e-4 {
+            ((wi.x * wo.x + wi.y * wo.y) / (sin_i * sin_o)).max(0.0)
+        } else {
+            0.0
+        };
+        // sin(alpha) tan(beta), with alpha the larger of the two angles to the normal and beta the smaller
+        let (sin_alpha, tan_beta) = if wi.z.abs() > wo.z.abs() {
+            (sin_o, sin_i / wi.z.abs())
+        } else {
+            (sin_i, sin_o / wo.z.abs())
+        };
+        self.albedo * ((a + b * cos_phi * sin_alpha * tan_beta) / consts::PI) as f32
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        Lambert::new(self.albedo).pdf(wo, wi)
+    }
+
+    fn sample(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Option<BsdfSample> {
+        let s = Lambert::new(self.albedo).sample(wo, u)?;
+        Some(BsdfSample {
+            value: self.eval(wo, &s.wi),
+            ..s
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm as glm;
+
+    #[test]
+    fn rough_diffuse_is_flatter() {
+        let wo = glm::normalize(&Vec3::new(1.0, 0.0, 0.2));
+        let lambert = OrenNayar::new(Color3::gray(1.0), 0.0);
+        assert!((lambert.eval(&wo, &wo).r - 1.0 / std::f32::consts::PI).abs() <= 1.0e-6);
+        // Light from behind the viewer at a grazing angle comes back brighter
+        let rough = OrenNayar::new(Color3::gray(1.0), 0.5);
+        let overhead = Vec3::new(0.0, 0.0, 1.0);
+        assert!(rough.eval(&wo, &wo).r > rough.eval(&wo, &overhead).r);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::math::*;
+use crate::Color3;
+use super::{Bsdf, BsdfSample, Lambert, same_hemisphere};
+use super::albedo::AlbedoTable;
+use crate::sampler::{Sampler, Sobol};
+use lazy_static::lazy_static;
+use nalgebra_glm as glm;
+
+/// Narrowest distribution used, where the exponent of the distribution is still well-behaved
+const MIN_ALPHA: Scalar = 1.0e-2;
+
+/**
+ * The soft retro-reflective glow of fabrics such as velvet, from fibers standing up off the surface
+ *
+ * Uses the "Charlie" distribution from Estevez and Kulla, "Production Friendly Microfacet Sheen BRDF", with the
+ * visibility term of Neubelt and Pettineo, as in glTF's KHR_materials_sheen. Sampled like a Lambertian surface.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct Sheen {
+    pub color: Color3,
+    /// Width of the distribution: the square of perceptual roughness
+    pub alpha: Scalar,
+}
+impl Sheen {
+    pub fn new(color: Color3, alpha: Scalar) -> Self {
+        Sheen {
+            color,
+            alpha: alpha.max(MIN_ALPHA),
+        }
+    }
+
+    /// Density of fiber directions around the half vector
+    fn d(&self, h: &Vec3) -> Scalar {
+        let inv_alpha = 1.0 / self.alpha;
+        let sin2 = (1.0 - h.z * h.z).max(0.0);
+        (2.0 + inv_alpha) * sin2.powf(0.5 * inv_alpha) / (2.0 * consts::PI)
+    }
+
+    fn brdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        let h = glm::normalize(&(wo + wi));
+        let visibility = 1.0 / (4.0 * (wi.z + wo.z - wi.z * wo.z));
+        self.d(&h) * visibility
+    }
+}
+impl Bsdf for Sheen {
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color3 {
+        if wo.z <= 0.0 || !same_hemisphere(wo, wi) {
+            return Color3::gray(0.0);
+        }
+        self.color * self.brdf(wo, wi) as f32
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> Scalar {
+        Lambert::new(self.color).pdf(wo, wi)
+    }
+
+    fn sample(&self, wo: &Vec3, u: (Scalar, Scalar)) -> Option<BsdfSample> {
+        let s = Lambert::new(self.color).sample(wo, u)?;
+        Some(BsdfSample {
+            value: self.eval(wo, &s.wi),
+            ..s
+        })
+    }
+}
+
+lazy_static! {
+    /// Directional albedo of white sheen
+    static ref ALBEDO: AlbedoTable = AlbedoTable::compute(|alpha, mu| {
+        const SAMPLES: usize = 256;
+        let sampler = Sobol::new(0);
+        let sheen = Sheen::new(Color3::gray(1.0), alpha);
+        let wo = Vec3::new((1.0 - mu * mu).sqrt(), 0.0, mu);
+        (0..SAMPLES).filter_map(|s| sheen.sample(&wo, sampler.sample_2d((0, 0), s, 0)))
+            .map(|s| s.value.g as Scalar * s.wi.z / s.pdf)
+            .sum::<Scalar>() / SAMPLES as Scalar
+    });
+}
+
+/// Fraction of the light arriving from a direction with the given cosine that white sheen reflects
+pub fn sheen_albedo(alpha: Scalar, cos_theta: Scalar) -> Scalar {
+    ALBEDO.lookup(alpha.max(MIN_ALPHA), cos_theta)
+}
+
+/// Cosine-weighted average of `sheen_albedo` over the hemisphere
+pub fn average_sheen_albedo(alpha: Scalar) -> Scalar {
+    ALBEDO.average(alpha.max(MIN_ALPHA))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sheen_is_strongest_at_grazing_angles() {
+        let sheen = Sheen::new(Color3::gray(1.0), 0.3);
+        let grazing = glm::normalize(&Vec3::new(1.0, 0.0, 0.1));
+        let overhead = Vec3::new(0.0, 0.0, 1.0);
+        assert!(sheen.eval(&grazing, &grazing).r > sheen.eval(&overhead, &overhead).r);
+        for &mu in [0.1, 0.5, 1.0].iter() {
+            let e = sheen_albedo(0.3, mu);
+            assert!(e > 0.0 && e < 1.0, "{}", e);
+        }
+    }
+}
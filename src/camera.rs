@@ -1,6 +1,6 @@
 use nalgebra_glm as glm;
 use crate::math::*;
-use crate::Ray;
+use crate::{Ray, RayDifferential};
 use consts::{RIGHT, UP, FORWARD};
 
 #[derive(Debug, Clone)]
@@ -186,6 +186,18 @@ impl Camera {
             }
         }
     }
+
+    /// Like `primary_ray`, with differentials towards the rays `spacing` further along in x and y, such as one pixel over
+    pub fn primary_ray_differential(&self, x: Scalar, y: Scalar, spacing: (Scalar, Scalar)) -> Ray {
+        let rx = self.primary_ray(x + spacing.0, y);
+        let ry = self.primary_ray(x, y + spacing.1);
+        self.primary_ray(x, y).with_differential(Some(RayDifferential {
+            rx_origin: rx.origin,
+            rx_direction: rx.direction,
+            ry_origin: ry.origin,
+            ry_direction: ry.direction,
+        }))
+    }
 }
 
 static DEFAULT_ASPECT: Scalar = 16.0/9.0;
@@ -273,5 +285,10 @@ mod tests {
         let ray = camera.primary_ray(0.0, 0.0);
         assert!(glm::length(&ray.origin) <= consts::EPSILON);
         assert!(glm::length(&(ray.direction - Vec3::new(0.0, 0.0, -1.0))) <= consts::EPSILON);
+
+        let ray = camera.primary_ray_differential(0.0, 0.0, (0.01, -0.01));
+        let d = ray.differential.unwrap();
+        assert!(d.rx_direction.x > 0.0 && d.ry_direction.y < 0.0);
+        assert!(glm::length(&(d.rx_direction - camera.primary_ray(0.01, 0.0).direction)) <= consts::EPSILON);
     }
 }
\ No newline at end of file
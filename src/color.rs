@@ -64,14 +64,49 @@ impl Color3 {
             b: (rgb.b as f32) / 255.0,
         }
     }
+    /// Quantizes linear values in [0, 1] without any transfer curve.
+    /// For display output, use `to_srgb8` (after tone mapping) instead.
     #[inline]
     pub fn to_rgb8(&self) -> RGB8 {
         RGB8 {
-            r: (self.r * 255.0) as u8,
-            g: (self.g * 255.0) as u8,
-            b: (self.b * 255.0) as u8,
+            r: quantize8(self.r),
+            g: quantize8(self.g),
+            b: quantize8(self.b),
         }
     }
+    /// Decodes an sRGB-encoded 8-bit color into linear values
+    #[inline]
+    pub fn from_srgb8(rgb: RGB8) -> Self {
+        Color3::from_rgb8(rgb).to_linear()
+    }
+    /// Applies the sRGB OETF, clamps to [0, 1] and rounds to the nearest 8-bit value
+    #[inline]
+    pub fn to_srgb8(&self) -> RGB8 {
+        self.to_srgb().to_rgb8()
+    }
+    /// Encodes linear values with the sRGB transfer curve. Values outside [0, 1] are clamped.
+    #[inline]
+    pub fn to_srgb(&self) -> Self {
+        Color3 {
+            r: linear_to_srgb(self.r),
+            g: linear_to_srgb(self.g),
+            b: linear_to_srgb(self.b),
+        }
+    }
+    /// Decodes sRGB-encoded values back into linear values
+    #[inline]
+    pub fn to_linear(&self) -> Self {
+        Color3 {
+            r: srgb_to_linear(self.r),
+            g: srgb_to_linear(self.g),
+            b: srgb_to_linear(self.b),
+        }
+    }
+    /// Relative luminance, using the Rec.709 primaries
+    #[inline]
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
 
     pub fn mix(&self, other: &Color3, alpha: f32) -> Color3 {
         Color3 {
@@ -82,6 +117,30 @@ impl Color3 {
     }
 }
 
+/// The sRGB OETF: maps linear [0, 1] to the encoded [0, 1] range
+pub fn linear_to_srgb(x: f32) -> f32 {
+    let x = glm::clamp_scalar(x, 0.0, 1.0);
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+/// Inverse of `linear_to_srgb`
+pub fn srgb_to_linear(x: f32) -> f32 {
+    let x = glm::clamp_scalar(x, 0.0, 1.0);
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[inline]
+fn quantize8(x: f32) -> u8 {
+    (glm::clamp_scalar(x, 0.0, 1.0) * 255.0).round() as u8
+}
+
 impl Add for Color3 {
     type Output = Self;
 
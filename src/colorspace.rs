@@ -0,0 +1,153 @@
+use crate::math::*;
+use crate::Color3;
+use nalgebra_glm as glm;
+
+/// CIE xy chromaticities of the primaries and white point of an RGB color space, as stored in OpenEXR headers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Chromaticities {
+    pub red: (f32, f32),
+    pub green: (f32, f32),
+    pub blue: (f32, f32),
+    pub white: (f32, f32),
+}
+
+/// The white point of the D65 illuminant, used by sRGB and Rec.2020
+const D65: (f32, f32) = (0.3127, 0.3290);
+
+/**
+ * A linear RGB color space, which gives meaning to the three components of a `Color3`
+ *
+ * Scene colors and rendered images are all in the world's working space. Converting between spaces with different
+ * white points adapts the white with the Bradford transform, so white stays white, except to and from XYZ,
+ * which is left absolute.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// The primaries of sRGB and Rec.709, with a D65 white and no transfer curve
+    #[default]
+    LinearSrgb,
+    /// The AP1 primaries of ACES, with the ACES white, for rendering
+    AcesCg,
+    /// The wide gamut of UHDTV, with a D65 white
+    Rec2020,
+    /// CIE 1931 XYZ
+    Xyz,
+}
+impl ColorSpace {
+    pub fn chromaticities(&self) -> Chromaticities {
+        match self {
+            ColorSpace::LinearSrgb => Chromaticities { red: (0.64, 0.33), green: (0.30, 0.60), blue: (0.15, 0.06), white: D65 },
+            ColorSpace::AcesCg => Chromaticities {
+                red: (0.713, 0.293),
+                green: (0.165, 0.830),
+                blue: (0.128, 0.044),
+                white: (0.32168, 0.33767),
+            },
+            ColorSpace::Rec2020 => Chromaticities { red: (0.708, 0.292), green: (0.170, 0.797), blue: (0.131, 0.046), white: D65 },
+            // Pure X, Y and Z as primaries, with the equal-energy white
+            ColorSpace::Xyz => Chromaticities { red: (1.0, 0.0), green: (0.0, 1.0), blue: (0.0, 0.0), white: (1.0 / 3.0, 1.0 / 3.0) },
+        }
+    }
+
+    /// Maps colors in this space to CIE XYZ, so that white has a luminance of 1
+    pub fn to_xyz(&self) -> Mat3 {
+        if *self == ColorSpace::Xyz {
+            return Mat3::identity();
+        }
+        let c = self.chromaticities();
+        let xyz = |(x, y): (f32, f32)| Vec3::new(x as Scalar / y as Scalar, 1.0, (1.0 - x - y) as Scalar / y as Scalar);
+        let primaries = Mat3::from_columns(&[xyz(c.red), xyz(c.green), xyz(c.blue)]);
+        // Scale each primary so that together they add up to the white point
+        let scale = glm::inverse(&primaries) * xyz(c.white);
+        primaries * Mat3::from_diagonal(&scale)
+    }
+
+    pub fn from_xyz(&self) -> Mat3 {
+        glm::inverse(&self.to_xyz())
+    }
+
+    /// Maps colors in this space to the same colors in `to`
+    pub fn conversion(&self, to: ColorSpace) -> Mat3 {
+        if *self == to {
+            return Mat3::identity();
+        }
+        let adaptation = if *self == ColorSpace::Xyz || to == ColorSpace::Xyz {
+            Mat3::identity()
+        } else {
+            bradford(self.chromaticities().white, to.chromaticities().white)
+        };
+        to.from_xyz() * adaptation * self.to_xyz()
+    }
+
+    pub fn convert(&self, c: Color3, to: ColorSpace) -> Color3 {
+        if *self == to {
+            return c;
+        }
+        transform(&self.conversion(to), c)
+    }
+
+    /// Luminance (CIE Y) of a color in this space
+    pub fn luminance(&self, c: Color3) -> f32 {
+        let m = self.to_xyz();
+        (m[(1, 0)] * c.r as Scalar + m[(1, 1)] * c.g as Scalar + m[(1, 2)] * c.b as Scalar) as f32
+    }
+}
+
+/// Applies a matrix to a color
+pub fn transform(m: &Mat3, c: Color3) -> Color3 {
+    let v = m * Vec3::new(c.r as Scalar, c.g as Scalar, c.b as Scalar);
+    Color3::new(v.x as f32, v.y as f32, v.z as f32)
+}
+
+/// Chromatic adaptation of XYZ colors from one white point to another, in the cone space of the Bradford transform
+fn bradford(from: (f32, f32), to: (f32, f32)) -> Mat3 {
+    if from == to {
+        return Mat3::identity();
+    }
+    let cone = Mat3::new(
+        0.8951, 0.2664, -0.1614,
+        -0.7502, 1.7135, 0.0367,
+        0.0389, -0.0685, 1.0296,
+    );
+    let xyz = |(x, y): (f32, f32)| Vec3::new(x as Scalar / y as Scalar, 1.0, (1.0 - x - y) as Scalar / y as Scalar);
+    let (source, destination) = (cone * xyz(from), cone * xyz(to));
+    let scale = Mat3::from_diagonal(&destination.component_div(&source));
+    glm::inverse(&cone) * scale * cone
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(m: &Mat3, row: usize, expected: [Scalar; 3]) -> bool {
+        (0..3).all(|j| (m[(row, j)] - expected[j]).abs() <= 2.0e-3)
+    }
+
+    #[test]
+    fn matrices_match_the_standards() {
+        let srgb = ColorSpace::LinearSrgb.to_xyz();
+        assert!(close(&srgb, 0, [0.4124, 0.3576, 0.1805]), "{}", srgb);
+        assert!(close(&srgb, 1, [0.2126, 0.7152, 0.0722]), "{}", srgb);
+        // The usual ACEScg to sRGB matrix, with Bradford adaptation from the ACES white to D65
+        let aces = ColorSpace::AcesCg.conversion(ColorSpace::LinearSrgb);
+        assert!(close(&aces, 0, [1.7051, -0.6218, -0.0833]), "{}", aces);
+        assert!(close(&aces, 2, [-0.0240, -0.1290, 1.1530]), "{}", aces);
+    }
+
+    #[test]
+    fn white_stays_white_and_conversions_round_trip() {
+        let spaces = [ColorSpace::LinearSrgb, ColorSpace::AcesCg, ColorSpace::Rec2020, ColorSpace::Xyz];
+        let c = Color3::new(0.8, 0.3, 0.1);
+        for &from in spaces.iter() {
+            for &to in spaces.iter() {
+                let back = to.convert(from.convert(c, to), from);
+                assert!((back.r - c.r).abs() <= 1.0e-4 && (back.b - c.b).abs() <= 1.0e-4, "{:?} to {:?}", from, to);
+                if from != ColorSpace::Xyz && to != ColorSpace::Xyz {
+                    let white = from.convert(Color3::gray(1.0), to);
+                    assert!((white.r - 1.0).abs() <= 1.0e-3 && (white.g - 1.0).abs() <= 1.0e-3 && (white.b - 1.0).abs() <= 1.0e-3);
+                }
+            }
+        }
+        assert!((ColorSpace::AcesCg.luminance(Color3::gray(1.0)) - 1.0).abs() <= 1.0e-4);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::Framebuffer;
+use std::sync::{Arc as Shared, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/**
+ * Progress and cancellation state shared between a render and the threads watching it
+ *
+ * Progress is counted in abstract units of work (pixels, or pixel-passes for progressive renders),
+ * so the total may be revised while the render is running.
+ */
+#[derive(Debug, Default)]
+pub struct RenderControl {
+    cancelled: AtomicBool,
+    complete: AtomicBool,
+    done: AtomicUsize,
+    total: AtomicUsize,
+    started: Mutex<Option<Instant>>,
+}
+impl RenderControl {
+    pub fn new() -> Self {
+        RenderControl::default()
+    }
+
+    /// Asks the render to stop as soon as possible. Already rendered pixels are kept.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+    /// Whether the render ran to completion without being cancelled
+    pub fn is_complete(&self) -> bool {
+        self.complete.load(Ordering::Acquire)
+    }
+
+    /// Fraction of the work done, in [0, 1]
+    pub fn progress(&self) -> f32 {
+        if self.is_complete() {
+            return 1.0;
+        }
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        (self.done.load(Ordering::Relaxed) as f64 / total as f64).min(1.0) as f32
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match *self.started.lock().unwrap() {
+            Some(t) => t.elapsed(),
+            None => Duration::from_secs(0),
+        }
+    }
+
+    /// Estimated time remaining, extrapolated from the progress so far. `None` until some work is done.
+    pub fn eta(&self) -> Option<Duration> {
+        let p = self.progress() as f64;
+        if p >= 1.0 {
+            return Some(Duration::from_secs(0));
+        }
+        if p <= 0.0 {
+            return None;
+        }
+        Some(self.elapsed().mul_f64((1.0 - p) / p))
+    }
+
+    pub(crate) fn start(&self, total: usize) {
+        *self.started.lock().unwrap() = Some(Instant::now());
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+        self.complete.store(false, Ordering::Release);
+    }
+    pub(crate) fn advance(&self, units: usize) {
+        self.done.fetch_add(units, Ordering::Relaxed);
+    }
+    pub(crate) fn set_total(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+    pub(crate) fn mark_complete(&self) {
+        self.complete.store(true, Ordering::Release);
+    }
+}
+
+/// The image from a render that either ran to completion or was cancelled part way
+#[derive(Debug, Clone)]
+pub enum Rendered {
+    Complete(Framebuffer),
+    /// Pixels that were not reached are black and transparent
+    Cancelled(Framebuffer),
+}
+impl Rendered {
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            Rendered::Complete(_) => false,
+            Rendered::Cancelled(_) => true,
+        }
+    }
+    pub fn image(&self) -> &Framebuffer {
+        match self {
+            Rendered::Complete(fb) | Rendered::Cancelled(fb) => fb,
+        }
+    }
+    pub fn into_image(self) -> Framebuffer {
+        match self {
+            Rendered::Complete(fb) | Rendered::Cancelled(fb) => fb,
+        }
+    }
+}
+
+/// A render running on a background thread
+pub struct RenderHandle {
+    control: Shared<RenderControl>,
+    thread: JoinHandle<Rendered>,
+}
+impl RenderHandle {
+    pub(crate) fn new(control: Shared<RenderControl>, thread: JoinHandle<Rendered>) -> Self {
+        RenderHandle {
+            control,
+            thread,
+        }
+    }
+
+    /// For sharing with other threads that need to watch or cancel the render
+    pub fn control(&self) -> &Shared<RenderControl> {
+        &self.control
+    }
+    pub fn progress(&self) -> f32 {
+        self.control.progress()
+    }
+    pub fn eta(&self) -> Option<Duration> {
+        self.control.eta()
+    }
+    pub fn cancel(&self) {
+        self.control.cancel()
+    }
+    /// Whether the render thread has stopped, either complete or cancelled
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the render to finish, or to stop after being cancelled
+    pub fn join(self) -> Rendered {
+        match self.thread.join() {
+            Ok(rendered) => rendered,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{Color3, Framebuffer};
+use nalgebra_glm as glm;
+#[cfg(feature="parallel")]
+use rayon::prelude::*;
+
+/// Weights of the B3 spline, which the filter spreads out further on each pass
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+/// Albedo is floored at this before it's divided out, so black surfaces and the background don't blow up
+const MIN_ALBEDO: f32 = 0.01;
+
+/**
+ * Smooths out the noise of low sample counts, while keeping the edges that the guide AOVs show
+ *
+ * An edge-avoiding à-trous wavelet filter, after Dammertz et al., "Edge-Avoiding À-Trous Wavelet Transform for fast
+ * Global Illumination Filtering". Each pass blurs with a 5x5 kernel whose taps are twice as far apart as the last,
+ * and each tap is weighted down by how much its color, normal and albedo differ from the pixel's. Lighting is
+ * filtered separately from the albedo, so textures stay sharp.
+ *
+ * The normal and albedo AOVs guide the filter, and it falls back on color alone without them.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct Denoiser {
+    /// Number of passes. The filter reaches 4 * (2^iterations - 1) pixels out.
+    pub iterations: usize,
+    /// How different the lighting may look before the filter stops blending, on a 0 to 1 scale. Halved every pass.
+    pub color_sigma: f32,
+    /// How far apart normals may point, as the length of their difference
+    pub normal_sigma: f32,
+    pub albedo_sigma: f32,
+}
+impl Denoiser {
+    pub fn new(iterations: usize) -> Self {
+        Denoiser {
+            iterations,
+            ..Denoiser::default()
+        }
+    }
+
+    /// A denoised copy of the image. Alpha and the AOVs are left as they are.
+    pub fn denoise(&self, fb: &Framebuffer) -> Framebuffer {
+        let (w, h) = (fb.width, fb.height);
+        let mut out = fb.clone();
+        if w == 0 || h == 0 || fb.pixels.len() != w * h {
+            return out;
+        }
+        let normals = fb.aovs.normal.as_ref();
+        let albedo = fb.aovs.albedo.as_ref();
+
+        // Take the albedo out, to put back in at the end. Every pixel is divided, so that all are filtered alike.
+        let floored = |i: usize| albedo.map(|a| Color3::new(a[i].r.max(MIN_ALBEDO), a[i].g.max(MIN_ALBEDO), a[i].b.max(MIN_ALBEDO)));
+        let mut lighting: Vec<Color3> = fb.pixels.iter().enumerate()
+            .map(|(i, &c)| floored(i).map_or(c, |a| c / a))
+            .collect();
+
+        let mut next = lighting.clone();
+        for pass in 0..self.iterations {
+            let step = 1usize << pass;
+            let sigma = self.color_sigma / (1 << pass) as f32;
+            let filter = |(y, row): (usize, &mut [Color3])| {
+                for (x, out) in row.iter_mut().enumerate() {
+                    let p = y * w + x;
+                    let center = compress(lighting[p]);
+                    let mut sum = Color3::gray(0.0);
+                    let mut total = 0.0;
+                    for (j, ky) in KERNEL.iter().enumerate() {
+                        let qy = y as isize + (j as isize - 2) * step as isize;
+                        if qy < 0 || qy >= h as isize {
+                            continue;
+                        }
+                        for (i, kx) in KERNEL.iter().enumerate() {
+                            let qx = x as isize + (i as isize - 2) * step as isize;
+                            if qx < 0 || qx >= w as isize {
+                                continue;
+                            }
+                            let q = qy as usize * w + qx as usize;
+                            let mut weight = ky * kx * gaussian(distance2(&center, &compress(lighting[q])), sigma);
+                            if let Some(n) = normals {
+                                weight *= gaussian(glm::distance2(&n[p], &n[q]) as f32, self.normal_sigma);
+                            }
+                            if let Some(a) = albedo {
+                                weight *= gaussian(distance2(&a[p], &a[q]), self.albedo_sigma);
+                            }
+                            sum += lighting[q] * weight;
+                            total += weight;
+                        }
+                    }
+                    // The center tap always counts fully, so the total is never 0
+                    *out = sum / total;
+                }
+            };
+            #[cfg(feature="parallel")]
+            next.par_chunks_mut(w).enumerate().for_each(filter);
+            #[cfg(not(feature="parallel"))]
+            next.chunks_mut(w).enumerate().for_each(filter);
+            std::mem::swap(&mut lighting, &mut next);
+        }
+
+        for (i, (p, c)) in out.pixels.iter_mut().zip(lighting.iter()).enumerate() {
+            *p = floored(i).map_or(*c, |a| *c * a);
+        }
+        out
+    }
+}
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            color_sigma: 0.8,
+            normal_sigma: 0.3,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+/// Squeezes HDR values into [0, 1), so that differences between bright pixels count for less
+fn compress(c: Color3) -> Color3 {
+    Color3::new(c.r / (1.0 + c.r.abs()), c.g / (1.0 + c.g.abs()), c.b / (1.0 + c.b.abs()))
+}
+
+fn distance2(a: &Color3, b: &Color3) -> f32 {
+    let d = *a - *b;
+    d.r * d.r + d.g * d.g + d.b * d.b
+}
+
+fn gaussian(distance2: f32, sigma: f32) -> f32 {
+    if sigma <= 0.0 {
+        return if distance2 <= 0.0 { 1.0 } else { 0.0 };
+    }
+    (-distance2 / (sigma * sigma)).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::*;
+    use crate::{AovSelection, AovBuffers};
+
+    /// Two flat walls meeting down the middle, lit evenly but noisily
+    fn walls() -> Framebuffer {
+        let (w, h) = (32, 32);
+        let mut fb = Framebuffer::new(w, h);
+        fb.aovs = AovBuffers::new(&AovSelection { normal: true, albedo: true, ..AovSelection::none() }, w * h);
+        let mut seed = 12345u32;
+        for i in 0..w * h {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            let noise = (seed >> 8) as f32 / (1u32 << 24) as f32 * 2.0;
+            let left = i % w < w / 2;
+            let albedo = Color3::gray(if left { 0.8 } else { 0.2 });
+            fb.aovs.albedo.as_mut().unwrap()[i] = albedo;
+            fb.aovs.normal.as_mut().unwrap()[i] = if left { Vec3::new(0.0, 0.0, 1.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+            fb.pixels[i] = albedo * noise;
+        }
+        fb
+    }
+
+    fn spread(fb: &Framebuffer, x_range: std::ops::Range<usize>) -> (f32, f32) {
+        let values: Vec<f32> = (0..fb.height).flat_map(|y| x_range.clone().map(move |x| (x, y)))
+            .map(|(x, y)| fb.get(x, y).g)
+            .collect();
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / values.len() as f32;
+        (mean, variance)
+    }
+
+    #[test]
+    fn smooths_noise_but_keeps_edges() {
+        let noisy = walls();
+        let clean = Denoiser::default().denoise(&noisy);
+        let (noisy_mean, noisy_variance) = spread(&noisy, 0..16);
+        let (mean, variance) = spread(&clean, 0..16);
+        assert!(variance < 0.1 * noisy_variance, "{} {}", variance, noisy_variance);
+        assert!((mean - noisy_mean).abs() <= 0.05 * noisy_mean);
+
+        // Neither wall bleeds into the other
+        for y in 0..32 {
+            assert!(clean.get(15, y).g > 0.6 && clean.get(16, y).g < 0.3, "{:?} {:?}", clean.get(15, y), clean.get(16, y));
+        }
+        let (right, _) = spread(&clean, 16..32);
+        assert!((right - spread(&noisy, 16..32).0).abs() <= 0.05 * right);
+    }
+
+    #[test]
+    fn albedo_with_black_channels_is_divided_out_too() {
+        // A fine texture with no blue in it, under flat light
+        let (w, h) = (16, 16);
+        let mut fb = Framebuffer::new(w, h);
+        fb.aovs = AovBuffers::new(&AovSelection { albedo: true, ..AovSelection::none() }, w * h);
+        for i in 0..w * h {
+            let albedo = Color3::new(0.2 + 0.6 * (i % 3) as f32 / 2.0, 0.5, 0.0);
+            fb.aovs.albedo.as_mut().unwrap()[i] = albedo;
+            fb.pixels[i] = albedo * 0.7;
+        }
+        // Loose enough that neighbours of any albedo blend
+        let denoiser = Denoiser { albedo_sigma: 10.0, ..Denoiser::default() };
+        let clean = denoiser.denoise(&fb);
+        for (a, b) in fb.pixels.iter().zip(clean.pixels.iter()) {
+            assert!((a.r - b.r).abs() <= 1.0e-5 && (a.g - b.g).abs() <= 1.0e-5 && b.b == 0.0, "{:?} {:?}", a, b);
+        }
+    }
+}
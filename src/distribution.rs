@@ -0,0 +1,128 @@
+use crate::math::*;
+
+/// A piecewise-constant density over [0, 1], for drawing samples in proportion to a tabulated function
+#[derive(Debug, Clone)]
+pub(crate) struct Distribution1d {
+    func: Vec<Scalar>,
+    /// Running integral of `func`, normalized to end at 1
+    cdf: Vec<Scalar>,
+    /// Integral of `func` over [0, 1]
+    pub integral: Scalar,
+}
+impl Distribution1d {
+    /// `func` must not be negative. If it is zero everywhere, the distribution is uniform.
+    pub fn new(func: Vec<Scalar>) -> Self {
+        let n = func.len().max(1);
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for (i, f) in func.iter().enumerate() {
+            cdf.push(cdf[i] + f.max(0.0) / n as Scalar);
+        }
+        let integral = *cdf.last().unwrap();
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 { *c / integral } else { i as Scalar / n as Scalar };
+        }
+        Distribution1d {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Density at the given bucket
+    fn density(&self, i: usize) -> Scalar {
+        if self.integral > 0.0 {
+            self.func[i].max(0.0) / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    /// A point in [0, 1) for a uniform `u`, with its density and the bucket it fell in
+    pub fn sample(&self, u: Scalar) -> (Scalar, Scalar, usize) {
+        let n = self.len();
+        // The last bucket whose running total doesn't exceed u
+        let i = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(n - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0.0 { (u - self.cdf[i]) / width } else { 0.0 };
+        let x = ((i as Scalar + du) / n as Scalar).min(1.0 - consts::EPSILON);
+        (x, self.density(i), i)
+    }
+
+    pub fn pdf(&self, x: Scalar) -> Scalar {
+        let n = self.len();
+        self.density(((x * n as Scalar) as usize).min(n - 1))
+    }
+}
+
+/// A piecewise-constant density over the unit square, tabulated row by row
+#[derive(Debug, Clone)]
+pub(crate) struct Distribution2d {
+    rows: Vec<Distribution1d>,
+    marginal: Distribution1d,
+}
+impl Distribution2d {
+    /// `func` holds `height` rows of `width` values each
+    pub fn new(func: &[Scalar], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1d> = func.chunks(width.max(1)).take(height)
+            .map(|row| Distribution1d::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1d::new(rows.iter().map(|r| r.integral).collect());
+        Distribution2d {
+            rows,
+            marginal,
+        }
+    }
+
+    /// A point in the unit square, choosing the row first, with its density
+    pub fn sample(&self, u: (Scalar, Scalar)) -> (Vec2, Scalar) {
+        if self.rows.is_empty() {
+            return (Vec2::new(0.5, 0.5), 0.0);
+        }
+        let (y, pdf_y, row) = self.marginal.sample(u.1);
+        let (x, pdf_x, _) = self.rows[row].sample(u.0);
+        (Vec2::new(x, y), pdf_x * pdf_y)
+    }
+
+    /// Integral of the function over the unit square
+    pub fn integral(&self) -> Scalar {
+        self.marginal.integral
+    }
+
+    pub fn pdf(&self, p: &Vec2) -> Scalar {
+        let n = self.rows.len();
+        if n == 0 {
+            return 0.0;
+        }
+        let row = ((p.y * n as Scalar) as usize).min(n - 1);
+        self.marginal.pdf(p.y) * self.rows[row].pdf(p.x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_follow_the_function() {
+        let d = Distribution1d::new(vec![0.0, 1.0, 3.0, 0.0]);
+        assert_eq!(d.integral, 1.0);
+        let (x, pdf, i) = d.sample(0.1);
+        assert_eq!(i, 1);
+        assert!((x - 0.35).abs() <= 1.0e-6 && pdf == 1.0);
+        assert_eq!(d.sample(0.9).2, 2);
+        assert_eq!(d.pdf(0.6), 3.0);
+
+        let flat = Distribution1d::new(vec![0.0; 5]);
+        assert!((flat.sample(0.5).0 - 0.5).abs() <= 1.0e-6);
+
+        let d2 = Distribution2d::new(&[1.0, 0.0, 0.0, 3.0], 2, 2);
+        let (p, pdf) = d2.sample((0.5, 0.9));
+        assert!(p.x >= 0.5 && p.y >= 0.5);
+        assert!((pdf - d2.pdf(&p)).abs() <= 1.0e-6 && (pdf - 3.0).abs() <= 1.0e-6);
+    }
+}
@@ -0,0 +1,344 @@
+use crate::{Color3, Framebuffer, AovSelection, AovBuffers, ColorSpace};
+use crate::math::*;
+use crate::integrator::PathSample;
+use crate::filter::Filter;
+use crate::tile::Rect;
+use crate::world::MaterialIds;
+use std::ops::Range;
+use std::sync::Arc as Shared;
+
+/// Least weight a pixel's splats may add up to, per sample taken in it and relative to the filter's integral, before
+/// the pixel falls back on the mean of its own samples
+const MIN_WEIGHT: f32 = 1.0e-2;
+
+/// Running mean and variance of the samples taken in one pixel, using Welford's online algorithm
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct PixelStats {
+    pub count: u32,
+    pub mean: Color3,
+    /// Sum of squared differences from the mean
+    m2: Color3,
+}
+impl PixelStats {
+    pub fn new() -> Self {
+        PixelStats {
+            count: 0,
+            mean: Color3::gray(0.0),
+            m2: Color3::gray(0.0),
+        }
+    }
+
+    pub fn add(&mut self, x: Color3) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Combines two sets of statistics, as if every sample had been added to one (Chan et al.)
+    pub fn merge(&mut self, other: &PixelStats) {
+        if other.count == 0 {
+            return;
+        }
+        let n = self.count + other.count;
+        let delta = other.mean - self.mean;
+        let weight = other.count as f32 / n as f32;
+        self.mean += delta * weight;
+        self.m2 += other.m2 + delta * delta * (self.count as f32 * weight);
+        self.count = n;
+    }
+
+    /// Unbiased sample variance, per channel
+    pub fn variance(&self) -> Color3 {
+        if self.count < 2 {
+            Color3::gray(0.0)
+        } else {
+            self.m2 / (self.count - 1) as f32
+        }
+    }
+
+    /// Estimated standard error of the mean, relative to the mean's luminance. Infinite until there are two samples.
+    pub fn relative_error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+        let std_error = (self.variance().luminance().max(0.0) / self.count as f32).sqrt();
+        // The offset keeps near-black pixels from demanding endless samples
+        std_error / (self.mean.luminance().max(0.0) + 1.0e-2)
+    }
+}
+
+/// Fractional bits of the fixed-point sums in a `Splat`
+const SPLAT_BITS: i32 = 40;
+
+/**
+ * Filter-weighted sums of the samples splatted into one pixel
+ *
+ * The sums are fixed point, so unlike floating-point sums they come out exactly the same whatever order their terms
+ * are added in. Tiles that splat into each other's margins can then be merged as they finish, and the image is the
+ * same bit for bit on any number of threads and with any tile size. 40 fractional bits resolve about 1e-12, and 128
+ * bits leave room for sums up to about 1e26.
+ */
+#[derive(Debug, Copy, Clone, Default)]
+struct Splat {
+    color: [i128; 3],
+    coverage: i128,
+    weight: i128,
+}
+impl Splat {
+    fn add(&mut self, color: Color3, coverage: f32, weight: f32) {
+        let fixed = |x: f32| (x as f64 * (SPLAT_BITS as f64).exp2()).round() as i128;
+        self.color[0] = self.color[0].saturating_add(fixed(color.r * weight));
+        self.color[1] = self.color[1].saturating_add(fixed(color.g * weight));
+        self.color[2] = self.color[2].saturating_add(fixed(color.b * weight));
+        self.coverage = self.coverage.saturating_add(fixed(coverage * weight));
+        self.weight = self.weight.saturating_add(fixed(weight));
+    }
+
+    fn merge(&mut self, other: &Splat) {
+        for (a, b) in self.color.iter_mut().zip(other.color.iter()) {
+            *a = a.saturating_add(*b);
+        }
+        self.coverage = self.coverage.saturating_add(other.coverage);
+        self.weight = self.weight.saturating_add(other.weight);
+    }
+
+    fn weight(&self) -> f32 {
+        (self.weight as f64 / (SPLAT_BITS as f64).exp2()) as f32
+    }
+
+    /// The weighted mean color and coverage
+    fn mean(&self) -> (Color3, f32) {
+        let w = self.weight as f64;
+        let c = |x: i128| (x as f64 / w) as f32;
+        (Color3::new(c(self.color[0]), c(self.color[1]), c(self.color[2])), c(self.coverage))
+    }
+}
+
+/**
+ * Accumulates camera ray samples for a rectangle of pixels: either a whole image or a single tile
+ *
+ * Per-pixel statistics, which drive adaptive sampling, only count the samples taken in each pixel. The image itself
+ * is reconstructed from samples splatted through the filter, which may reach past the rectangle. Those splats
+ * land in a margin around it, to be added into the neighbouring pixels when the tile is merged.
+ */
+pub(crate) struct Film {
+    pub rect: Rect,
+    pub stats: Vec<PixelStats>,
+    /// How many of each pixel's samples hit something, for its coverage when the splats can't be used
+    hits: Vec<u32>,
+    aovs: AovBuffers,
+    filter: Shared<dyn Filter + Send + Sync>,
+    /// Of the filter's weights, see `Filter::integral`
+    integral: f32,
+    /// The pixels samples are splatted into: the rectangle and as much of its margin as lies within `clip`
+    bounds: Rect,
+    splats: Vec<Splat>,
+}
+impl Film {
+    pub fn new(rect: Rect, clip: &Rect, aovs: &AovSelection, filter: &Shared<dyn Filter + Send + Sync>) -> Self {
+        let n = rect.area();
+        let margin = (filter.radius() - 0.5).ceil().max(0.0) as usize;
+        let (x, y) = (rect.x.saturating_sub(margin), rect.y.saturating_sub(margin));
+        let bounds = Rect::new(x, y, rect.x + rect.width + margin - x, rect.y + rect.height + margin - y).intersection(clip);
+        Film {
+            rect,
+            stats: vec![PixelStats::new(); n],
+            hits: vec![0; n],
+            aovs: AovBuffers::new(aovs, n),
+            filter: filter.clone(),
+            integral: filter.integral() as f32,
+            bounds,
+            splats: vec![Splat::default(); bounds.area()],
+        }
+    }
+
+    /// Adds one sample to pixel i of the rectangle, taken at `position` on the image in pixels.
+    /// AOVs come from each pixel's first sample only, so IDs are never blended, except for
+    /// albedo and the direct and indirect light, which are averaged.
+    pub fn add_sample(&mut self, i: usize, position: (Scalar, Scalar), sample: &PathSample, materials: &MaterialIds) {
+        #[cfg(feature="spectral")]
+        let sample = &sample.to_rgb();
+        let first = self.stats[i].count == 0;
+        let radiance = sample.radiance();
+        self.stats[i].add(radiance);
+        self.aovs.add_means(i, sample, self.stats[i].count);
+        let coverage = if sample.hit.is_some() { 1.0 } else { 0.0 };
+        self.hits[i] += sample.hit.is_some() as u32;
+        if let Some(hit) = &sample.hit {
+            if first {
+                let material_id = materials.get(&hit.material);
+                self.aovs.record(i, sample, material_id);
+            }
+        } else if first {
+            self.aovs.record(i, sample, None);
+        }
+
+        let r = self.filter.radius();
+        let b = self.bounds;
+        for y in span(position.1, r, b.y, b.height) {
+            for x in span(position.0, r, b.x, b.width) {
+                let weight = self.filter.evaluate(x as Scalar + 0.5 - position.0, y as Scalar + 0.5 - position.1) as f32;
+                if weight != 0.0 {
+                    self.splats[(y - b.y) * b.width + (x - b.x)].add(radiance, coverage, weight);
+                }
+            }
+        }
+    }
+
+    /// Accumulates a tile that lies within this film, margin and all. Splats from neighbouring tiles sum exactly,
+    /// so the order tiles are merged in doesn't matter.
+    pub fn merge(&mut self, tile: &Film) {
+        for ty in 0..tile.rect.height {
+            for tx in 0..tile.rect.width {
+                let src = ty * tile.rect.width + tx;
+                let dst = (tile.rect.y - self.rect.y + ty) * self.rect.width + (tile.rect.x - self.rect.x + tx);
+                if self.stats[dst].count == 0 && tile.stats[src].count > 0 {
+                    self.aovs.copy_pixel(&tile.aovs, src, dst);
+                } else if tile.stats[src].count > 0 {
+                    self.aovs.merge_means(&tile.aovs, src, dst, self.stats[dst].count, tile.stats[src].count);
+                }
+                self.stats[dst].merge(&tile.stats[src]);
+                self.hits[dst] += tile.hits[src];
+            }
+        }
+        let (from, to) = (&tile.bounds, &self.bounds);
+        for ty in 0..from.height {
+            for tx in 0..from.width {
+                self.splats[(from.y - to.y + ty) * to.width + (from.x - to.x + tx)].merge(&tile.splats[ty * from.width + tx]);
+            }
+        }
+    }
+
+    /// Mean relative error over the pixels that have been sampled
+    pub fn noise(&self) -> f32 {
+        let (sum, n) = self.stats.iter()
+            .filter(|s| s.count > 0)
+            .fold((0.0f64, 0usize), |(sum, n), s| (sum + s.relative_error() as f64, n + 1));
+        if n == 0 {
+            0.0
+        } else {
+            (sum / n as f64) as f32
+        }
+    }
+
+    /// The filtered samples around each pixel, tagged with the color space they were rendered in
+    pub fn to_framebuffer(&self, color_space: ColorSpace) -> Framebuffer {
+        let mut fb = Framebuffer::new(self.rect.width, self.rect.height);
+        fb.color_space = color_space;
+        let mut alpha = vec![0.0; self.rect.area()];
+        for y in 0..self.rect.height {
+            for x in 0..self.rect.width {
+                let i = y * self.rect.width + x;
+                let splat = &self.splats[(self.rect.y - self.bounds.y + y) * self.bounds.width + (self.rect.x - self.bounds.x + x)];
+                // Negative lobes can all but cancel out the weights; fall back on the pixel's own samples
+                let min_weight = (MIN_WEIGHT * self.integral * self.stats[i].count as f32).max(0.0);
+                if splat.weight() > min_weight {
+                    let (color, coverage) = splat.mean();
+                    fb.pixels[i] = color;
+                    alpha[i] = coverage.clamp(0.0, 1.0);
+                } else if self.stats[i].count > 0 {
+                    fb.pixels[i] = self.stats[i].mean;
+                    alpha[i] = self.hits[i] as f32 / self.stats[i].count as f32;
+                }
+            }
+        }
+        fb.alpha = Some(alpha);
+        fb.aovs = self.aovs.clone();
+        if let Some(counts) = &mut fb.aovs.sample_count {
+            for (c, s) in counts.iter_mut().zip(self.stats.iter()) {
+                *c = s.count;
+            }
+        }
+        fb
+    }
+}
+
+/// The pixels along one axis whose centers lie within the filter radius of a sample, clipped to `start..start + len`
+fn span(position: Scalar, radius: Scalar, start: usize, len: usize) -> Range<usize> {
+    // Pixel x has its center at x + 0.5; the half-open bounds match the box filter's
+    let first = ((position - 0.5 - radius).floor() + 1.0).max(start as Scalar);
+    let last = ((position - 0.5 + radius).floor() + 1.0).min((start + len) as Scalar).max(first);
+    first as usize..last as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PixelStats, Film};
+    use crate::{Color3, AovSelection, ColorSpace};
+    use crate::filter::Filter;
+    use crate::math::*;
+    use crate::tile::Rect;
+    use std::sync::Arc as Shared;
+
+    #[test]
+    fn welford_matches_two_pass() {
+        let xs = [0.5, 2.0, 1.25, 0.0, 3.5];
+        let mut stats = PixelStats::new();
+        for &x in xs.iter() {
+            stats.add(Color3::new(x, 2.0 * x, 1.0));
+        }
+        let mean = xs.iter().sum::<f32>() / xs.len() as f32;
+        let var = xs.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / (xs.len() - 1) as f32;
+        assert!((stats.mean.r - mean).abs() <= 1.0e-5);
+        assert!((stats.variance().r - var).abs() <= 1.0e-5);
+        assert!((stats.variance().g - 4.0 * var).abs() <= 1.0e-4);
+        assert!(stats.variance().b.abs() <= 1.0e-6);
+    }
+
+    #[test]
+    fn merge_matches_sequential() {
+        let xs = [0.5, 2.0, 1.25, 0.0, 3.5, 7.0, 0.25];
+        let mut all = PixelStats::new();
+        let mut a = PixelStats::new();
+        let mut b = PixelStats::new();
+        for (i, &x) in xs.iter().enumerate() {
+            all.add(Color3::gray(x));
+            if i < 3 { a.add(Color3::gray(x)) } else { b.add(Color3::gray(x)) }
+        }
+        a.merge(&b);
+        assert_eq!(a.count, all.count);
+        assert!((a.mean.r - all.mean.r).abs() <= 1.0e-5);
+        assert!((a.variance().r - all.variance().r).abs() <= 1.0e-4);
+    }
+
+    #[test]
+    fn error_needs_two_samples() {
+        let mut stats = PixelStats::new();
+        stats.add(Color3::gray(1.0));
+        assert!(stats.relative_error().is_infinite());
+        stats.add(Color3::gray(1.0));
+        assert_eq!(stats.relative_error(), 0.0);
+    }
+
+    /// A box with a negative band either side of it
+    struct Ringing;
+    impl Filter for Ringing {
+        fn radius(&self) -> Scalar {
+            1.5
+        }
+
+        fn evaluate(&self, x: Scalar, y: Scalar) -> Scalar {
+            match (x.abs(), y.abs()) {
+                (x, y) if x <= 0.5 && y <= 0.5 => 1.0,
+                (_, y) if y <= 0.5 => -0.2495,
+                _ => 0.0,
+            }
+        }
+    }
+
+    #[test]
+    fn cancelled_weights_fall_back_on_the_mean() {
+        let rect = Rect::new(0, 0, 3, 1);
+        let filter: Shared<dyn Filter + Send + Sync> = Shared::new(Ringing);
+        let mut film = Film::new(rect, &rect, &AovSelection::none(), &filter);
+        // One sample in the first pixel, and four in the second that all but cancel its weight
+        film.stats[0].add(Color3::gray(1.0));
+        film.hits[0] = 1;
+        film.splats[0].add(Color3::gray(1.0), 1.0, 1.0);
+        film.splats[0].add(Color3::gray(2.0), 1.0, -0.998);
+        let fb = film.to_framebuffer(ColorSpace::LinearSrgb);
+        assert_eq!(fb.pixels[0].r, 1.0);
+        assert_eq!(fb.alpha.unwrap()[0], 1.0);
+    }
+}
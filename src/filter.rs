@@ -0,0 +1,263 @@
+use crate::math::*;
+
+/**
+ * How a sample is shared out between the pixels around it
+ *
+ * Each pixel is the weighted average of the samples within the filter's radius of its center, so wide filters
+ * reach across pixel boundaries and into neighbouring tiles. Weights need not be normalized, and filters with
+ * negative lobes sharpen the image at the cost of some ringing.
+ */
+pub trait Filter {
+    /// How far from a pixel's center samples count towards it, in pixels along each axis
+    fn radius(&self) -> Scalar;
+
+    /// Weight of a sample at offset (x, y) from a pixel's center, in pixels. Zero outside the radius.
+    fn evaluate(&self, x: Scalar, y: Scalar) -> Scalar;
+
+    /// The weights integrated over the square the radius covers, with the midpoint rule. With one sample per pixel
+    /// spread evenly, each pixel gathers about this much weight.
+    fn integral(&self) -> Scalar {
+        let steps = 64;
+        let r = self.radius();
+        let step = 2.0 * r / steps as Scalar;
+        let at = |i: usize| -r + (i as Scalar + 0.5) * step;
+        let sum: Scalar = (0..steps).flat_map(|j| (0..steps).map(move |i| self.evaluate(at(i), at(j)))).sum();
+        sum * step * step
+    }
+}
+
+/// Weighs every sample within the radius the same. With the default radius of half a pixel, each sample only
+/// counts towards the pixel it was taken in.
+#[derive(Debug, Copy, Clone)]
+pub struct BoxFilter {
+    pub radius: Scalar,
+}
+impl BoxFilter {
+    pub fn new(radius: Scalar) -> Self {
+        BoxFilter { radius }
+    }
+}
+impl Default for BoxFilter {
+    fn default() -> Self {
+        BoxFilter::new(0.5)
+    }
+}
+impl Filter for BoxFilter {
+    fn radius(&self) -> Scalar {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Scalar, y: Scalar) -> Scalar {
+        // Half-open, so a sample on the border between two pixels only counts towards one
+        let inside = |d: Scalar| -self.radius < d && d <= self.radius;
+        if inside(x) && inside(y) { 1.0 } else { 0.0 }
+    }
+}
+
+/// Falls off linearly from the center, for a slightly softer image than the box
+#[derive(Debug, Copy, Clone)]
+pub struct TentFilter {
+    pub radius: Scalar,
+}
+impl TentFilter {
+    pub fn new(radius: Scalar) -> Self {
+        TentFilter { radius }
+    }
+}
+impl Default for TentFilter {
+    fn default() -> Self {
+        TentFilter::new(1.0)
+    }
+}
+impl Filter for TentFilter {
+    fn radius(&self) -> Scalar {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Scalar, y: Scalar) -> Scalar {
+        let tent = |d: Scalar| (1.0 - d.abs() / self.radius).max(0.0);
+        tent(x) * tent(y)
+    }
+}
+
+/// A Gaussian, shifted down so it reaches zero at the radius
+#[derive(Debug, Copy, Clone)]
+pub struct GaussianFilter {
+    pub radius: Scalar,
+    /// Standard deviation, in pixels
+    pub sigma: Scalar,
+}
+impl GaussianFilter {
+    pub fn new(radius: Scalar, sigma: Scalar) -> Self {
+        GaussianFilter { radius, sigma }
+    }
+
+    fn gaussian(&self, d: Scalar) -> Scalar {
+        (-d * d / (2.0 * self.sigma * self.sigma)).exp()
+    }
+}
+impl Default for GaussianFilter {
+    fn default() -> Self {
+        GaussianFilter::new(1.5, 0.5)
+    }
+}
+impl Filter for GaussianFilter {
+    fn radius(&self) -> Scalar {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Scalar, y: Scalar) -> Scalar {
+        let edge = self.gaussian(self.radius);
+        let g = |d: Scalar| (self.gaussian(d) - edge).max(0.0);
+        g(x) * g(y)
+    }
+}
+
+/**
+ * The cubic family of Mitchell and Netravali's "Reconstruction Filters in Computer Graphics"
+ *
+ * `b` blurs and `c` rings. Their recommended B = C = 1/3 is the default; B = 0, C = 0.5 gives the sharper
+ * Catmull-Rom spline.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct MitchellFilter {
+    pub radius: Scalar,
+    pub b: Scalar,
+    pub c: Scalar,
+}
+impl MitchellFilter {
+    pub fn new(radius: Scalar, b: Scalar, c: Scalar) -> Self {
+        MitchellFilter { radius, b, c }
+    }
+
+    /// The cubic over [-2, 2]
+    fn cubic(&self, x: Scalar) -> Scalar {
+        let (b, c) = (self.b, self.c);
+        let x = x.abs();
+        let p = if x < 1.0 {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+        } else if x < 2.0 {
+            (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+        } else {
+            0.0
+        };
+        p / 6.0
+    }
+}
+impl Default for MitchellFilter {
+    fn default() -> Self {
+        MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)
+    }
+}
+impl Filter for MitchellFilter {
+    fn radius(&self) -> Scalar {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Scalar, y: Scalar) -> Scalar {
+        let scale = 2.0 / self.radius;
+        self.cubic(x * scale) * self.cubic(y * scale)
+    }
+}
+
+/// A sinc windowed by a wider sinc, with as many lobes on each side as its radius in pixels. The sharpest of the
+/// filters, and the one that rings most.
+#[derive(Debug, Copy, Clone)]
+pub struct LanczosFilter {
+    pub radius: Scalar,
+}
+impl LanczosFilter {
+    pub fn new(radius: Scalar) -> Self {
+        LanczosFilter { radius }
+    }
+
+    fn windowed_sinc(&self, x: Scalar) -> Scalar {
+        if x.abs() >= self.radius {
+            0.0
+        } else {
+            sinc(x) * sinc(x / self.radius)
+        }
+    }
+}
+impl Default for LanczosFilter {
+    fn default() -> Self {
+        LanczosFilter::new(3.0)
+    }
+}
+impl Filter for LanczosFilter {
+    fn radius(&self) -> Scalar {
+        self.radius
+    }
+
+    fn evaluate(&self, x: Scalar, y: Scalar) -> Scalar {
+        self.windowed_sinc(x) * self.windowed_sinc(y)
+    }
+}
+
+/// The normalized sinc, sin(pi x) / (pi x)
+fn sinc(x: Scalar) -> Scalar {
+    if x.abs() < 1.0e-5 {
+        return 1.0;
+    }
+    let x = consts::PI * x;
+    x.sin() / x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc as Shared;
+
+    fn filters() -> Vec<Shared<dyn Filter + Send + Sync>> {
+        vec![
+            Shared::new(BoxFilter::new(1.0)),
+            Shared::new(TentFilter::default()),
+            Shared::new(GaussianFilter::default()),
+            Shared::new(MitchellFilter::default()),
+            Shared::new(LanczosFilter::default()),
+        ]
+    }
+
+    #[test]
+    fn filters_peak_at_the_center_and_vanish_at_the_radius() {
+        for filter in filters() {
+            let r = filter.radius();
+            let center = filter.evaluate(0.0, 0.0);
+            assert!(center > 0.0);
+            for &d in [0.1, 0.3, 0.7, r * 0.9].iter() {
+                assert!(filter.evaluate(d, 0.0) <= center);
+                assert_eq!(filter.evaluate(d, 0.0), filter.evaluate(-d, 0.0));
+                assert_eq!(filter.evaluate(d, 0.2), filter.evaluate(0.2, d));
+            }
+            assert_eq!(filter.evaluate(r + 0.01, 0.0), 0.0);
+            assert_eq!(filter.evaluate(0.0, r + 0.1), 0.0);
+        }
+    }
+
+    #[test]
+    fn mitchell_is_normalized_and_rings() {
+        let filter = MitchellFilter::default();
+        let steps = 400;
+        let integral: Scalar = (0..steps)
+            .map(|i| -2.0 + 4.0 * (i as Scalar + 0.5) / steps as Scalar)
+            .map(|x| filter.cubic(x) * 4.0 / steps as Scalar)
+            .sum();
+        assert!((integral - 1.0).abs() <= 1.0e-3, "{}", integral);
+        assert!(filter.evaluate(1.5, 0.0) < 0.0);
+        assert!(LanczosFilter::default().evaluate(1.5, 0.0) < 0.0);
+    }
+
+    #[test]
+    fn integrals_of_normalized_filters_are_one() {
+        for integral in [BoxFilter::default().integral(), TentFilter::default().integral(), MitchellFilter::default().integral()].iter() {
+            assert!((integral - 1.0).abs() <= 1.0e-2, "{}", integral);
+        }
+    }
+
+    #[test]
+    fn box_counts_each_sample_once() {
+        let filter = BoxFilter::default();
+        // A sample on the border between two pixels
+        assert_eq!(filter.evaluate(-0.5, 0.0) + filter.evaluate(0.5, 0.0), 1.0);
+    }
+}
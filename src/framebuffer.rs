@@ -0,0 +1,66 @@
+use crate::{Color3, AovBuffers, ColorSpace};
+use std::borrow::Cow;
+
+/// A linear, floating-point image stored in scanline order, top row first
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    /// Premultiplied by alpha, i.e. composited over black
+    pub pixels: Vec<Color3>,
+    /// Coverage in [0, 1]: how much of each pixel was covered by geometry
+    pub alpha: Option<Vec<f32>>,
+    pub aovs: AovBuffers,
+    /// The space the pixels are in. Only the color is converted; AOVs are left alone.
+    pub color_space: ColorSpace,
+}
+impl Framebuffer {
+    /// A black image of the given size
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color3::gray(0.0); width * height],
+            alpha: None,
+            aovs: AovBuffers::default(),
+            color_space: ColorSpace::LinearSrgb,
+        }
+    }
+
+    /// Converts the pixels into another color space
+    pub fn convert(&mut self, to: ColorSpace) {
+        if self.color_space == to {
+            return;
+        }
+        let m = self.color_space.conversion(to);
+        for p in self.pixels.iter_mut() {
+            *p = crate::colorspace::transform(&m, *p);
+        }
+        self.color_space = to;
+    }
+
+    /// The image in another color space, copied only if it needs converting
+    pub fn in_color_space(&self, to: ColorSpace) -> Cow<'_, Framebuffer> {
+        if self.color_space == to {
+            Cow::Borrowed(self)
+        } else {
+            let mut fb = self.clone();
+            fb.convert(to);
+            Cow::Owned(fb)
+        }
+    }
+
+    #[inline]
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> Color3 {
+        self.pixels[self.index(x, y)]
+    }
+    #[inline]
+    pub fn set(&mut self, x: usize, y: usize, c: Color3) {
+        let i = self.index(x, y);
+        self.pixels[i] = c;
+    }
+}
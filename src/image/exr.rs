@@ -0,0 +1,422 @@
+//! Uncompressed, single-part scanline OpenEXR output
+
+use crate::math::*;
+use crate::{Color3, Framebuffer};
+use super::invalid_input;
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+const MAGIC: u32 = 20000630;
+const VERSION: u32 = 2; // single-part scanline, no long names
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PixelType {
+    /// 16-bit IEEE half floats
+    Half,
+    /// 32-bit IEEE floats
+    Float,
+}
+impl PixelType {
+    fn id(self) -> i32 {
+        match self {
+            PixelType::Half => 1,
+            PixelType::Float => 2,
+        }
+    }
+    fn size(self) -> usize {
+        match self {
+            PixelType::Half => 2,
+            PixelType::Float => 4,
+        }
+    }
+}
+
+/// Per-pixel data for an extra layer. Must contain exactly width * height values.
+#[derive(Debug, Copy, Clone)]
+pub enum LayerData<'a> {
+    /// A single channel, written as `<name>.Y`
+    Gray(&'a [f32]),
+    /// Three channels, written as `<name>.R`, `<name>.G` and `<name>.B`
+    Rgb(&'a [Color3]),
+    /// Three channels, written as `<name>.X`, `<name>.Y` and `<name>.Z`
+    Xyz(&'a [Vec3]),
+    /// Two channels, written as `<name>.U` and `<name>.V`
+    Uv(&'a [Vec2]),
+    /// A single 32-bit unsigned integer channel, written as `<name>.id` regardless of the pixel type
+    Id(&'a [u32]),
+}
+
+/// An additional named layer (AOV) stored alongside the beauty image
+#[derive(Debug, Clone)]
+pub struct Layer<'a> {
+    pub name: String,
+    pub data: LayerData<'a>,
+}
+impl<'a> Layer<'a> {
+    pub fn new(name: &str, data: LayerData<'a>) -> Self {
+        Layer {
+            name: name.to_owned(),
+            data,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExrOptions<'a> {
+    pub pixel_type: PixelType,
+    /// Written as the A channel when present
+    pub alpha: Option<&'a [f32]>,
+    pub layers: Vec<Layer<'a>>,
+}
+impl<'a> ExrOptions<'a> {
+    pub fn new(pixel_type: PixelType) -> Self {
+        ExrOptions {
+            pixel_type,
+            alpha: None,
+            layers: vec![],
+        }
+    }
+}
+
+/// Half-precision RGB with no extra layers
+impl<'a> Default for ExrOptions<'a> {
+    fn default() -> Self {
+        ExrOptions::new(PixelType::Half)
+    }
+}
+
+pub fn write_exr<P>(path: P, fb: &Framebuffer, options: &ExrOptions) -> io::Result<()>
+    where P: AsRef<Path> {
+    let mut w = BufWriter::new(File::create(path)?);
+    encode_exr(&mut w, fb, options)?;
+    w.flush()
+}
+
+/// A single output channel: its full name and where to read its values from
+struct Channel<'a> {
+    name: String,
+    source: Source<'a>,
+}
+impl<'a> Channel<'a> {
+    fn new(name: String, source: Source<'a>) -> Self {
+        Channel {
+            name,
+            source,
+        }
+    }
+}
+#[derive(Copy, Clone)]
+enum Source<'a> {
+    Scalar(&'a [f32]),
+    Red(&'a [Color3]),
+    Green(&'a [Color3]),
+    Blue(&'a [Color3]),
+    Vector(&'a [Vec3], usize),
+    Uv(&'a [Vec2], usize),
+    Id(&'a [u32]),
+}
+impl<'a> Source<'a> {
+    fn pixel_type(&self, default: PixelType) -> Option<PixelType> {
+        match self {
+            Source::Id(_) => None,
+            _ => Some(default),
+        }
+    }
+    #[inline]
+    fn write(&self, i: usize, pixel_type: PixelType, out: &mut Vec<u8>) {
+        let v = match self {
+            Source::Scalar(v) => v[i],
+            Source::Red(v) => v[i].r,
+            Source::Green(v) => v[i].g,
+            Source::Blue(v) => v[i].b,
+            Source::Vector(v, axis) => v[i][*axis] as f32,
+            Source::Uv(v, axis) => v[i][*axis] as f32,
+            Source::Id(v) => {
+                out.extend_from_slice(&v[i].to_le_bytes());
+                return;
+            }
+        };
+        match pixel_type {
+            PixelType::Half => out.extend_from_slice(&f32_to_f16(v).to_le_bytes()),
+            PixelType::Float => out.extend_from_slice(&v.to_le_bytes()),
+        }
+    }
+}
+
+/// The EXR type id and size in bytes of a channel. `None` means 32-bit unsigned integer.
+fn channel_type(pixel_type: Option<PixelType>) -> (i32, usize) {
+    match pixel_type {
+        Some(t) => (t.id(), t.size()),
+        None => (0, 4),
+    }
+}
+
+fn rgb_channels<'a>(prefix: &str, data: &'a [Color3]) -> Vec<Channel<'a>> {
+    vec![
+        Channel::new(format!("{}R", prefix), Source::Red(data)),
+        Channel::new(format!("{}G", prefix), Source::Green(data)),
+        Channel::new(format!("{}B", prefix), Source::Blue(data)),
+    ]
+}
+
+pub fn encode_exr<W: Write>(w: &mut W, fb: &Framebuffer, options: &ExrOptions) -> io::Result<()> {
+    let count = fb.width * fb.height;
+    if fb.pixels.len() != count {
+        return Err(invalid_input(format!("framebuffer has {} pixels, expected {}", fb.pixels.len(), count)));
+    }
+
+    let mut channels = rgb_channels("", &fb.pixels);
+    if let Some(alpha) = options.alpha {
+        if alpha.len() != count {
+            return Err(invalid_input(format!("alpha has {} values, expected {}", alpha.len(), count)));
+        }
+        channels.push(Channel::new("A".to_owned(), Source::Scalar(alpha)));
+    }
+    for layer in options.layers.iter() {
+        let len = match layer.data {
+            LayerData::Gray(v) => {
+                channels.push(Channel::new(format!("{}.Y", layer.name), Source::Scalar(v)));
+                v.len()
+            }
+            LayerData::Rgb(v) => {
+                channels.extend(rgb_channels(&format!("{}.", layer.name), v));
+                v.len()
+            }
+            LayerData::Xyz(v) => {
+                for (axis, suffix) in ["X", "Y", "Z"].iter().enumerate() {
+                    channels.push(Channel::new(format!("{}.{}", layer.name, suffix), Source::Vector(v, axis)));
+                }
+                v.len()
+            }
+            LayerData::Uv(v) => {
+                for (axis, suffix) in ["U", "V"].iter().enumerate() {
+                    channels.push(Channel::new(format!("{}.{}", layer.name, suffix), Source::Uv(v, axis)));
+                }
+                v.len()
+            }
+            LayerData::Id(v) => {
+                channels.push(Channel::new(format!("{}.id", layer.name), Source::Id(v)));
+                v.len()
+            }
+        };
+        if len != count {
+            return Err(invalid_input(format!("layer '{}' has {} values, expected {}", layer.name, len, count)));
+        }
+    }
+    // The channel list, and the order of channel data within each scanline, is sorted by name
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    for pair in channels.windows(2) {
+        if pair[0].name == pair[1].name {
+            return Err(invalid_input(format!("duplicate channel '{}'", pair[0].name)));
+        }
+    }
+
+    let header = header(fb, &channels, options.pixel_type);
+    w.write_all(&header)?;
+
+    // Offset table: with no compression every chunk is a single scanline of fixed size
+    let line_size = fb.width * channels.iter()
+        .map(|c| channel_type(c.source.pixel_type(options.pixel_type)).1)
+        .sum::<usize>();
+    let chunk_size = 8 + line_size;
+    let table_end = header.len() + 8 * fb.height;
+    for y in 0..fb.height {
+        w.write_all(&((table_end + y * chunk_size) as u64).to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(chunk_size);
+    for y in 0..fb.height {
+        line.clear();
+        line.extend_from_slice(&(y as i32).to_le_bytes());
+        line.extend_from_slice(&(line_size as i32).to_le_bytes());
+        let row = y * fb.width;
+        for c in channels.iter() {
+            for x in 0..fb.width {
+                c.source.write(row + x, options.pixel_type, &mut line);
+            }
+        }
+        w.write_all(&line)?;
+    }
+
+    Ok(())
+}
+
+fn header(fb: &Framebuffer, channels: &[Channel], pixel_type: PixelType) -> Vec<u8> {
+    let mut h = Vec::new();
+    h.extend_from_slice(&MAGIC.to_le_bytes());
+    h.extend_from_slice(&VERSION.to_le_bytes());
+
+    let mut chlist = Vec::new();
+    for c in channels {
+        chlist.extend_from_slice(c.name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&channel_type(c.source.pixel_type(pixel_type)).0.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear, reserved
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+        chlist.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+    }
+    chlist.push(0);
+    attribute(&mut h, "channels", "chlist", &chlist);
+
+    // Readers assume sRGB primaries without this, so always say which space the pixels are in
+    let c = fb.color_space.chromaticities();
+    let mut chromaticities = Vec::new();
+    for &(x, y) in [c.red, c.green, c.blue, c.white].iter() {
+        chromaticities.extend_from_slice(&x.to_le_bytes());
+        chromaticities.extend_from_slice(&y.to_le_bytes());
+    }
+    attribute(&mut h, "chromaticities", "chromaticities", &chromaticities);
+
+    attribute(&mut h, "compression", "compression", &[0]); // NO_COMPRESSION
+
+    let mut window = Vec::new();
+    for &v in [0, 0, fb.width as i32 - 1, fb.height as i32 - 1].iter() {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+    attribute(&mut h, "dataWindow", "box2i", &window);
+    attribute(&mut h, "displayWindow", "box2i", &window);
+
+    attribute(&mut h, "lineOrder", "lineOrder", &[0]); // INCREASING_Y
+    attribute(&mut h, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute(&mut h, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut h, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    h.push(0);
+    h
+}
+
+fn attribute(h: &mut Vec<u8>, name: &str, ty: &str, value: &[u8]) {
+    h.extend_from_slice(name.as_bytes());
+    h.push(0);
+    h.extend_from_slice(ty.as_bytes());
+    h.push(0);
+    h.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    h.extend_from_slice(value);
+}
+
+/// Converts to IEEE half precision, rounding to nearest even
+pub fn f32_to_f16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mant = bits & 0x7f_ffff;
+
+    if exp == 0xff {
+        // Infinity stays infinity, NaN stays NaN
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if e <= 0 {
+        // Subnormal half, or too small to represent
+        if e < -10 {
+            return sign;
+        }
+        let m = mant | 0x80_0000;
+        let shift = (14 - e) as u32;
+        let mut h = m >> shift;
+        let rem = m & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if rem > halfway || (rem == halfway && (h & 1) != 0) {
+            h += 1;
+        }
+        return sign | h as u16;
+    }
+
+    let mut h = ((e as u32) << 10) | (mant >> 13);
+    let rem = mant & 0x1fff;
+    // A carry out of the mantissa correctly bumps the exponent, up to infinity
+    if rem > 0x1000 || (rem == 0x1000 && (h & 1) != 0) {
+        h += 1;
+    }
+    sign | h as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_i32(b: &[u8], at: usize) -> i32 {
+        let mut a = [0; 4];
+        a.copy_from_slice(&b[at..at + 4]);
+        i32::from_le_bytes(a)
+    }
+
+    #[test]
+    fn half_conversion() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1.0e6), 0x7c00);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NAN) & 0x7c00, 0x7c00);
+        assert_eq!(f32_to_f16(5.960_464_5e-8), 0x0001); // smallest subnormal
+        assert_eq!(f32_to_f16(1.0e-10), 0x0000);
+        assert_eq!(f32_to_f16(0.333_333_34), 0x3555);
+    }
+
+    #[test]
+    fn file_layout() {
+        let mut fb = Framebuffer::new(3, 2);
+        fb.set(1, 1, Color3::new(0.25, 0.5, 2.0));
+        let alpha = vec![1.0; 6];
+        let depth = vec![7.0; 6];
+        let mut options = ExrOptions::new(PixelType::Float);
+        options.alpha = Some(&alpha);
+        options.layers.push(Layer::new("depth", LayerData::Gray(&depth)));
+
+        let mut bytes = Vec::new();
+        encode_exr(&mut bytes, &fb, &options).unwrap();
+        assert_eq!(read_i32(&bytes, 0) as u32, MAGIC);
+        let tag = b"chromaticities\0chromaticities\0";
+        let at = bytes.windows(tag.len()).position(|w| w == tag).unwrap() + tag.len();
+        assert_eq!(read_i32(&bytes, at), 32);
+        assert_eq!(f32::from_bits(read_i32(&bytes, at + 4) as u32), 0.64);
+
+        // Two scanlines of A, B, G, R, depth.Y for 3 pixels each, preceded by their offset table
+        let offsets_at = bytes.len() - 2 * (8 + 3 * 5 * 4) - 2 * 8;
+        let first = read_i32(&bytes, offsets_at) as usize;
+        assert_eq!(first, offsets_at + 16);
+
+        // Second scanline: y, size, then the channels in sorted order
+        let second = read_i32(&bytes, offsets_at + 8) as usize;
+        assert_eq!(read_i32(&bytes, second), 1);
+        assert_eq!(read_i32(&bytes, second + 4), 3 * 5 * 4);
+        let value = |channel: usize, x: usize| {
+            f32::from_bits(read_i32(&bytes, second + 8 + (channel * 3 + x) * 4) as u32)
+        };
+        assert_eq!(value(0, 1), 1.0);
+        assert_eq!(value(1, 1), 2.0);
+        assert_eq!(value(2, 1), 0.5);
+        assert_eq!(value(3, 1), 0.25);
+        assert_eq!(value(3, 0), 0.0);
+        assert_eq!(value(4, 2), 7.0);
+    }
+
+    #[test]
+    fn id_layers_are_uint() {
+        let fb = Framebuffer::new(2, 1);
+        let ids = vec![3, 0x0102_0304];
+        let mut options = ExrOptions::new(PixelType::Half);
+        options.layers.push(Layer::new("object", LayerData::Id(&ids)));
+
+        let mut bytes = Vec::new();
+        encode_exr(&mut bytes, &fb, &options).unwrap();
+        // The only scanline is B, G, R as halves then object.id as u32s
+        let line_size = 2 * 3 * 2 + 2 * 4;
+        assert_eq!(read_i32(&bytes, bytes.len() - line_size - 4) as usize, line_size);
+        assert_eq!(read_i32(&bytes, bytes.len() - 8), 3);
+        assert_eq!(read_i32(&bytes, bytes.len() - 4), 0x0102_0304);
+    }
+
+    #[test]
+    fn rejects_mismatched_layers() {
+        let fb = Framebuffer::new(2, 2);
+        let short = vec![0.0; 3];
+        let mut options = ExrOptions::default();
+        options.layers.push(Layer::new("depth", LayerData::Gray(&short)));
+        assert!(encode_exr(&mut Vec::new(), &fb, &options).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+//! Radiance RGBE (.hdr) input and output
+
+use crate::{Color3, Framebuffer, ColorSpace};
+use super::{check_size, invalid_data};
+use std::{fs::File, io::{self, BufRead, BufReader, BufWriter, Read, Write}, path::Path};
+
+pub fn write_hdr<P>(path: P, fb: &Framebuffer) -> io::Result<()>
+    where P: AsRef<Path> {
+    let mut w = BufWriter::new(File::create(path)?);
+    encode_hdr(&mut w, fb)?;
+    w.flush()
+}
+
+/// Writes new-style run-length encoded scanlines, or flat pixels for widths that format can't represent.
+/// Colors are stored as linear sRGB.
+pub fn encode_hdr<W: Write>(w: &mut W, fb: &Framebuffer) -> io::Result<()> {
+    check_size(fb)?;
+    let fb = fb.in_color_space(ColorSpace::LinearSrgb);
+    write!(w, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", fb.height, fb.width)?;
+
+    let rle = fb.width >= 8 && fb.width <= 0x7fff;
+    let mut line = Vec::with_capacity(fb.width * 4);
+    let mut component = Vec::with_capacity(fb.width);
+    for row in fb.pixels.chunks(fb.width.max(1)) {
+        line.clear();
+        if rle {
+            line.extend_from_slice(&[2, 2, (fb.width >> 8) as u8, (fb.width & 0xff) as u8]);
+            let rgbe: Vec<[u8; 4]> = row.iter().map(|&c| to_rgbe(c)).collect();
+            for i in 0..4 {
+                component.clear();
+                component.extend(rgbe.iter().map(|p| p[i]));
+                encode_rle(&mut line, &component);
+            }
+        } else {
+            for &c in row {
+                line.extend_from_slice(&to_rgbe(c));
+            }
+        }
+        w.write_all(&line)?;
+    }
+
+    Ok(())
+}
+
+pub fn read_hdr<P>(path: P) -> io::Result<Framebuffer>
+    where P: AsRef<Path> {
+    decode_hdr(&mut BufReader::new(File::open(path)?))
+}
+
+/// Largest image `decode_hdr` accepts, at 12 bytes a pixel once decoded
+const MAX_PIXELS: usize = 1 << 28;
+
+/// Reads an RGBE image with the usual `-Y height +X width` orientation, in flat or new-style run-length encoded scanlines
+pub fn decode_hdr<R: BufRead>(r: &mut R) -> io::Result<Framebuffer> {
+    let mut line = String::new();
+    r.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid_data("not a Radiance HDR file".to_string()));
+    }
+    // Header variables, up to an empty line
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Err(invalid_data("HDR header ends early".to_string()));
+        }
+        let l = line.trim();
+        if l.is_empty() {
+            break;
+        }
+        if l.starts_with("FORMAT=") && l != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data(format!("unsupported HDR {}", l)));
+        }
+    }
+    line.clear();
+    r.read_line(&mut line)?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match fields.as_slice() {
+        ["-Y", h, "+X", w] => match (h.parse::<usize>(), w.parse::<usize>()) {
+            (Ok(h), Ok(w)) => (h, w),
+            _ => return Err(invalid_data(format!("bad HDR resolution {:?}", line.trim()))),
+        },
+        _ => return Err(invalid_data(format!("unsupported HDR orientation {:?}", line.trim()))),
+    };
+
+    if width == 0 || height == 0 {
+        return Err(invalid_data(format!("empty HDR image: {} x {}", width, height)));
+    }
+    match width.checked_mul(height) {
+        Some(n) if n <= MAX_PIXELS => {}
+        _ => return Err(invalid_data(format!("HDR image too large: {} x {}", width, height))),
+    }
+
+    // Grown as scanlines arrive, so a header that claims more rows than the file holds fails before it allocates them
+    let mut fb = Framebuffer::new(0, 0);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_scanline(r, &mut scanline)?;
+        fb.pixels.extend(scanline.iter().map(|&p| from_rgbe(p)));
+    }
+    fb.width = width;
+    fb.height = height;
+    Ok(fb)
+}
+
+fn read_scanline<R: Read>(r: &mut R, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    if width == 0 {
+        return Ok(());
+    }
+    let mut first = [0u8; 4];
+    r.read_exact(&mut first)?;
+    let rle = first[0] == 2 && first[1] == 2 && first[2] < 128;
+    if !rle || !(8..=0x7fff).contains(&width) {
+        scanline[0] = first;
+        for p in scanline[1..].iter_mut() {
+            r.read_exact(p)?;
+        }
+        return Ok(());
+    }
+    if ((first[2] as usize) << 8 | first[3] as usize) != width {
+        return Err(invalid_data("HDR scanline width mismatch".to_string()));
+    }
+    // Each component is stored separately, as runs and literal dumps
+    for i in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut code = [0u8; 2];
+            r.read_exact(&mut code)?;
+            let count = code[0] as usize;
+            if count > 128 {
+                let count = count - 128;
+                if x + count > width {
+                    return Err(invalid_data("HDR run overflows scanline".to_string()));
+                }
+                for p in scanline[x..x + count].iter_mut() {
+                    p[i] = code[1];
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("bad HDR literal run".to_string()));
+                }
+                scanline[x][i] = code[1];
+                for p in scanline[x + 1..x + count].iter_mut() {
+                    let mut b = [0u8];
+                    r.read_exact(&mut b)?;
+                    p[i] = b[0];
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Just below 2^127, the largest value whose exponent still fits in the E byte
+const MAX_RGBE: f32 = 1.7e38;
+
+/// Shared-exponent encoding; negative components are clamped to zero
+pub fn to_rgbe(c: Color3) -> [u8; 4] {
+    // NaN and negative components become 0, and anything past the largest exponent saturates
+    let clamp = |x: f32| if x.is_nan() { 0.0 } else { x.clamp(0.0, MAX_RGBE) };
+    let c = Color3::new(clamp(c.r), clamp(c.g), clamp(c.b));
+    let v = c.r.max(c.g).max(c.b);
+    if v < 1.0e-32 {
+        return [0, 0, 0, 0];
+    }
+    let (m, e) = frexp(v);
+    let scale = m * 256.0 / v;
+    [
+        (c.r * scale) as u8,
+        (c.g * scale) as u8,
+        (c.b * scale) as u8,
+        (e + 128) as u8,
+    ]
+}
+
+pub fn from_rgbe(p: [u8; 4]) -> Color3 {
+    if p[3] == 0 {
+        return Color3::gray(0.0);
+    }
+    let f = 2.0f32.powi(p[3] as i32 - (128 + 8));
+    Color3::new(
+        (p[0] as f32 + 0.5) * f,
+        (p[1] as f32 + 0.5) * f,
+        (p[2] as f32 + 0.5) * f,
+    )
+}
+
+/// Splits a positive, finite x into a mantissa in [0.5, 1) and a power of two
+fn frexp(x: f32) -> (f32, i32) {
+    let bits = x.to_bits();
+    let exp = ((bits >> 23) & 0xff) as i32;
+    if exp == 0 {
+        // Subnormal: normalize first
+        let (m, e) = frexp(x * 2.0f32.powi(64));
+        return (m, e - 64);
+    }
+    let m = f32::from_bits((bits & 0x807f_ffff) | (126 << 23));
+    (m, exp - 126)
+}
+
+/// Encodes one component of a scanline as runs (for 4 or more equal bytes) and literal dumps
+fn encode_rle(out: &mut Vec<u8>, data: &[u8]) {
+    const MIN_RUN: usize = 4;
+    let n = data.len();
+    let mut cur = 0;
+    while cur < n {
+        // Find the start of the next run long enough to be worth encoding
+        let mut beg_run = cur;
+        let mut run_count = 0;
+        let mut old_run_count = 0;
+        while run_count < MIN_RUN && beg_run < n {
+            beg_run += run_count;
+            old_run_count = run_count;
+            run_count = 1;
+            while beg_run + run_count < n && run_count < 127 && data[beg_run] == data[beg_run + run_count] {
+                run_count += 1;
+            }
+        }
+        // A short run right before the long one is still cheaper as a run
+        if old_run_count > 1 && old_run_count == beg_run - cur {
+            out.push((128 + old_run_count) as u8);
+            out.push(data[cur]);
+            cur = beg_run;
+        }
+        while cur < beg_run {
+            let dump = (beg_run - cur).min(128);
+            out.push(dump as u8);
+            out.extend_from_slice(&data[cur..cur + dump]);
+            cur += dump;
+        }
+        if run_count >= MIN_RUN {
+            out.push((128 + run_count) as u8);
+            out.push(data[beg_run]);
+            cur += run_count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_rle(data: &[u8], n: usize) -> (Vec<u8>, usize) {
+        let mut out = Vec::new();
+        let mut i = 0;
+        while out.len() < n {
+            let count = data[i] as usize;
+            if count > 128 {
+                out.resize(out.len() + count - 128, data[i + 1]);
+                i += 2;
+            } else {
+                out.extend_from_slice(&data[i + 1..i + 1 + count]);
+                i += 1 + count;
+            }
+        }
+        (out, i)
+    }
+
+    #[test]
+    fn rgbe_roundtrip() {
+        for &c in [Color3::new(1.0, 0.5, 0.25), Color3::new(1000.0, 3.0, 0.0), Color3::gray(1.0e-3)].iter() {
+            let d = from_rgbe(to_rgbe(c));
+            let max = c.r.max(c.g).max(c.b);
+            assert!((d.r - c.r).abs() <= max / 128.0, "{:?} {:?}", c, d);
+            assert!((d.g - c.g).abs() <= max / 128.0, "{:?} {:?}", c, d);
+            assert!((d.b - c.b).abs() <= max / 128.0, "{:?} {:?}", c, d);
+        }
+        assert_eq!(to_rgbe(Color3::gray(0.0)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(Color3::gray(1.0)), [128, 128, 128, 129]);
+        // Out-of-range components saturate instead of wrapping the exponent
+        let saturated = to_rgbe(Color3::new(f32::INFINITY, f32::NAN, f32::MAX));
+        assert_eq!((saturated[1], saturated[3]), (0, 255));
+        assert!(from_rgbe(saturated).r.is_finite() && from_rgbe(saturated).r > 1.0e38);
+    }
+
+    #[test]
+    fn rle_roundtrip() {
+        let mut data = vec![1, 2, 3, 3, 3, 3, 3, 3, 4, 4, 5];
+        data.resize(data.len() + 300, 9);
+        data.extend((0..200).map(|i| i as u8));
+        let mut encoded = Vec::new();
+        encode_rle(&mut encoded, &data);
+        let (decoded, used) = decode_rle(&encoded, data.len());
+        assert_eq!(decoded, data);
+        assert_eq!(used, encoded.len());
+    }
+
+    #[test]
+    fn decode_roundtrip() {
+        for &width in [3, 40].iter() {
+            let mut fb = Framebuffer::new(width, 3);
+            for (i, p) in fb.pixels.iter_mut().enumerate() {
+                *p = if i % 7 < 4 { Color3::new(2.0, 0.5, 0.25) } else { Color3::gray(i as f32) };
+            }
+            let mut bytes = Vec::new();
+            encode_hdr(&mut bytes, &fb).unwrap();
+            let decoded = decode_hdr(&mut &bytes[..]).unwrap();
+            assert_eq!((decoded.width, decoded.height), (width, 3));
+            for (a, b) in fb.pixels.iter().zip(decoded.pixels.iter()) {
+                let expected = from_rgbe(to_rgbe(*a));
+                assert_eq!((expected.r, expected.g, expected.b), (b.r, b.g, b.b));
+            }
+        }
+        assert!(decode_hdr(&mut &b"P6\n"[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_impossible_sizes() {
+        let header = |size: &str| format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", size).into_bytes();
+        for size in ["-Y 18446744073709551615 +X 2", "-Y 1000000 +X 1000000"].iter() {
+            let err = decode_hdr(&mut &header(size)[..]).unwrap_err();
+            assert!(err.to_string().contains("too large"), "{}", err);
+        }
+        // Would read nothing for every one of its rows
+        for size in ["-Y 18446744073709551615 +X 0", "-Y 0 +X 4"].iter() {
+            let err = decode_hdr(&mut &header(size)[..]).unwrap_err();
+            assert!(err.kind() == std::io::ErrorKind::InvalidData && err.to_string().contains("empty"), "{}", err);
+        }
+        // Within the limit, but with no pixel data behind it
+        let err = decode_hdr(&mut &header("-Y 10000 +X 10000")[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn scanline_header() {
+        let fb = Framebuffer::new(16, 2);
+        let mut bytes = Vec::new();
+        encode_hdr(&mut bytes, &fb).unwrap();
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 16\n";
+        assert_eq!(&bytes[..header.len()], &header[..]);
+        assert_eq!(&bytes[header.len()..header.len() + 4], &[2, 2, 0, 16]);
+    }
+}
@@ -0,0 +1,44 @@
+//! Writers for saving a `Framebuffer` to disk, and readers for environment maps and textures
+
+pub mod exr;
+pub mod hdr;
+pub mod png;
+pub mod pnm;
+
+pub use self::exr::{write_exr, ExrOptions, Layer, LayerData, PixelType};
+pub use self::hdr::{write_hdr, read_hdr};
+pub use self::png::{write_png, read_png, PngOptions, BitDepth};
+pub use self::pnm::{write_ppm, write_pfm};
+
+use crate::{Color3, Framebuffer};
+use std::io;
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn check_size(fb: &Framebuffer) -> io::Result<()> {
+    let count = fb.width * fb.height;
+    if fb.pixels.len() != count {
+        return Err(invalid_input(format!("framebuffer has {} pixels, expected {}", fb.pixels.len(), count)));
+    }
+    if let Some(alpha) = &fb.alpha {
+        if alpha.len() != count {
+            return Err(invalid_input(format!("alpha has {} values, expected {}", alpha.len(), count)));
+        }
+    }
+    Ok(())
+}
+
+/// Framebuffer colors are premultiplied by coverage; formats with straight alpha need them divided back out
+fn unpremultiply(c: Color3, alpha: f32) -> Color3 {
+    if alpha > 0.0 && alpha < 1.0 {
+        c / alpha
+    } else {
+        c
+    }
+}
@@ -0,0 +1,169 @@
+//! Tone-mapped, sRGB-encoded PNG output, and input for textures
+
+use crate::{Color3, Framebuffer, ToneMapper, ColorSpace};
+use super::{check_size, unpremultiply, invalid_data};
+use crate::color::srgb_to_linear;
+use nalgebra_glm as glm;
+use std::{fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::Path};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PngOptions {
+    pub bit_depth: BitDepth,
+    /// Write an alpha channel from the framebuffer's coverage. Images without coverage are written as opaque.
+    pub alpha: bool,
+    pub tone_mapper: ToneMapper,
+}
+impl PngOptions {
+    pub fn new(bit_depth: BitDepth, alpha: bool, tone_mapper: ToneMapper) -> Self {
+        PngOptions {
+            bit_depth,
+            alpha,
+            tone_mapper,
+        }
+    }
+}
+
+/// 8-bit RGB with the default tone mapper
+impl Default for PngOptions {
+    fn default() -> Self {
+        PngOptions::new(BitDepth::Eight, false, ToneMapper::default())
+    }
+}
+
+pub fn write_png<P>(path: P, fb: &Framebuffer, options: &PngOptions) -> io::Result<()>
+    where P: AsRef<Path> {
+    let mut w = BufWriter::new(File::create(path)?);
+    encode_png(&mut w, fb, options)?;
+    w.flush()
+}
+
+pub fn encode_png<W: Write>(w: &mut W, fb: &Framebuffer, options: &PngOptions) -> io::Result<()> {
+    use ::png::HasParameters;
+    check_size(fb)?;
+    let fb = fb.in_color_space(ColorSpace::LinearSrgb);
+
+    let channels = if options.alpha { 4 } else { 3 };
+    let bytes_per_sample = match options.bit_depth {
+        BitDepth::Eight => 1,
+        BitDepth::Sixteen => 2,
+    };
+    let mut data = Vec::with_capacity(fb.pixels.len() * channels * bytes_per_sample);
+    for (i, &c) in fb.pixels.iter().enumerate() {
+        let a = fb.alpha.as_ref().map_or(1.0, |alpha| alpha[i]);
+        // PNG stores straight alpha, while the framebuffer is premultiplied
+        let c = if options.alpha { unpremultiply(c, a) } else { c };
+        let encoded = options.tone_mapper.map(c).to_srgb();
+        let mut samples = vec![encoded.r, encoded.g, encoded.b];
+        if options.alpha {
+            samples.push(glm::clamp_scalar(a, 0.0, 1.0));
+        }
+        for v in samples {
+            match options.bit_depth {
+                BitDepth::Eight => data.push((v * 255.0).round() as u8),
+                BitDepth::Sixteen => data.extend_from_slice(&(((v * 65535.0).round()) as u16).to_be_bytes()),
+            }
+        }
+    }
+
+    let mut e = ::png::Encoder::new(w, fb.width as u32, fb.height as u32);
+    e.set(if options.alpha { ::png::ColorType::RGBA } else { ::png::ColorType::RGB })
+     .set(match options.bit_depth {
+         BitDepth::Eight => ::png::BitDepth::Eight,
+         BitDepth::Sixteen => ::png::BitDepth::Sixteen,
+     });
+    let mut writer = e.write_header()?;
+    writer.write_image_data(&data)?;
+
+    Ok(())
+}
+
+pub fn read_png<P>(path: P) -> io::Result<Framebuffer>
+    where P: AsRef<Path> {
+    decode_png(BufReader::new(File::open(path)?))
+}
+
+/// Decodes 8 or 16-bit PNGs of any color type, undoing the sRGB curve. Alpha is kept, and premultiplied.
+pub fn decode_png<R: Read>(r: R) -> io::Result<Framebuffer> {
+    use ::png::HasParameters;
+    let mut decoder = ::png::Decoder::new(r);
+    // Expands palettes and low bit depths, but keeps 16 bits
+    decoder.set(::png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    let channels = match info.color_type {
+        ::png::ColorType::Grayscale => 1,
+        ::png::ColorType::GrayscaleAlpha => 2,
+        ::png::ColorType::RGB => 3,
+        ::png::ColorType::RGBA => 4,
+        ::png::ColorType::Indexed => return Err(invalid_data("unexpanded PNG palette".to_string())),
+    };
+    // The reported depth doesn't account for expansion, so go by the size of the data
+    let pixels = info.width as usize * info.height as usize;
+    let samples: Vec<f32> = match buf.len() / (pixels * channels).max(1) {
+        2 => buf.chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]]) as f32 / 65535.0).collect(),
+        _ => buf.iter().map(|&b| b as f32 / 255.0).collect(),
+    };
+
+    let mut fb = Framebuffer::new(info.width as usize, info.height as usize);
+    let alpha = channels % 2 == 0;
+    let mut coverage = Vec::with_capacity(fb.pixels.len());
+    for (p, s) in fb.pixels.iter_mut().zip(samples.chunks(channels)) {
+        let c = if channels < 3 {
+            Color3::gray(srgb_to_linear(s[0]))
+        } else {
+            Color3::new(srgb_to_linear(s[0]), srgb_to_linear(s[1]), srgb_to_linear(s[2]))
+        };
+        let a = if alpha { s[channels - 1] } else { 1.0 };
+        *p = c * a;
+        coverage.push(a);
+    }
+    if alpha {
+        fb.alpha = Some(coverage);
+    }
+    Ok(fb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ToneMapOperator;
+    use crate::color::linear_to_srgb;
+
+    #[test]
+    fn decodes_with_alpha() {
+        let mut fb = Framebuffer::new(2, 1);
+        fb.set(0, 0, Color3::new(1.0, 0.5, 0.0));
+        fb.alpha = Some(vec![1.0, 0.0]);
+        let options = PngOptions::new(BitDepth::Sixteen, true, ToneMapper::new(0.0, ToneMapOperator::Clamp));
+
+        let mut bytes = Vec::new();
+        encode_png(&mut bytes, &fb, &options).unwrap();
+
+        use ::png::HasParameters;
+        let mut decoder = ::png::Decoder::new(bytes.as_slice());
+        decoder.set(::png::Transformations::IDENTITY);
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!(info.color_type, ::png::ColorType::RGBA);
+        assert_eq!(info.bit_depth, ::png::BitDepth::Sixteen);
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+
+        let sample = |i: usize| u16::from_be_bytes([buf[2 * i], buf[2 * i + 1]]);
+        assert_eq!(sample(0), 65535);
+        assert_eq!(sample(1), (linear_to_srgb(0.5) * 65535.0).round() as u16);
+        assert_eq!(sample(3), 65535);
+        assert_eq!(sample(7), 0);
+
+        let decoded = decode_png(bytes.as_slice()).unwrap();
+        assert!((decoded.get(0, 0).g - 0.5).abs() <= 1.0e-4);
+        assert_eq!(decoded.alpha, Some(vec![1.0, 0.0]));
+    }
+}
@@ -0,0 +1,49 @@
+//! Netpbm-style output: 8-bit PPM for display, linear PFM for HDR
+
+use crate::{Framebuffer, ToneMapper, ColorSpace};
+use super::check_size;
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+pub fn write_ppm<P>(path: P, fb: &Framebuffer, tone_mapper: &ToneMapper) -> io::Result<()>
+    where P: AsRef<Path> {
+    let mut w = BufWriter::new(File::create(path)?);
+    encode_ppm(&mut w, fb, tone_mapper)?;
+    w.flush()
+}
+
+/// Binary (P6) PPM, tone mapped and sRGB-encoded. PPM has no alpha channel.
+pub fn encode_ppm<W: Write>(w: &mut W, fb: &Framebuffer, tone_mapper: &ToneMapper) -> io::Result<()> {
+    check_size(fb)?;
+    let fb = fb.in_color_space(ColorSpace::LinearSrgb);
+    write!(w, "P6\n{} {}\n255\n", fb.width, fb.height)?;
+    let mut data = Vec::with_capacity(fb.pixels.len() * 3);
+    for &c in fb.pixels.iter() {
+        let rgb = tone_mapper.to_srgb8(c);
+        data.extend_from_slice(&[rgb.r, rgb.g, rgb.b]);
+    }
+    w.write_all(&data)
+}
+
+pub fn write_pfm<P>(path: P, fb: &Framebuffer) -> io::Result<()>
+    where P: AsRef<Path> {
+    let mut w = BufWriter::new(File::create(path)?);
+    encode_pfm(&mut w, fb)?;
+    w.flush()
+}
+
+/// Little-endian color PFM with linear sRGB values. Rows are stored bottom to top, as the format requires.
+pub fn encode_pfm<W: Write>(w: &mut W, fb: &Framebuffer) -> io::Result<()> {
+    check_size(fb)?;
+    let fb = fb.in_color_space(ColorSpace::LinearSrgb);
+    // A negative scale marks the data as little-endian
+    write!(w, "PF\n{} {}\n-1.0\n", fb.width, fb.height)?;
+    let mut data = Vec::with_capacity(fb.pixels.len() * 12);
+    for row in fb.pixels.chunks(fb.width.max(1)).rev() {
+        for c in row {
+            data.extend_from_slice(&c.r.to_le_bytes());
+            data.extend_from_slice(&c.g.to_le_bytes());
+            data.extend_from_slice(&c.b.to_le_bytes());
+        }
+    }
+    w.write_all(&data)
+}
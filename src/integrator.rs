@@ -0,0 +1,570 @@
+use crate::math::*;
+use crate::{World, Ray, RayDifferential, Hit, Color3, LightSample, Medium, Material};
+#[cfg(feature="spectral")]
+use crate::spectrum::Wavelengths;
+use crate::bsdf::{Bsdf, Frame};
+use crate::sampler::SampleStream;
+use nalgebra_glm as glm;
+use std::sync::Arc as Shared;
+
+/// How far rays leaving a surface start from it, so they don't hit it again through rounding error
+const RAY_OFFSET: Scalar = 1.0e-4;
+/// Bounces before paths may be ended at random
+const ROULETTE_DEPTH: usize = 3;
+/// Interface surfaces a path may cross, so rays caught between them still end
+const MAX_CROSSINGS: usize = 64;
+
+/// The result of following a single camera ray
+pub(crate) struct PathSample {
+    /// Light arriving straight from light sources after one bounce
+    pub direct: Color3,
+    /// Light that bounced more than once
+    pub indirect: Color3,
+    /// The first surface the camera ray hit, if any
+    pub hit: Option<Hit>,
+    /// Albedo of the first surface hit with its textures looked up, in the world's color space
+    pub albedo: Color3,
+    /// What the radiance was sampled at: each color holds one value per wavelength rather than red, green and blue
+    #[cfg(feature="spectral")]
+    pub wavelengths: Wavelengths,
+}
+impl PathSample {
+    #[inline]
+    pub fn radiance(&self) -> Color3 {
+        self.direct + self.indirect
+    }
+
+    /// The sample with its radiance converted to linear sRGB
+    #[cfg(feature="spectral")]
+    pub fn to_rgb(&self) -> PathSample {
+        PathSample {
+            direct: self.wavelengths.to_rgb(&self.direct),
+            indirect: self.wavelengths.to_rgb(&self.indirect),
+            hit: self.hit.clone(),
+            albedo: self.albedo,
+            wavelengths: self.wavelengths,
+        }
+    }
+}
+
+/// Turns the colors of the scene into what a path carries: RGB, or values at its wavelengths in spectral renders
+struct Spectrum {
+    #[cfg(feature="spectral")]
+    wavelengths: Wavelengths,
+}
+impl Spectrum {
+    #[cfg(feature="spectral")]
+    fn light(&self, c: Color3) -> Color3 {
+        self.wavelengths.upsample(&c)
+    }
+    #[cfg(not(feature="spectral"))]
+    fn light(&self, c: Color3) -> Color3 {
+        c
+    }
+
+    #[cfg(feature="spectral")]
+    fn material(&self, material: &Material) -> Material {
+        material.at_wavelengths(&self.wavelengths)
+    }
+    #[cfg(not(feature="spectral"))]
+    fn material(&self, material: &Material) -> Material {
+        *material
+    }
+
+    #[cfg(feature="spectral")]
+    fn medium(&self, medium: &Shared<Medium>) -> Shared<Medium> {
+        Shared::new(medium.at_wavelengths(&self.wavelengths))
+    }
+    #[cfg(not(feature="spectral"))]
+    fn medium(&self, medium: &Shared<Medium>) -> Shared<Medium> {
+        medium.clone()
+    }
+}
+
+/**
+ * Estimates the radiance arriving along a camera ray with a unidirectional path tracer
+ *
+ * At each surface, one light is sampled directly (next event estimation) and the BSDF is sampled to continue
+ * the path. A light with a surface can be reached either way, so both estimates are combined with multiple
+ * importance sampling using the power heuristic. Paths end after `max_bounces` surfaces, or earlier at random
+ * once their throughput is low (Russian roulette). The world's background counts as one more light, seen by rays
+ * that miss everything.
+ *
+ * Rays carry the medium they travel through, which may scatter them on the way to the next surface; scattering
+ * events are treated like surfaces, with the phase function in place of the BSDF. Rays start out in the world's
+ * medium, and pick up the interior of a primitive as they enter it. Primitives that leave their interior unset
+ * are filled with their material's `interior_medium`. Interface surfaces are crossed without counting as a bounce.
+ *
+ * In spectral renders, a path carries the wavelengths of its camera ray, or three chosen at random. Refracting
+ * through a dispersive material keeps only the first of them, since the others would have bent differently.
+ */
+pub(crate) fn trace(world: &World, camera_ray: &Ray, samples: &mut SampleStream, max_bounces: usize) -> PathSample {
+    #[cfg(feature="spectral")]
+    let wavelengths = camera_ray.wavelengths.unwrap_or_else(|| Wavelengths::sample(samples.next_1d()))
+        .in_color_space(world.color_space);
+    #[cfg(feature="spectral")]
+    let spectrum = Spectrum { wavelengths };
+    #[cfg(not(feature="spectral"))]
+    let spectrum = Spectrum {};
+    let mut result = PathSample {
+        direct: Color3::gray(0.0),
+        indirect: Color3::gray(0.0),
+        hit: None,
+        albedo: Color3::gray(0.0),
+        #[cfg(feature="spectral")]
+        wavelengths,
+    };
+    // Light that scattered at most once on its way to the camera counts as direct
+    let mut add = |scatterings: usize, radiance: Color3| {
+        if scatterings <= 1 {
+            result.direct += radiance;
+        } else {
+            result.indirect += radiance;
+        }
+    };
+    let mut first_hit = None;
+    let mut first_albedo = Color3::gray(0.0);
+
+    let medium = camera_ray.medium.as_ref().or(world.medium.as_ref()).map(|m| spectrum.medium(m));
+    let mut ray = camera_ray.continued(camera_ray.origin, camera_ray.direction, medium)
+        .with_differential(camera_ray.differential);
+    #[cfg(feature="spectral")]
+    {
+        ray.wavelengths = Some(wavelengths);
+    }
+    let mut throughput = Color3::gray(1.0);
+    // Density of the BSDF or phase function sample that chose the current ray, or None for the camera ray and
+    // specular bounces, and where it was taken
+    let mut bsdf_pdf: Option<Scalar> = None;
+    let mut scattered_at = ray.origin;
+    // The background is sampled as if it were the last light
+    let background = world.background.is_emissive();
+    let light_count = world.lights.len() + background as usize;
+    let light_choice = 1.0 / light_count.max(1) as Scalar;
+
+    let mut bounce = 0;
+    let mut crossings = 0;
+    #[cfg(feature="spectral")]
+    let mut hero_only = false;
+    loop {
+        let hit = world.cast(&ray);
+
+        // A light's surface in front of everything else
+        let surface_distance = hit.as_ref().map_or(Scalar::INFINITY, |h| h.distance);
+        let light_hit = world.cast_lights(&ray).filter(|&(_, t)| t < surface_distance);
+        if let Some(medium) = ray.medium.clone() {
+            let distance = light_hit.map_or(surface_distance, |(_, t)| t);
+            let ms = medium.sample(&ray, distance, samples);
+            throughput *= ms.weight;
+            if is_black(&throughput) {
+                break;
+            }
+            if let Some(t) = ms.scatter {
+                let point = ray.at(t);
+                let phase = medium.phase();
+                if let Some((ls, is_delta)) = sample_light(world, &point, light_count, samples) {
+                    let p = phase.eval(&ray.direction, &ls.direction);
+                    if ls.pdf > 0.0 {
+                        let shadow = ray.continued(point, ls.direction, ray.medium.clone());
+                        let tr = transmittance(world, &shadow, ls.distance - RAY_OFFSET, &spectrum, samples);
+                        let light_pdf = ls.pdf * light_choice;
+                        let weight = if is_delta { 1.0 } else { power_heuristic(light_pdf, p) };
+                        add(bounce + 1, throughput * tr * spectrum.light(ls.radiance) * (p * weight / light_pdf) as f32);
+                    }
+                }
+
+                if bounce == max_bounces {
+                    break;
+                }
+                // The phase function is sampled exactly, so the throughput only changes through roulette
+                let (direction, pdf) = phase.sample(&ray.direction, samples.next_2d());
+                if !survives_roulette(&mut throughput, bounce, samples) {
+                    break;
+                }
+                ray = ray.continued(point, direction, ray.medium.clone());
+                bsdf_pdf = Some(pdf);
+                scattered_at = point;
+                bounce += 1;
+                continue;
+            }
+        }
+        if let Some((light, _)) = light_hit {
+            let weight = match bsdf_pdf {
+                Some(pdf) => power_heuristic(pdf, light.pdf(&scattered_at, &ray.direction) * light_choice),
+                None => 1.0,
+            };
+            add(bounce, throughput * spectrum.light(light.emitted()) * weight as f32);
+            break;
+        }
+
+        let hit = match hit {
+            Some(hit) => hit,
+            None => {
+                let weight = match bsdf_pdf {
+                    Some(pdf) if background => power_heuristic(pdf, world.background.pdf(&ray.direction) * light_choice),
+                    _ => 1.0,
+                };
+                add(bounce, throughput * spectrum.light(world.background.radiance(&ray.direction, world.color_space)) * weight as f32);
+                break;
+            }
+        };
+        let point = ray.at(hit.distance);
+        if hit.material.is_interface() {
+            crossings += 1;
+            if crossings > MAX_CROSSINGS {
+                break;
+            }
+            let medium = medium_beyond(world, &hit, &spectrum.material(&hit.material), &ray.direction, &spectrum);
+            let differential = scattered_differential(&ray, &hit, &point, &ray.direction);
+            ray = ray.continued(offset(&point, &hit.normal, &ray.direction), ray.direction, medium)
+                .with_differential(differential);
+            continue;
+        }
+        let frame = Frame::from_normal_tangent(&hit.normal, hit.tangent.as_ref());
+        let wo = frame.to_local(&-ray.direction);
+        let textured = hit.material.textured(&world.textures, &hit, world.color_space);
+        let material = spectrum.material(&textured);
+        let bsdf = material.bsdf();
+        if bounce == 0 {
+            first_hit = Some(hit.clone());
+            first_albedo = textured.albedo;
+        }
+
+        // Next event estimation
+        if let Some((ls, is_delta)) = sample_light(world, &point, light_count, samples) {
+            let wi = frame.to_local(&ls.direction);
+            let f = bsdf.eval(&wo, &wi) * wi.z.abs() as f32;
+            if !is_black(&f) && ls.pdf > 0.0 {
+                let medium = medium_beyond(world, &hit, &material, &ls.direction, &spectrum);
+                let shadow = ray.continued(offset(&point, &hit.normal, &ls.direction), ls.direction, medium);
+                let tr = transmittance(world, &shadow, ls.distance - 2.0 * RAY_OFFSET, &spectrum, samples);
+                let light_pdf = ls.pdf * light_choice;
+                let weight = if is_delta {
+                    1.0
+                } else {
+                    power_heuristic(light_pdf, bsdf.pdf(&wo, &wi))
+                };
+                add(bounce + 1, throughput * f * tr * spectrum.light(ls.radiance) * (weight / light_pdf) as f32);
+            }
+        }
+
+        if bounce == max_bounces {
+            break;
+        }
+        let s = match bsdf.sample(&wo, samples.next_2d()) {
+            Some(s) if s.pdf > 0.0 => s,
+            _ => break,
+        };
+        throughput *= s.value * (s.wi.z.abs() / s.pdf) as f32;
+        if is_black(&throughput) || !survives_roulette(&mut throughput, bounce, samples) {
+            break;
+        }
+
+        #[cfg(feature="spectral")]
+        {
+            if s.wi.z * wo.z < 0.0 && material.dispersion.is_some() && !hero_only {
+                // Only the hero wavelength goes this way, standing in for all three
+                throughput *= Color3::new(3.0, 0.0, 0.0);
+                hero_only = true;
+            }
+        }
+
+        let direction = frame.to_world(&s.wi);
+        let medium = medium_beyond(world, &hit, &material, &direction, &spectrum);
+        // Only specular bounces keep the footprint narrow enough to be worth following
+        let differential = if s.delta { scattered_differential(&ray, &hit, &point, &direction) } else { None };
+        ray = ray.continued(offset(&point, &hit.normal, &direction), direction, medium)
+            .with_differential(differential);
+        bsdf_pdf = if s.delta { None } else { Some(s.pdf) };
+        scattered_at = point;
+        bounce += 1;
+    }
+
+    result.hit = first_hit;
+    result.albedo = first_albedo;
+    result
+}
+
+/// Differentials for a ray leaving `hit` in `direction`, if the ray that arrived there had them
+fn scattered_differential(ray: &Ray, hit: &Hit, point: &Vec3, direction: &Vec3) -> Option<RayDifferential> {
+    let footprint = hit.footprint.as_ref()?;
+    ray.differential.as_ref()?.scattered(&ray.direction, &hit.normal, footprint, point, direction)
+}
+
+/// Chooses one of the lights, or the background, and a direction from `point` towards it
+fn sample_light(world: &World, point: &Vec3, light_count: usize, samples: &mut SampleStream) -> Option<(LightSample, bool)> {
+    let u_light = samples.next_1d();
+    let u = samples.next_2d();
+    if light_count == 0 {
+        return None;
+    }
+    let i = ((u_light * light_count as Scalar) as usize).min(light_count - 1);
+    match world.lights.get(i) {
+        Some(light) => light.sample(point, u).map(|s| (s, light.is_delta())),
+        None => world.background.sample(u, world.color_space).map(|s| (s, false)),
+    }
+}
+
+/// Ends dim paths at random once they are long enough, and boosts the throughput of those that go on to make up for it
+fn survives_roulette(throughput: &mut Color3, bounce: usize, samples: &mut SampleStream) -> bool {
+    if bounce < ROULETTE_DEPTH {
+        return true;
+    }
+    let survive = throughput.r.max(throughput.g).max(throughput.b).min(0.95) as Scalar;
+    if samples.next_1d() >= survive {
+        return false;
+    }
+    *throughput = *throughput / survive as f32;
+    true
+}
+
+/// The medium on the side of the surface that `direction` leaves through. `material` is the hit's, as the path sees it.
+fn medium_beyond(world: &World, hit: &Hit, material: &Material, direction: &Vec3, spectrum: &Spectrum) -> Option<Shared<Medium>> {
+    if glm::dot(&hit.normal, direction) >= 0.0 {
+        return world.medium.as_ref().map(|m| spectrum.medium(m));
+    }
+    match hit.interior {
+        Some(ref medium) => Some(spectrum.medium(medium)),
+        // Only for primitives that don't build their interior up front, like `Sphere` does
+        None => material.interior_medium().map(Shared::new),
+    }
+}
+
+/// Fraction of the light that makes it along a shadow ray out to `distance`, through media and interfaces. Anything
+/// else in the way blocks it.
+fn transmittance(world: &World, shadow: &Ray, distance: Scalar, spectrum: &Spectrum, samples: &mut SampleStream) -> Color3 {
+    let mut ray = shadow.continued(shadow.origin, shadow.direction, shadow.medium.clone());
+    let mut remaining = distance;
+    let mut transmittance = Color3::gray(1.0);
+    for _ in 0..=MAX_CROSSINGS {
+        let hit = world.cast(&ray).filter(|h| h.distance < remaining);
+        if let Some(ref medium) = ray.medium {
+            transmittance *= medium.transmittance(&ray, hit.as_ref().map_or(remaining, |h| h.distance), samples);
+        }
+        let hit = match hit {
+            Some(hit) if hit.material.is_interface() => hit,
+            Some(_) => return Color3::gray(0.0),
+            None => return transmittance,
+        };
+        let point = ray.at(hit.distance);
+        remaining -= hit.distance;
+        let medium = medium_beyond(world, &hit, &spectrum.material(&hit.material), &ray.direction, spectrum);
+        ray = ray.continued(offset(&point, &hit.normal, &ray.direction), ray.direction, medium);
+    }
+    Color3::gray(0.0)
+}
+
+/// Weight for a sample from one of two strategies, from Veach's power heuristic with an exponent of 2
+fn power_heuristic(pdf: Scalar, other_pdf: Scalar) -> Scalar {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b <= 0.0 {
+        0.0
+    } else {
+        a / (a + b)
+    }
+}
+
+/// Moves a point off the surface, to the side `direction` leaves through
+fn offset(point: &Vec3, normal: &Vec3, direction: &Vec3) -> Vec3 {
+    if glm::dot(normal, direction) >= 0.0 {
+        point + normal * RAY_OFFSET
+    } else {
+        point - normal * RAY_OFFSET
+    }
+}
+
+#[inline]
+fn is_black(c: &Color3) -> bool {
+    c.r <= 0.0 && c.g <= 0.0 && c.b <= 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive::Sphere;
+    use crate::sampler::Sobol;
+    use crate::{Light, Material, Background, Medium};
+    #[cfg(feature="spectral")]
+    use crate::spectrum::Dispersion;
+    use std::sync::Arc as Shared;
+
+    fn average(world: &World, ray: &Ray, n: usize) -> Color3 {
+        let sampler = Sobol::new(0);
+        (0..n).fold(Color3::gray(0.0), |sum, i| {
+            let mut stream = SampleStream::new(&sampler, (0, 0), i);
+            sum + trace(world, ray, &mut stream, 4).radiance()
+        }) / n as f32
+    }
+
+    fn matte() -> Shared<Material> {
+        Shared::new(Material {
+            albedo: Color3::gray(0.5),
+            // No specular reflection at all
+            fresnel_ior: 1.0,
+            ..Material::default()
+        })
+    }
+
+    #[test]
+    fn lambertian_under_directional_light() {
+        let world = World {
+            primitives: vec![Box::new(Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, &matte()))],
+            lights: vec![Light::directional(Vec3::new(0.0, 0.0, 1.0), Color3::gray(2.0))],
+            ..World::default()
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let l = average(&world, &ray, 4);
+        // Close to albedo / pi * irradiance * cos, less what the Fresnel term of the coat keeps from the base
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let expected = matte().shade(&ray, &normal, &normal) * 2.0;
+        assert!((l.r - expected.r).abs() <= 1.0e-4, "{:?} vs {:?}", l, expected);
+        assert!((expected.r - 0.5 / std::f32::consts::PI * 2.0).abs() <= 0.05 * expected.r);
+    }
+
+    #[test]
+    #[cfg(not(feature="spectral"))] // Compares colors channel by channel
+    fn glass_absorbs_along_its_thickness() {
+        // A ball of tinted glass in front of a light
+        let glass = Shared::new(Material {
+            roughness: 0.0,
+            transmission: 1.0,
+            ior: 1.0,
+            transmittance: Color3::new(0.9, 0.5, 0.1),
+            ..Material::default()
+        });
+        let world = World {
+            primitives: vec![Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 2.0, &glass))],
+            lights: vec![Light::sphere(Vec3::new(0.0, 0.0, -10.0), 3.0, Color3::gray(1.0))],
+            ..World::default()
+        };
+        // The ray crosses 4 units of glass with no reflection at an index-matched boundary
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let l = average(&world, &ray, 1);
+        assert!((l.g - 0.5f32.powi(4)).abs() <= 1.0e-4, "{:?}", l);
+        assert!(l.r > l.g && l.g > l.b);
+    }
+
+    #[test]
+    fn convex_object_under_a_uniform_sky() {
+        // Every direction above a convex diffuse surface sees the sky, so it reflects albedo times the sky's radiance
+        let world = World {
+            primitives: vec![Box::new(Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, &matte()))],
+            background: Background::Constant(Color3::gray(2.0)),
+            ..World::default()
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), glm::normalize(&Vec3::new(0.1, 0.2, -1.0)));
+        let l = average(&world, &ray, 256);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let albedo = matte().shade(&ray, &normal, &normal).r * std::f32::consts::PI;
+        assert!((l.r - 2.0 * albedo).abs() <= 0.03 * l.r, "{:?} vs {}", l, 2.0 * albedo);
+    }
+
+    #[test]
+    fn mis_agrees_with_the_analytic_sphere_light() {
+        // A small sphere light straight above a huge diffuse ball, seen from right above its surface
+        let world = World {
+            primitives: vec![Box::new(Sphere::new(Vec3::new(0.0, -100.0, 0.0), 100.0, &matte()))],
+            lights: vec![Light::sphere(Vec3::new(0.0, 4.0, 0.0), 1.0, Color3::gray(3.0))],
+            ..World::default()
+        };
+        let ray = Ray::new(Vec3::new(0.0, 1.0e-2, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let l = average(&world, &ray, 1024);
+        // Irradiance from a sphere of radiance L subtending sin^2 = r^2/d^2, straight overhead: pi L sin^2
+        let expected = 0.5 / std::f32::consts::PI * std::f32::consts::PI * 3.0 / 16.0;
+        assert!((l.r - expected).abs() <= 0.03 * expected, "{} vs {}", l.r, expected);
+    }
+
+    #[test]
+    #[cfg(not(feature="spectral"))] // Compares colors channel by channel
+    fn fog_dims_what_lies_behind_it() {
+        let world = World {
+            lights: vec![Light::sphere(Vec3::new(0.0, 0.0, -10.0), 1.0, Color3::gray(1.0))],
+            medium: Some(Shared::new(Medium::homogeneous(Color3::new(0.05, 0.1, 0.2), Color3::gray(0.0), 0.0))),
+            ..World::default()
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let l = average(&world, &ray, 1);
+        assert!((l.r - (-0.45f32).exp()).abs() <= 1.0e-4 && (l.b - (-1.8f32).exp()).abs() <= 1.0e-4, "{:?}", l);
+    }
+
+    #[test]
+    fn scattering_medium_under_a_uniform_sky_neither_gains_nor_loses_light() {
+        // A ball of white smoke, which scatters without absorbing, in front of a sky that's the same everywhere
+        let smoke = Shared::new(Medium::homogeneous(Color3::gray(0.0), Color3::gray(0.5), 0.5));
+        let ball = Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, &Shared::new(Material::interface())).with_interior(&smoke);
+        let world = World {
+            primitives: vec![Box::new(ball)],
+            background: Background::Constant(Color3::gray(2.0)),
+            ..World::default()
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), glm::normalize(&Vec3::new(0.1, 0.0, -1.0)));
+        let l = average(&world, &ray, 512);
+        assert!((l.g - 2.0).abs() <= 0.03 * 2.0, "{:?}", l);
+    }
+
+    #[test]
+    #[cfg(not(feature="spectral"))] // Compares colors channel by channel
+    fn light_finds_its_way_back_out_of_a_subsurface_material() {
+        // A ball of wax that hardly absorbs red light, under a uniform sky. Random walks take many bounces.
+        let wax = Shared::new(Material {
+            subsurface: 1.0,
+            subsurface_color: Color3::new(0.99, 0.5, 0.1),
+            subsurface_radius: Color3::gray(0.5),
+            ior: 1.5,
+            fresnel_ior: 1.5,
+            ..Material::default()
+        });
+        let world = World {
+            primitives: vec![Box::new(Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, &wax))],
+            background: Background::Constant(Color3::gray(1.0)),
+            ..World::default()
+        };
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let sampler = Sobol::new(0);
+        let n = 256;
+        let l = (0..n).fold(Color3::gray(0.0), |sum, i| {
+            let mut stream = SampleStream::new(&sampler, (0, 0), i);
+            sum + trace(&world, &ray, &mut stream, 256).radiance()
+        }) / n as f32;
+        // Only the coat's internal reflection keeps light in
+        assert!(l.r > 0.8 && l.r <= 1.02, "{:?}", l);
+        assert!(l.r > l.g && l.g > l.b, "{:?}", l);
+    }
+
+    #[test]
+    #[cfg(feature="spectral")]
+    fn white_stays_white_through_dispersive_glass() {
+        let light = Light::sphere(Vec3::new(0.0, 0.0, -10.0), 3.0, Color3::gray(1.0));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let world = World {
+            lights: vec![light],
+            ..World::default()
+        };
+        let to_rgb = |world: &World| {
+            let sampler = Sobol::new(0);
+            let n = 256;
+            (0..n).fold(Color3::gray(0.0), |sum, i| {
+                let mut stream = SampleStream::new(&sampler, (0, 0), i);
+                sum + trace(world, &ray, &mut stream, 4).to_rgb().radiance()
+            }) / n as f32
+        };
+        let l = to_rgb(&world);
+        assert!((l.r - 1.0).abs() <= 0.02 && (l.g - 1.0).abs() <= 0.02 && (l.b - 1.0).abs() <= 0.02, "{:?}", l);
+
+        // Straight through the middle of a ball of flint glass, every wavelength makes it, less what's reflected
+        let glass = Shared::new(Material {
+            roughness: 0.0,
+            transmission: 1.0,
+            transmittance: Color3::gray(1.0),
+            dispersion: Some(Dispersion::flint()),
+            ..Material::default()
+        });
+        let world = World {
+            primitives: vec![Box::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, &glass))],
+            lights: vec![light],
+            ..World::default()
+        };
+        let l = to_rgb(&world);
+        assert!(l.g > 0.7 && l.g < 1.0 && (l.r - l.b).abs() <= 0.1, "{:?}", l);
+    }
+}
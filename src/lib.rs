@@ -1,17 +1,49 @@
-#![feature(const_fn)]
-
 pub mod math;
 mod ray;
 pub mod primitive;
+pub mod image;
+pub mod sampler;
+pub mod filter;
+pub mod bsdf;
+pub mod spectrum;
 mod camera;
 mod screen;
 mod world;
 mod material;
+mod light;
+mod background;
+mod sky;
+mod distribution;
+mod medium;
 mod color;
+mod colorspace;
+mod tonemap;
+mod denoise;
+mod post;
+mod texture;
+mod framebuffer;
+mod aov;
+mod integrator;
+mod film;
+mod tile;
+mod control;
 
-pub use ray::{Hit, Ray};
+pub use ray::{Hit, Ray, RayDifferential, Footprint};
 pub use camera::Camera;
-pub use screen::Screen;
+pub use screen::{Screen, StopCondition, PassInfo, AdaptiveSampling};
 pub use world::World;
 pub use material::Material;
-pub use color::Color3;
\ No newline at end of file
+pub use light::{Light, LightSample};
+pub use background::{Background, EnvironmentMap};
+pub use sky::Sky;
+pub use medium::{Medium, HenyeyGreenstein, DensityGrid, MediumSample};
+pub use color::Color3;
+pub use colorspace::{ColorSpace, Chromaticities};
+pub use tonemap::{ToneMapper, ToneMapOperator};
+pub use denoise::Denoiser;
+pub use post::{PostEffect, Bloom, Vignette, ChromaticAberration, Grain, ColorGrade, ParseEffectError};
+pub use texture::{TextureCache, TextureId, TextureStats};
+pub use framebuffer::Framebuffer;
+pub use aov::{AovSelection, AovBuffers};
+pub use tile::{Rect, TileOrder};
+pub use control::{RenderControl, RenderHandle, Rendered};
\ No newline at end of file
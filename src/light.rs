@@ -0,0 +1,192 @@
+use crate::math::*;
+use crate::{Ray, Color3};
+use crate::bsdf::Frame;
+use nalgebra_glm as glm;
+
+/// A direction towards a light, chosen by `Light::sample`
+#[derive(Debug, Copy, Clone)]
+pub struct LightSample {
+    /// Unit vector from the shaded point towards the light
+    pub direction: Vec3,
+    /// How far the light is along `direction`; infinite for directional lights
+    pub distance: Scalar,
+    /// Radiance arriving along `direction`, or irradiance for lights with no area
+    pub radiance: Color3,
+    /// Density per unit solid angle of having chosen `direction`. 1 for lights with no area.
+    pub pdf: Scalar,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Light {
+    /// Parallel light from infinitely far away, like the sun
+    Directional {
+        /// Unit vector pointing towards the light
+        direction: Vec3,
+        /// Irradiance on a surface facing the light
+        irradiance: Color3,
+    },
+    /// Light from a single point, falling off with the square of distance
+    Point {
+        position: Vec3,
+        /// Radiant intensity, the same in every direction
+        intensity: Color3,
+    },
+    /// A sphere emitting constant radiance from its whole surface. It doesn't block light or camera rays from other sources.
+    Sphere {
+        center: Vec3,
+        radius: Scalar,
+        radiance: Color3,
+    },
+}
+impl Light {
+    pub fn directional(direction: Vec3, irradiance: Color3) -> Self {
+        Light::Directional {
+            direction: glm::normalize(&direction),
+            irradiance,
+        }
+    }
+
+    pub fn point(position: Vec3, intensity: Color3) -> Self {
+        Light::Point {
+            position,
+            intensity,
+        }
+    }
+
+    pub fn sphere(center: Vec3, radius: Scalar, radiance: Color3) -> Self {
+        Light::Sphere {
+            center,
+            radius,
+            radiance,
+        }
+    }
+
+    /// Whether the light can only be reached by sampling it, never by a ray that happens to hit it
+    pub fn is_delta(&self) -> bool {
+        match self {
+            Light::Directional { .. } | Light::Point { .. } => true,
+            Light::Sphere { .. } => false,
+        }
+    }
+
+    /// Chooses a direction from `point` towards the light. `u` is a uniformly distributed point in the unit square.
+    pub fn sample(&self, point: &Vec3, u: (Scalar, Scalar)) -> Option<LightSample> {
+        match *self {
+            Light::Directional { direction, irradiance } => Some(LightSample {
+                direction,
+                distance: Scalar::INFINITY,
+                radiance: irradiance,
+                pdf: 1.0,
+            }),
+            Light::Point { position, intensity } => {
+                let to_light = position - point;
+                let d2 = glm::dot(&to_light, &to_light);
+                if d2 <= 0.0 {
+                    return None;
+                }
+                let distance = d2.sqrt();
+                Some(LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    radiance: intensity / d2 as f32,
+                    pdf: 1.0,
+                })
+            }
+            Light::Sphere { center, radius, radiance } => {
+                // Uniformly within the cone of directions that see the sphere
+                let to_center = center - point;
+                let d2 = glm::dot(&to_center, &to_center);
+                if d2 <= radius * radius {
+                    return None;
+                }
+                let d = d2.sqrt();
+                let cos_max = (1.0 - radius * radius / d2).max(0.0).sqrt();
+                let cos_theta = 1.0 - u.0 * (1.0 - cos_max);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * consts::PI * u.1;
+                let frame = Frame::from_normal(&(to_center / d));
+                let direction = frame.to_world(&Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+                // Nearest intersection with the sphere along the sampled direction
+                let distance = d * cos_theta - (radius * radius - d2 * sin_theta * sin_theta).max(0.0).sqrt();
+                Some(LightSample {
+                    direction,
+                    distance,
+                    radiance,
+                    pdf: cone_pdf(cos_max),
+                })
+            }
+        }
+    }
+
+    /// Density with which `sample` would choose `direction` from `point`. Always 0 for delta lights.
+    pub fn pdf(&self, point: &Vec3, direction: &Vec3) -> Scalar {
+        match *self {
+            Light::Directional { .. } | Light::Point { .. } => 0.0,
+            Light::Sphere { center, radius, .. } => {
+                if self.intersect(&Ray::new(*point, *direction)).is_none() {
+                    return 0.0;
+                }
+                let d2 = glm::distance2(&center, point);
+                cone_pdf((1.0 - radius * radius / d2).max(0.0).sqrt())
+            }
+        }
+    }
+
+    /// Distance along the ray to the light's surface, for lights that have one
+    pub fn intersect(&self, ray: &Ray) -> Option<Scalar> {
+        match *self {
+            Light::Directional { .. } | Light::Point { .. } => None,
+            Light::Sphere { center, radius, .. } => {
+                let oc = ray.origin - center;
+                let b = glm::dot(&oc, &ray.direction);
+                let c = glm::dot(&oc, &oc) - radius * radius;
+                let disc = b * b - c;
+                if disc < 0.0 {
+                    return None;
+                }
+                let root = disc.sqrt();
+                [-b - root, -b + root].iter().cloned().find(|&t| t > 0.0)
+            }
+        }
+    }
+
+    /// Radiance leaving the light's surface
+    pub fn emitted(&self) -> Color3 {
+        match *self {
+            Light::Sphere { radiance, .. } => radiance,
+            Light::Directional { .. } | Light::Point { .. } => Color3::gray(0.0),
+        }
+    }
+}
+
+/// Density of sampling a cone of directions uniformly
+fn cone_pdf(cos_max: Scalar) -> Scalar {
+    1.0 / (2.0 * consts::PI * (1.0 - cos_max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_samples_land_on_the_sphere() {
+        let light = Light::sphere(Vec3::new(1.0, 5.0, -2.0), 0.5, Color3::gray(10.0));
+        let p = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..64 {
+            let u = ((i % 8) as Scalar / 8.0 + 0.01, (i / 8) as Scalar / 8.0 + 0.02);
+            let s = light.sample(&p, u).unwrap();
+            let hit = light.intersect(&Ray::new(p, s.direction)).unwrap();
+            assert!((hit - s.distance).abs() <= 1.0e-4 * hit);
+            assert!((light.pdf(&p, &s.direction) - s.pdf).abs() <= 1.0e-4 * s.pdf);
+        }
+        assert_eq!(light.pdf(&p, &Vec3::new(0.0, -1.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn point_light_falls_off() {
+        let light = Light::point(Vec3::new(0.0, 2.0, 0.0), Color3::gray(8.0));
+        let s = light.sample(&Vec3::new(0.0, 0.0, 0.0), (0.5, 0.5)).unwrap();
+        assert_eq!(s.radiance.r, 2.0);
+        assert!(light.is_delta());
+    }
+}
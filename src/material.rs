@@ -1,11 +1,11 @@
-use crate::math::Vec3;
-use crate::{Ray, Color3};
-use nalgebra_glm as glm;
-use std::f32::consts::PI;
+use crate::math::*;
+use crate::{Ray, Hit, Color3, Medium, ColorSpace, TextureCache, TextureId};
+use crate::spectrum::{Dispersion, Wavelengths};
+use crate::bsdf::{Bsdf, Composite, Frame, Lambert, LambertTransmission, OrenNayar, Ggx, Sheen, Dielectric, Coat, Coated, Fresnel, ComplexIor};
 
 /**
  * A physically-based material model
- * TODO: textures
+ * TODO: textures for the other parameters
  * TODO: should area lights be implemented by adding an emissive component?
  */
 #[derive(Debug, Copy, Clone)]
@@ -14,9 +14,34 @@ pub struct Material {
     pub metallic: f32,
     pub albedo: Color3,
     pub reflectance: Color3,
+    /// Fraction of the light that survives travelling a unit of distance through the material, when it is transmissive
     pub transmittance: Color3,
+    /// How much of the dielectric part is clear like glass rather than diffuse, from 0 to 1
+    pub transmission: f32,
     pub ior: f32,
     pub fresnel_ior: f32, // separate the IOR used in shading from the IOR used for refraction, solely for artistic expressiveness (not physically-based)
+    /// Complex IOR of the metallic part. Without one, it is fitted to the albedo.
+    pub conductor: Option<ComplexIor>,
+    /// Oren-Nayar roughness of the diffuse part: the standard deviation of facet angles in radians. 0 is Lambertian.
+    pub diffuse_roughness: f32,
+    /// Stretches specular highlights along the surface tangent when positive, or across it when negative, from -1 to 1
+    pub anisotropy: f32,
+    /// Coverage of a clear varnish layer on top of everything else, as on car paint, from 0 to 1
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    /// Color of the glow of fabric fibers at grazing angles. Black for none.
+    pub sheen: Color3,
+    pub sheen_roughness: f32,
+    /// How much of the diffuse part scatters under the surface rather than off it, as in skin or wax, from 0 to 1
+    pub subsurface: f32,
+    /// Color the material takes on once light has scattered around inside it for a while
+    pub subsurface_color: Color3,
+    /// Average distance light travels between scattering events under the surface, per channel, in scene units
+    pub subsurface_radius: Color3,
+    /// Makes `ior` vary with wavelength in spectral renders
+    pub dispersion: Option<Dispersion>,
+    /// Multiplies `albedo`, looked up at the hit's uv in the world's textures
+    pub albedo_texture: Option<TextureId>,
 }
 impl Material {
     pub fn new(roughness: f32, metallic: f32, albedo: Color3, reflectance: Color3, transmittance: Color3, ior: f32) -> Self {
@@ -26,66 +51,272 @@ impl Material {
             albedo: albedo,
             reflectance: reflectance,
             transmittance: transmittance,
+            transmission: 0.0,
             ior: ior,
             fresnel_ior: ior,
+            conductor: None,
+            diffuse_roughness: 0.0,
+            anisotropy: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: Color3::gray(0.0),
+            sheen_roughness: 0.3,
+            subsurface: 0.0,
+            subsurface_color: Color3::gray(0.8),
+            // Red light goes deepest, as in skin
+            subsurface_radius: Color3::new(0.1, 0.02, 0.01),
+            dispersion: None,
+            albedo_texture: None,
         }
     }
 
-    fn chi_ggx(v: f32) -> f32 {
-        if v > 0.0 {
-            1.0
-        } else {
-            0.0
+    /// The material at a hit, with its textures looked up, filtered by the hit's footprint
+    pub fn textured(&self, textures: &TextureCache, hit: &Hit, color_space: ColorSpace) -> Material {
+        match self.albedo_texture {
+            Some(id) => Material {
+                albedo: self.albedo * textures.lookup(id, &hit.uv, hit.footprint.as_ref(), color_space),
+                ..*self
+            },
+            None => *self,
         }
     }
 
-    // TODO: texture coordinates (2D and 3D)
-    pub fn shade(&self, ray: &Ray, normal: &Vec3, dir_to_light: &Vec3) -> Color3 {
-
-        let view_dir = -ray.direction;
+    /// An invisible surface that lets all light straight through, marking where a medium begins and ends
+    pub fn interface() -> Self {
+        Material {
+            transmission: 1.0,
+            transmittance: Color3::gray(1.0),
+            ior: 1.0,
+            fresnel_ior: 1.0,
+            ..Material::default()
+        }
+    }
 
-        // Cook-Torrance microfacet specular
-        let half = glm::normalize(&(dir_to_light + view_dir));
-        let alpha = self.roughness * self.roughness;
-        let alpha_sq = alpha * alpha;
+    /// Whether the surface leaves light alone: index-matched, clear, with nothing layered on top
+    pub fn is_interface(&self) -> bool {
+        self.transmission >= 1.0 && self.metallic <= 0.0 && self.ior == 1.0 && self.clearcoat <= 0.0 && self.sheen.luminance() <= 0.0
+    }
 
-        let nol = glm::dot(&normal, &dir_to_light) as f32;
-        let nov = glm::dot(&normal, &view_dir) as f32;
-        let noh = glm::dot(&normal, &half) as f32;
-        let noh_sq = noh * noh;
-        let voh = glm::dot(&view_dir, &half) as f32;
+    /**
+     * The material as seen by a path carrying the given wavelengths, with each color holding one value per wavelength
+     *
+     * Colors are upsampled to smooth spectra, complex IORs are interpolated between the wavelengths they were
+     * given for, and `ior` follows `dispersion` at the hero wavelength.
+     */
+    pub fn at_wavelengths(&self, wavelengths: &Wavelengths) -> Material {
+        let upsample = |c: &Color3| wavelengths.upsample(c);
+        Material {
+            albedo: upsample(&self.albedo),
+            reflectance: upsample(&self.reflectance),
+            transmittance: upsample(&self.transmittance),
+            conductor: self.conductor.map(|c| ComplexIor::new(wavelengths.interpolate(&c.eta), wavelengths.interpolate(&c.k))),
+            sheen: upsample(&self.sheen),
+            subsurface_color: upsample(&self.subsurface_color),
+            subsurface_radius: upsample(&self.subsurface_radius),
+            ior: self.dispersion.map_or(self.ior, |d| d.ior(wavelengths.hero()) as f32),
+            ..*self
+        }
+    }
 
-        // D: normal distribution function
-        let d_denom = noh_sq * alpha_sq + (1.0 - noh_sq);
-        let d = (Self::chi_ggx(noh) * alpha_sq) / (PI * d_denom * d_denom);
+    /**
+     * The medium light scatters through under the surface, for a random walk
+     *
+     * The single-scattering albedo is chosen so that light scattered many times comes out with `subsurface_color`,
+     * by the fit of Chiang et al., "Practical and Controllable Subsurface Scattering for Production Path Tracing".
+     */
+    pub fn subsurface_medium(&self) -> Option<Medium> {
+        if self.subsurface <= 0.0 {
+            return None;
+        }
+        let albedo = |a: f32| {
+            let a = a.clamp(0.0, 1.0);
+            let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            (1.0 - s * s).clamp(0.0, 1.0)
+        };
+        let extinction = |d: f32| 1.0 / d.max(1.0e-6);
+        let c = self.subsurface_color;
+        let r = self.subsurface_radius;
+        let sigma_t = Color3::new(extinction(r.r), extinction(r.g), extinction(r.b));
+        let sigma_s = sigma_t * Color3::new(albedo(c.r), albedo(c.g), albedo(c.b));
+        Some(Medium::homogeneous(sigma_t - sigma_s, sigma_s, 0.0))
+    }
 
-        // G: geometry/self-shadowing
-        let k = ((self.roughness + 1.0) * (self.roughness + 1.0)) / 8.0;
-        let g1_l = nol / (nol * (1.0 - k) + k);
-        let g1_v = nov / (nov * (1.0 - k) + k);
-        let g = g1_l * g1_v;
+    /// What fills a closed surface made of this material: its subsurface medium, or absorption following the
+    /// Beer-Lambert law if it transmits tinted light
+    pub fn interior_medium(&self) -> Option<Medium> {
+        let clear = |c: &Color3| c.r >= 1.0 && c.g >= 1.0 && c.b >= 1.0;
+        if self.subsurface > 0.0 {
+            self.subsurface_medium()
+        } else if self.transmission > 0.0 && !clear(&self.transmittance) {
+            Some(Medium::absorbing(self.transmittance))
+        } else {
+            None
+        }
+    }
 
-        // F: schlick's fresnel approximation
-        let sqrt_f0 = (1.0 - self.fresnel_ior) / (1.0 + self.fresnel_ior); // the 1.0 is the IOR of the material the ray is exiting (assumed air)
-        let f0 = Color3::gray(sqrt_f0 * sqrt_f0).mix(&self.albedo, self.metallic);
-        // TODO: this forumula is a bad approximation for metals
-        let f = f0 + (Color3::gray(1.0) - f0) * (1.0 - voh).powi(5);
+    /// Reflectance of the dielectric part at normal incidence, from `fresnel_ior`
+    fn f0(&self) -> Color3 {
+        // The 1.0 is the IOR of the material the ray is exiting (assumed air)
+        let sqrt_f0 = (1.0 - self.fresnel_ior) / (1.0 + self.fresnel_ior);
+        Color3::gray(sqrt_f0 * sqrt_f0)
+    }
 
-        let specular = (d * f * g) / (4.0 * nol * nov);
+    /**
+     * The material's BSDF in the shading frame, along the lines of the glTF metallic-roughness model
+     *
+     * The dielectric part is a GGX specular coat over a diffuse base that only receives the light the coat
+     * doesn't reflect, so the two never add up to more than what arrives. `transmission` blends that towards
+     * glass, which refracts with `ior`. The metallic part is GGX with the Fresnel reflectance of a conductor.
+     * `metallic` blends linearly between the dielectric and metallic parts. `subsurface` trades the diffuse base
+     * for diffuse transmission into the material, where the path tracer follows a random walk through
+     * `subsurface_medium` until the light comes back out.
+     *
+     * Sheen and then clearcoat are layered on top, each taking away from what's underneath the light it reflects.
+     * Specular lobes other than the clearcoat follow `anisotropy`, along the x axis of the shading frame.
+     */
+    pub fn bsdf(&self) -> Composite {
+        let alpha = (self.roughness * self.roughness) as Scalar;
+        let (alpha_x, alpha_y) = Ggx::anisotropic_alphas(alpha, self.anisotropy as Scalar);
+        let metallic = self.metallic.clamp(0.0, 1.0);
+        let opaque = (1.0 - metallic) * (1.0 - self.transmission.clamp(0.0, 1.0));
+        let clear = (1.0 - metallic) - opaque;
+        let f0 = self.f0();
+        let mut bsdf = Composite::new();
+        let specular = Coat::Specular { alpha, f0, weight: 1.0 };
+        let subsurface = self.subsurface.clamp(0.0, 1.0);
+        let diffuse_weight = opaque * (1.0 - subsurface);
+        if self.diffuse_roughness > 0.0 {
+            let diffuse = OrenNayar::new(self.albedo, self.diffuse_roughness as Scalar);
+            bsdf.push_scaled(diffuse_weight, self.albedo.luminance(), Coated::new(diffuse, specular, true));
+        } else {
+            bsdf.push_scaled(diffuse_weight, self.albedo.luminance(), Coated::new(Lambert::new(self.albedo), specular, true));
+        }
+        // The way into and back out of `subsurface_medium`
+        let into = Coated::new(LambertTransmission::new(Color3::gray(1.0)), specular, false);
+        bsdf.push_scaled(opaque * subsurface, 1.0, into);
+        // Fresnel brightens the specular lobe at grazing angles, so don't let dim f0 starve it of samples
+        bsdf.push_scaled(opaque, f0.luminance().max(0.1), Ggx::anisotropic(alpha_x, alpha_y, Fresnel::Schlick(f0)));
+        bsdf.push_scaled(clear, 1.0, Dielectric::new(self.ior as Scalar, alpha));
+        let conductor = self.conductor.unwrap_or_else(|| ComplexIor::from_reflectance(self.albedo, self.albedo));
+        bsdf.push_scaled(metallic, 1.0, Ggx::anisotropic(alpha_x, alpha_y, Fresnel::Conductor(conductor)));
 
-        // Lambertian diffuse
-        // weight by 1 - metallic because metallic surfaces do not have any diffuse
-        let diffuse = (1.0 - self.metallic) * self.albedo / PI;
+        if self.sheen.luminance() > 0.0 {
+            let sheen_alpha = (self.sheen_roughness * self.sheen_roughness) as Scalar;
+            let coat = Coat::Sheen { alpha: sheen_alpha, color: self.sheen };
+            bsdf = layer(bsdf, coat, 1.0, self.sheen.luminance(), Sheen::new(self.sheen, sheen_alpha));
+        }
+        let clearcoat = self.clearcoat.clamp(0.0, 1.0);
+        if clearcoat > 0.0 {
+            let coat_alpha = (self.clearcoat_roughness * self.clearcoat_roughness) as Scalar;
+            // A varnish with an IOR of 1.5
+            let f0 = Color3::gray(0.04);
+            let coat = Coat::Specular { alpha: coat_alpha, f0, weight: clearcoat };
+            bsdf = layer(bsdf, coat, clearcoat, 0.25, Ggx::new(coat_alpha, f0));
+        }
+        bsdf
+    }
 
-        let nol = glm::clamp_scalar(nol, 0.0, 1.0);
-        // TODO: weights k_s and k_d
-        ((diffuse + specular) * nol).clamped()
+    /// Reflected radiance towards the viewer from a single light of unit irradiance along `dir_to_light`
+    // TODO: texture coordinates (2D and 3D)
+    pub fn shade(&self, ray: &Ray, normal: &Vec3, dir_to_light: &Vec3) -> Color3 {
+        let frame = Frame::from_normal(normal);
+        let wo = frame.to_local(&-ray.direction);
+        let wi = frame.to_local(dir_to_light);
+        // Facing away from either the light or the viewer
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color3::gray(0.0);
+        }
+        // Left unclamped: HDR values are compressed later by the tone mapper
+        self.bsdf().eval(&wo, &wi) * wi.z as f32
     }
 }
 
+/// Puts `top` over `base`, which keeps only the light that `coat` lets through
+fn layer<B: Bsdf + 'static>(base: Composite, coat: Coat, scale: f32, sampling_weight: f32, top: B) -> Composite {
+    let mut layered = Composite::new();
+    layered.push(1.0, Coated::new(base, coat, false));
+    layered.push_scaled(scale, sampling_weight, top);
+    layered
+}
+
 // a fairly rough pink plastic
 impl Default for Material {
     fn default() -> Self {
         Material::new(0.5, 0.0, Color3::new(1.0, 0.0, 1.0), Color3::gray(0.0), Color3::gray(0.0), 5.0)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{Sampler, Sobol};
+    use nalgebra_glm as glm;
+
+    /// Fraction of uniform incoming light reflected towards `wo`, estimated by sampling the BSDF
+    fn furnace(material: &Material, wo: &Vec3) -> Color3 {
+        let bsdf = material.bsdf();
+        let sampler = Sobol::new(5);
+        let n = 1 << 14;
+        (0..n).fold(Color3::gray(0.0), |sum, i| {
+            match bsdf.sample(wo, sampler.sample_2d((0, 0), i, 0)) {
+                Some(s) => sum + s.value * (s.wi.z / s.pdf) as f32,
+                None => sum,
+            }
+        }) / n as f32
+    }
+
+    #[test]
+    fn white_furnace() {
+        let views = [Vec3::new(0.0, 0.0, 1.0), glm::normalize(&Vec3::new(1.0, 0.0, 1.0)), glm::normalize(&Vec3::new(0.0, 1.0, 0.2))];
+        for &roughness in [0.05, 0.3, 0.6, 1.0].iter() {
+            for &metallic in [0.0, 0.5, 1.0].iter() {
+                let material = Material {
+                    roughness,
+                    metallic,
+                    albedo: Color3::gray(1.0),
+                    fresnel_ior: 1.5,
+                    ..Material::default()
+                };
+                for wo in views.iter() {
+                    let e = furnace(&material, wo);
+                    assert!(e.r <= 1.01, "roughness {} metallic {} {:?}: {:?}", roughness, metallic, wo, e);
+                    // A white dielectric reflects everything one way or another
+                    if metallic == 0.0 {
+                        assert!(e.r >= 0.98, "roughness {} {:?}: {:?}", roughness, wo, e);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn layers_never_add_energy() {
+        let base = Material {
+            albedo: Color3::gray(1.0),
+            fresnel_ior: 1.5,
+            ..Material::default()
+        };
+        let materials = [
+            Material { diffuse_roughness: 1.0, ..base },
+            Material { clearcoat: 1.0, roughness: 0.8, ..base },
+            Material { sheen: Color3::gray(1.0), sheen_roughness: 0.5, ..base },
+            Material { metallic: 1.0, anisotropy: 0.9, roughness: 0.3, clearcoat: 0.5, ..base },
+        ];
+        let wo = glm::normalize(&Vec3::new(0.6, 0.2, 0.5));
+        for material in materials.iter() {
+            let e = furnace(material, &wo);
+            assert!(e.r <= 1.01 && e.r >= 0.5, "{:?}: {:?}", material, e);
+        }
+    }
+
+    #[test]
+    fn gold_is_yellow() {
+        let material = Material {
+            metallic: 1.0,
+            conductor: Some(ComplexIor::gold()),
+            ..Material::default()
+        };
+        let e = furnace(&material, &Vec3::new(0.0, 0.0, 1.0));
+        assert!(e.r > e.g && e.g > e.b && e.r <= 1.0, "{:?}", e);
+    }
+}
@@ -3,8 +3,10 @@ pub type Scalar = f64;
 #[cfg(not(feature="double-precision"))]
 pub type Scalar = f32;
 
+pub type Vec2 = nalgebra_glm::TVec2<Scalar>;
 pub type Vec3 = nalgebra_glm::TVec3<Scalar>;
 pub type Quat = nalgebra_glm::Qua<Scalar>;
+pub type Mat3 = nalgebra_glm::TMat3<Scalar>;
 pub type Mat4 = nalgebra_glm::TMat4<Scalar>;
 
 pub mod consts;
\ No newline at end of file
@@ -0,0 +1,383 @@
+use crate::math::*;
+use crate::{Ray, Color3};
+use crate::bsdf::Frame;
+use crate::sampler::SampleStream;
+use crate::spectrum::Wavelengths;
+use nalgebra_glm as glm;
+use std::sync::Arc as Shared;
+
+/// Phase function of Henyey and Greenstein: how much light a particle scatters towards each direction
+#[derive(Debug, Copy, Clone)]
+pub struct HenyeyGreenstein {
+    /// Mean cosine of the scattering angle: positive scatters forward, negative backward, 0 evenly in all directions
+    pub g: Scalar,
+}
+impl HenyeyGreenstein {
+    pub fn new(g: Scalar) -> Self {
+        HenyeyGreenstein {
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+
+    /// Density over the sphere of turning from travelling along `direction` to travelling along `scattered`
+    pub fn eval(&self, direction: &Vec3, scattered: &Vec3) -> Scalar {
+        let cos_theta = glm::dot(direction, scattered);
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * consts::PI * denominator * denominator.max(0.0).sqrt())
+    }
+
+    /// A scattered direction for light travelling along `direction`, with its density. The phase function is
+    /// sampled exactly, so the density is also its value.
+    pub fn sample(&self, direction: &Vec3, u: (Scalar, Scalar)) -> (Vec3, Scalar) {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1.0e-3 {
+            1.0 - 2.0 * u.0
+        } else {
+            let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * u.0);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * consts::PI * u.1;
+        let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        let scattered = Frame::from_normal(direction).to_world(&local);
+        (scattered, self.eval(direction, &scattered))
+    }
+}
+
+/// Densities on a regular grid of points, interpolated trilinearly in between
+#[derive(Debug, Clone)]
+pub struct DensityGrid {
+    resolution: (usize, usize, usize),
+    values: Shared<Vec<f32>>,
+    max: f32,
+}
+impl DensityGrid {
+    /// `values` runs along x first, then y, then z. Missing values are 0.
+    pub fn new(resolution: (usize, usize, usize), mut values: Vec<f32>) -> Self {
+        let (nx, ny, nz) = resolution;
+        values.resize(nx * ny * nz, 0.0);
+        let max = values.iter().cloned().fold(0.0, f32::max);
+        DensityGrid {
+            resolution,
+            values: Shared::new(values),
+            max,
+        }
+    }
+
+    /// Fills the grid from a function of the position of each point within the unit cube
+    pub fn from_fn<F: Fn(&Vec3) -> f32>(resolution: (usize, usize, usize), f: F) -> Self {
+        let (nx, ny, nz) = resolution;
+        let mut values = Vec::with_capacity(nx * ny * nz);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let p = Vec3::new(
+                        (x as Scalar + 0.5) / nx as Scalar,
+                        (y as Scalar + 0.5) / ny as Scalar,
+                        (z as Scalar + 0.5) / nz as Scalar,
+                    );
+                    values.push(f(&p));
+                }
+            }
+        }
+        DensityGrid::new(resolution, values)
+    }
+
+    pub fn resolution(&self) -> (usize, usize, usize) {
+        self.resolution
+    }
+
+    /// The largest density anywhere in the grid
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    fn value(&self, x: isize, y: isize, z: isize) -> Scalar {
+        let (nx, ny, nz) = self.resolution;
+        let clamp = |i: isize, n: usize| i.clamp(0, n as isize - 1) as usize;
+        self.values[(clamp(z, nz) * ny + clamp(y, ny)) * nx + clamp(x, nx)].max(0.0) as Scalar
+    }
+
+    /// Density at a point in the unit cube. Outside of it, the density is 0.
+    pub fn density(&self, p: &Vec3) -> Scalar {
+        let (nx, ny, nz) = self.resolution;
+        if self.values.is_empty() || p.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return 0.0;
+        }
+        // Values sit at the centers of the cells
+        let g = Vec3::new(p.x * nx as Scalar - 0.5, p.y * ny as Scalar - 0.5, p.z * nz as Scalar - 0.5);
+        let (x, y, z) = (g.x.floor(), g.y.floor(), g.z.floor());
+        let (fx, fy, fz) = (g.x - x, g.y - y, g.z - z);
+        let (x, y, z) = (x as isize, y as isize, z as isize);
+        let lerp = |a: Scalar, b: Scalar, t: Scalar| a + (b - a) * t;
+        let row = |y: isize, z: isize| lerp(self.value(x, y, z), self.value(x + 1, y, z), fx);
+        let plane = |z: isize| lerp(row(y, z), row(y + 1, z), fy);
+        lerp(plane(z), plane(z + 1), fz)
+    }
+}
+
+/// Where a ray travelling through a medium stops
+#[derive(Debug, Copy, Clone)]
+pub struct MediumSample {
+    /// Distance along the ray at which the medium scattered it, or None if it got all the way through
+    pub scatter: Option<Scalar>,
+    /// Transmittance up to where the ray stopped, times the scattering coefficient if it scattered, over the
+    /// density of stopping there
+    pub weight: Color3,
+}
+
+/**
+ * A participating medium, such as fog, smoke or the inside of a translucent object, that absorbs and scatters
+ * light travelling through it rather than only at surfaces
+ *
+ * Coefficients are per unit distance. Media fill the whole world, or the inside of a primitive whose material is
+ * an interface (see `Material::interface`).
+ */
+#[derive(Debug, Clone)]
+pub enum Medium {
+    /// The same everywhere
+    Homogeneous { sigma_a: Color3, sigma_s: Color3, g: Scalar },
+    /**
+     * Density varying over the box from `min` to `max`, and nothing outside of it
+     *
+     * Extinction is the density times `sigma_t`, the same for every channel, of which `albedo` is scattered.
+     * Free paths are sampled with delta tracking and transmittance is estimated with ratio tracking.
+     */
+    Grid { density: DensityGrid, min: Vec3, max: Vec3, sigma_t: Scalar, albedo: Color3, g: Scalar },
+}
+impl Medium {
+    pub fn homogeneous(sigma_a: Color3, sigma_s: Color3, g: Scalar) -> Self {
+        Medium::Homogeneous { sigma_a, sigma_s, g }
+    }
+
+    /// A medium that only absorbs, letting through `transmittance` of the light per unit distance
+    pub fn absorbing(transmittance: Color3) -> Self {
+        let sigma = |t: f32| if t <= 0.0 { f32::INFINITY } else { -t.min(1.0).ln() };
+        let sigma_a = Color3::new(sigma(transmittance.r), sigma(transmittance.g), sigma(transmittance.b));
+        Medium::homogeneous(sigma_a, Color3::gray(0.0), 0.0)
+    }
+
+    /// The medium as seen by a path carrying the given wavelengths, as with `Material::at_wavelengths`
+    pub fn at_wavelengths(&self, wavelengths: &Wavelengths) -> Medium {
+        match self {
+            Medium::Homogeneous { sigma_a, sigma_s, g } => {
+                Medium::homogeneous(wavelengths.upsample(sigma_a), wavelengths.upsample(sigma_s), *g)
+            }
+            // The grid itself is shared, so this is cheap
+            Medium::Grid { density, min, max, sigma_t, albedo, g } => Medium::Grid {
+                density: density.clone(),
+                min: *min,
+                max: *max,
+                sigma_t: *sigma_t,
+                albedo: wavelengths.upsample(albedo),
+                g: *g,
+            },
+        }
+    }
+
+    pub fn phase(&self) -> HenyeyGreenstein {
+        match *self {
+            Medium::Homogeneous { g, .. } | Medium::Grid { g, .. } => HenyeyGreenstein::new(g),
+        }
+    }
+
+    /// Fraction of the light that makes it along the ray from its origin out to `distance`
+    pub fn transmittance(&self, ray: &Ray, distance: Scalar, samples: &mut SampleStream) -> Color3 {
+        match self {
+            Medium::Homogeneous { sigma_a, sigma_s, .. } => {
+                let sigma_t = *sigma_a + *sigma_s;
+                let d = distance as f32;
+                Color3::new(attenuation(sigma_t.r, d), attenuation(sigma_t.g, d), attenuation(sigma_t.b, d))
+            }
+            Medium::Grid { density, sigma_t, .. } => {
+                let (t0, t1) = match self.clip(ray, distance) {
+                    Some(range) => range,
+                    None => return Color3::gray(1.0),
+                };
+                let majorant = sigma_t * density.max() as Scalar;
+                if majorant <= 0.0 {
+                    return Color3::gray(1.0);
+                }
+                let mut transmittance = 1.0;
+                let mut t = t0;
+                loop {
+                    t -= (1.0 - samples.next_1d()).ln() / majorant;
+                    if t >= t1 || transmittance <= 0.0 {
+                        break;
+                    }
+                    transmittance *= 1.0 - self.density(&ray.at(t)) / density.max() as Scalar;
+                }
+                Color3::gray(transmittance.max(0.0) as f32)
+            }
+        }
+    }
+
+    /// Chooses where along the ray, before `max_distance`, the medium scatters it, if anywhere
+    pub fn sample(&self, ray: &Ray, max_distance: Scalar, samples: &mut SampleStream) -> MediumSample {
+        let passed = |weight: Color3| MediumSample { scatter: None, weight };
+        match self {
+            Medium::Homogeneous { sigma_a, sigma_s, .. } => {
+                if sigma_s.r <= 0.0 && sigma_s.g <= 0.0 && sigma_s.b <= 0.0 {
+                    return passed(self.transmittance(ray, max_distance, samples));
+                }
+                // Distances follow the extinction of a channel chosen at random, which the density averages over
+                let sigma_t = *sigma_a + *sigma_s;
+                let channels = [sigma_t.r, sigma_t.g, sigma_t.b];
+                let channel = channels[((samples.next_1d() * 3.0) as usize).min(2)] as Scalar;
+                let u = samples.next_1d();
+                let t = if channel > 0.0 { -(1.0 - u).ln() / channel } else { Scalar::INFINITY };
+                let scattered = t < max_distance;
+                let t = t.min(max_distance);
+                let d = t as f32;
+                let tr = Color3::new(attenuation(sigma_t.r, d), attenuation(sigma_t.g, d), attenuation(sigma_t.b, d));
+                let density = if scattered { sigma_t * tr } else { tr };
+                let pdf = (density.r + density.g + density.b) / 3.0;
+                if pdf <= 0.0 {
+                    return passed(Color3::gray(0.0));
+                }
+                MediumSample {
+                    scatter: if scattered { Some(t) } else { None },
+                    weight: if scattered { tr * *sigma_s / pdf } else { tr / pdf },
+                }
+            }
+            Medium::Grid { density, sigma_t, albedo, .. } => {
+                let (t0, t1) = match self.clip(ray, max_distance) {
+                    Some(range) => range,
+                    None => return passed(Color3::gray(1.0)),
+                };
+                let majorant = sigma_t * density.max() as Scalar;
+                if majorant <= 0.0 {
+                    return passed(Color3::gray(1.0));
+                }
+                // Delta tracking: collide with the majorant, and keep going at collisions with fictitious particles
+                let mut t = t0;
+                loop {
+                    t -= (1.0 - samples.next_1d()).ln() / majorant;
+                    if t >= t1 {
+                        return passed(Color3::gray(1.0));
+                    }
+                    if self.density(&ray.at(t)) / density.max() as Scalar > samples.next_1d() {
+                        return MediumSample { scatter: Some(t), weight: *albedo };
+                    }
+                }
+            }
+        }
+    }
+
+    /// Density of a grid medium at a point in world space
+    fn density(&self, p: &Vec3) -> Scalar {
+        match self {
+            Medium::Homogeneous { .. } => 1.0,
+            Medium::Grid { density, min, max, .. } => {
+                let size = max - min;
+                density.density(&(p - min).component_div(&size))
+            }
+        }
+    }
+
+    /// The part of the ray, up to `max_distance`, inside the box of a grid medium
+    fn clip(&self, ray: &Ray, max_distance: Scalar) -> Option<(Scalar, Scalar)> {
+        let (min, max) = match self {
+            Medium::Homogeneous { .. } => return Some((0.0, max_distance)),
+            Medium::Grid { min, max, .. } => (min, max),
+        };
+        let (mut t0, mut t1) = (0.0 as Scalar, max_distance);
+        for axis in 0..3 {
+            let inverse = 1.0 / ray.direction[axis];
+            let mut near = (min[axis] - ray.origin[axis]) * inverse;
+            let mut far = (max[axis] - ray.origin[axis]) * inverse;
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // NaN when the ray runs along a face leaves the range alone
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+/// Fraction of light surviving `distance` with extinction coefficient `sigma`, with no light getting through an
+/// infinitely dense medium, and all of it through an empty one, whatever the distance
+fn attenuation(sigma: f32, distance: f32) -> f32 {
+    if sigma <= 0.0 || distance <= 0.0 {
+        1.0
+    } else {
+        (-sigma * distance).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{Sampler, Sobol};
+
+    #[test]
+    fn phase_function_sampling_matches_its_density() {
+        let sampler = Sobol::new(0);
+        let direction = glm::normalize(&Vec3::new(0.3, -0.4, 1.0));
+        for &g in [-0.6, 0.0, 0.8].iter() {
+            let phase = HenyeyGreenstein::new(g);
+            let n = 4096;
+            let mut mean_cos = 0.0;
+            for i in 0..n {
+                let (wi, pdf) = phase.sample(&direction, sampler.sample_2d((0, 0), i, 0));
+                assert!((phase.eval(&direction, &wi) - pdf).abs() <= 1.0e-6 * pdf.max(1.0));
+                mean_cos += glm::dot(&direction, &wi);
+            }
+            assert!((mean_cos / n as Scalar - g).abs() <= 0.02, "g = {}: {}", g, mean_cos / n as Scalar);
+        }
+    }
+
+    /// Mean of the weights of passing through `distance` of the medium, which is the transmittance
+    fn estimated_transmittance(medium: &Medium, ray: &Ray, distance: Scalar) -> (Color3, Color3) {
+        let sampler = Sobol::new(1);
+        let n = 4096;
+        let (mut passed, mut ratio) = (Color3::gray(0.0), Color3::gray(0.0));
+        for i in 0..n {
+            let mut stream = SampleStream::new(&sampler, (0, 0), i);
+            let s = medium.sample(ray, distance, &mut stream);
+            if s.scatter.is_none() {
+                passed += s.weight;
+            }
+            ratio += medium.transmittance(ray, distance, &mut stream);
+        }
+        (passed / n as f32, ratio / n as f32)
+    }
+
+    #[test]
+    fn free_paths_agree_with_transmittance() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let fog = Medium::homogeneous(Color3::new(0.1, 0.2, 0.0), Color3::new(0.3, 0.1, 0.5), 0.3);
+        let (passed, exact) = estimated_transmittance(&fog, &ray, 2.0);
+        assert!((exact.r - (-0.8f32).exp()).abs() <= 1.0e-4);
+        assert!((passed.r - exact.r).abs() <= 0.02 && (passed.b - exact.b).abs() <= 0.02, "{:?} vs {:?}", passed, exact);
+
+        // A uniform grid is a homogeneous medium in its box
+        let grid = Medium::Grid {
+            density: DensityGrid::from_fn((4, 4, 4), |_| 2.0),
+            min: Vec3::new(-1.0, -1.0, -3.0),
+            max: Vec3::new(1.0, 1.0, -1.0),
+            sigma_t: 0.25,
+            albedo: Color3::gray(0.8),
+            g: 0.0,
+        };
+        let (passed, ratio) = estimated_transmittance(&grid, &ray, 10.0);
+        let expected = (-1.0f32).exp();
+        assert!((passed.g - expected).abs() <= 0.02 && (ratio.g - expected).abs() <= 0.02, "{:?} {:?}", passed, ratio);
+    }
+
+    #[test]
+    fn grid_interpolates_between_its_points() {
+        let grid = DensityGrid::new((2, 1, 1), vec![0.0, 4.0]);
+        assert_eq!(grid.max(), 4.0);
+        assert!((grid.density(&Vec3::new(0.5, 0.5, 0.5)) - 2.0).abs() <= 1.0e-6);
+        assert_eq!(grid.density(&Vec3::new(0.1, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.density(&Vec3::new(0.9, 0.5, 0.5)), 4.0);
+        assert_eq!(grid.density(&Vec3::new(1.5, 0.5, 0.5)), 0.0);
+    }
+}
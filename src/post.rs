@@ -0,0 +1,416 @@
+use crate::{Color3, Framebuffer};
+use crate::sampler::{hash_keys, to_unit};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Strongest chromatic aberration allowed, short of shrinking blue to a point
+const MAX_ABERRATION: f32 = 0.99;
+
+/**
+ * A lens or film effect applied to the HDR image, after denoising and before tone mapping
+ *
+ * Effects are stacked in `Screen::post` and run in order. Each one parses from `name` or
+ * `name:key=value,key=value`, e.g. `bloom:threshold=2,radius=0.05` or `grade:gain=1.1/1/0.9`,
+ * with unset parameters keeping their defaults.
+ */
+#[derive(Debug, Copy, Clone)]
+pub enum PostEffect {
+    Bloom(Bloom),
+    Vignette(Vignette),
+    ChromaticAberration(ChromaticAberration),
+    Grain(Grain),
+    Grade(ColorGrade),
+}
+impl PostEffect {
+    /// Applies the effect to the pixels of an image. Alpha and the AOVs are left as they are.
+    pub fn apply(&self, fb: &mut Framebuffer) {
+        if fb.width == 0 || fb.height == 0 || fb.pixels.len() != fb.width * fb.height {
+            return;
+        }
+        match self {
+            PostEffect::Bloom(bloom) => bloom.apply(fb),
+            PostEffect::Vignette(vignette) => vignette.apply(fb),
+            PostEffect::ChromaticAberration(aberration) => aberration.apply(fb),
+            PostEffect::Grain(grain) => grain.apply(fb),
+            PostEffect::Grade(grade) => grade.apply(fb),
+        }
+    }
+}
+
+/// Glow around pixels brighter than a threshold, as light scatters in the lens
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bloom {
+    /// Luminance above which pixels start to glow
+    pub threshold: f32,
+    /// How much of the glow is added back onto the image
+    pub intensity: f32,
+    /// Spread of the glow, as a fraction of the image height
+    pub radius: f32,
+}
+impl Bloom {
+    pub fn new(threshold: f32, intensity: f32, radius: f32) -> Self {
+        Bloom { threshold, intensity, radius }
+    }
+
+    fn apply(&self, fb: &mut Framebuffer) {
+        let bright: Vec<Color3> = fb.pixels.iter().map(|&c| {
+            let l = c.luminance();
+            if l <= self.threshold || l <= 0.0 {
+                Color3::gray(0.0)
+            } else {
+                c * ((l - self.threshold) / l)
+            }
+        }).collect();
+        let radius = (self.radius * fb.height as f32).round().max(0.0) as usize;
+        // Three box blurs come close to a Gaussian, at a cost that doesn't grow with the radius
+        let mut glow = bright;
+        for _ in 0..3 {
+            glow = box_blur(&glow, fb.width, fb.height, radius);
+        }
+        for (p, g) in fb.pixels.iter_mut().zip(glow.iter()) {
+            *p += *g * self.intensity;
+        }
+    }
+}
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom::new(1.0, 0.1, 0.02)
+    }
+}
+
+/// Darkening towards the corners of the frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vignette {
+    /// How much light the corners lose, from 0 to 1
+    pub strength: f32,
+    /// How quickly the darkening sets in away from the center; 2 falls off with the square of the distance
+    pub falloff: f32,
+}
+impl Vignette {
+    pub fn new(strength: f32, falloff: f32) -> Self {
+        Vignette { strength, falloff }
+    }
+
+    fn apply(&self, fb: &mut Framebuffer) {
+        let (w, h) = (fb.width, fb.height);
+        for y in 0..h {
+            for x in 0..w {
+                let (dx, dy) = centered(x, y, w, h);
+                // Distance from the center, with the corners at 1
+                let r = ((dx * dx + dy * dy) / 2.0).sqrt();
+                fb.pixels[y * w + x] *= Color3::gray((1.0 - self.strength * r.powf(self.falloff)).max(0.0));
+            }
+        }
+    }
+}
+impl Default for Vignette {
+    fn default() -> Self {
+        Vignette::new(0.3, 2.0)
+    }
+}
+
+/// Lateral color fringing: red is magnified and blue shrunk, so edges split apart towards the corners
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChromaticAberration {
+    /// How far red and blue shift at the corners, as a fraction of the distance from the center. Kept within
+    /// [0, 0.99], since at 1 blue would shrink to a point.
+    pub strength: f32,
+}
+impl ChromaticAberration {
+    pub fn new(strength: f32) -> Self {
+        let strength = if strength.is_nan() { 0.0 } else { strength.clamp(0.0, MAX_ABERRATION) };
+        ChromaticAberration { strength }
+    }
+
+    fn apply(&self, fb: &mut Framebuffer) {
+        let (w, h) = (fb.width, fb.height);
+        let source = fb.pixels.clone();
+        let (cx, cy) = (w as f32 * 0.5, h as f32 * 0.5);
+        let strength = ChromaticAberration::new(self.strength).strength;
+        for y in 0..h {
+            for x in 0..w {
+                let (px, py) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+                let at = |scale: f32| bilinear(&source, w, h, cx + px * scale, cy + py * scale);
+                let p = &mut fb.pixels[y * w + x];
+                p.r = at(1.0 / (1.0 + strength)).r;
+                p.b = at(1.0 / (1.0 - strength)).b;
+            }
+        }
+    }
+}
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        ChromaticAberration::new(0.003)
+    }
+}
+
+/// Film grain: noise that scales with the brightness underneath, fixed by a seed so animations can vary it per frame
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Grain {
+    /// Largest change in brightness, as a fraction
+    pub amount: f32,
+    pub seed: u32,
+}
+impl Grain {
+    pub fn new(amount: f32, seed: u32) -> Self {
+        Grain { amount, seed }
+    }
+
+    fn apply(&self, fb: &mut Framebuffer) {
+        let w = fb.width;
+        for (i, p) in fb.pixels.iter_mut().enumerate() {
+            let noise = to_unit(hash_keys(self.seed, &[i % w, i / w])) as f32 * 2.0 - 1.0;
+            *p *= Color3::gray((1.0 + noise * self.amount).max(0.0));
+        }
+    }
+}
+impl Default for Grain {
+    fn default() -> Self {
+        Grain::new(0.05, 0)
+    }
+}
+
+/**
+ * Lift, gamma and gain color correction, per channel
+ *
+ * Lift raises the shadows and leaves white alone, gain scales everything, and gamma bends the midtones; values
+ * above 1 brighten them. The defaults leave the image unchanged.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct ColorGrade {
+    pub lift: Color3,
+    pub gamma: Color3,
+    pub gain: Color3,
+}
+impl ColorGrade {
+    pub fn new(lift: Color3, gamma: Color3, gain: Color3) -> Self {
+        ColorGrade { lift, gamma, gain }
+    }
+
+    pub fn grade(&self, c: Color3) -> Color3 {
+        let channel = |x: f32, lift: f32, gamma: f32, gain: f32| {
+            // Past white, only the gain applies
+            let lifted = x + lift * (1.0 - x.min(1.0));
+            let graded = gain * lifted.max(0.0);
+            if gamma > 0.0 && graded < 1.0 { graded.powf(1.0 / gamma) } else { graded }
+        };
+        Color3::new(
+            channel(c.r, self.lift.r, self.gamma.r, self.gain.r),
+            channel(c.g, self.lift.g, self.gamma.g, self.gain.g),
+            channel(c.b, self.lift.b, self.gamma.b, self.gain.b),
+        )
+    }
+
+    fn apply(&self, fb: &mut Framebuffer) {
+        for p in fb.pixels.iter_mut() {
+            *p = self.grade(*p);
+        }
+    }
+}
+impl Default for ColorGrade {
+    fn default() -> Self {
+        ColorGrade::new(Color3::gray(0.0), Color3::gray(1.0), Color3::gray(1.0))
+    }
+}
+
+/// Why a post-processing effect failed to parse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseEffectError(String);
+impl fmt::Display for ParseEffectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+impl Error for ParseEffectError {}
+
+impl FromStr for PostEffect {
+    type Err = ParseEffectError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, params) = match s.find(':') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, ""),
+        };
+        let mut effect = match name.trim() {
+            "bloom" => PostEffect::Bloom(Bloom::default()),
+            "vignette" => PostEffect::Vignette(Vignette::default()),
+            "aberration" | "chromatic-aberration" => PostEffect::ChromaticAberration(ChromaticAberration::default()),
+            "grain" => PostEffect::Grain(Grain::default()),
+            "grade" => PostEffect::Grade(ColorGrade::default()),
+            other => return Err(ParseEffectError(format!("unknown effect \"{}\"", other))),
+        };
+        for param in params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = match param.find('=') {
+                Some(i) => (param[..i].trim(), param[i + 1..].trim()),
+                None => return Err(ParseEffectError(format!("expected key=value, found \"{}\"", param))),
+            };
+            match (&mut effect, key) {
+                (PostEffect::Bloom(b), "threshold") => b.threshold = number(key, value)?,
+                (PostEffect::Bloom(b), "intensity") => b.intensity = number(key, value)?,
+                (PostEffect::Bloom(b), "radius") => b.radius = number(key, value)?,
+                (PostEffect::Vignette(v), "strength") => v.strength = number(key, value)?,
+                (PostEffect::Vignette(v), "falloff") => v.falloff = number(key, value)?,
+                (PostEffect::ChromaticAberration(a), "strength") => {
+                    a.strength = number(key, value)?;
+                    if !(0.0..=MAX_ABERRATION).contains(&a.strength) {
+                        return Err(ParseEffectError(format!("aberration strength must be between 0 and {}, found \"{}\"",
+                            MAX_ABERRATION, value)));
+                    }
+                }
+                (PostEffect::Grain(g), "amount") => g.amount = number(key, value)?,
+                (PostEffect::Grain(g), "seed") => g.seed = number(key, value)?,
+                (PostEffect::Grade(g), "lift") => g.lift = color(key, value)?,
+                (PostEffect::Grade(g), "gamma") => g.gamma = color(key, value)?,
+                (PostEffect::Grade(g), "gain") => g.gain = color(key, value)?,
+                _ => return Err(ParseEffectError(format!("{} has no parameter \"{}\"", name.trim(), key))),
+            }
+        }
+        Ok(effect)
+    }
+}
+
+fn number<T: FromStr>(key: &str, value: &str) -> Result<T, ParseEffectError> {
+    value.parse().map_err(|_| ParseEffectError(format!("invalid {} \"{}\"", key, value)))
+}
+
+/// A gray level, or r/g/b
+fn color(key: &str, value: &str) -> Result<Color3, ParseEffectError> {
+    let channels = value.split('/').map(|v| number::<f32>(key, v.trim())).collect::<Result<Vec<_>, _>>()?;
+    match channels[..] {
+        [gray] => Ok(Color3::gray(gray)),
+        [r, g, b] => Ok(Color3::new(r, g, b)),
+        _ => Err(ParseEffectError(format!("{} needs one or three channels, found \"{}\"", key, value))),
+    }
+}
+
+/// Offset of a pixel's center from the middle of the image, with the edges at -1 and 1
+fn centered(x: usize, y: usize, w: usize, h: usize) -> (f32, f32) {
+    ((x as f32 + 0.5) / w as f32 * 2.0 - 1.0, (y as f32 + 0.5) / h as f32 * 2.0 - 1.0)
+}
+
+/// Samples an image between pixel centers, clamping at the edges. Pixel (x, y) has its center at (x + 0.5, y + 0.5).
+fn bilinear(pixels: &[Color3], w: usize, h: usize, x: f32, y: f32) -> Color3 {
+    let (x, y) = ((x - 0.5).max(0.0).min((w - 1) as f32), (y - 0.5).max(0.0).min((h - 1) as f32));
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+    let top = pixels[y0 * w + x0].mix(&pixels[y0 * w + x1], fx);
+    let bottom = pixels[y1 * w + x0].mix(&pixels[y1 * w + x1], fx);
+    top.mix(&bottom, fy)
+}
+
+/// Averages each pixel with those up to `radius` away horizontally, then vertically, extending the edges outwards
+fn box_blur(pixels: &[Color3], w: usize, h: usize, radius: usize) -> Vec<Color3> {
+    if radius == 0 {
+        return pixels.to_vec();
+    }
+    let mut rows = vec![Color3::gray(0.0); pixels.len()];
+    for y in 0..h {
+        blur_line(pixels, &mut rows, y * w, 1, w, radius);
+    }
+    let mut out = vec![Color3::gray(0.0); pixels.len()];
+    for x in 0..w {
+        blur_line(&rows, &mut out, x, w, h, radius);
+    }
+    out
+}
+
+/// A running-sum box blur along `len` pixels starting at `start`, `stride` apart
+fn blur_line(src: &[Color3], dst: &mut [Color3], start: usize, stride: usize, len: usize, radius: usize) {
+    let at = |i: isize| src[start + i.clamp(0, len as isize - 1) as usize * stride];
+    let r = radius as isize;
+    let mut sum = (-r..=r).fold(Color3::gray(0.0), |sum, i| sum + at(i));
+    let scale = 1.0 / (2 * radius + 1) as f32;
+    for i in 0..len as isize {
+        dst[start + i as usize * stride] = sum * scale;
+        sum += at(i + r + 1) - at(i - r);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat(w: usize, h: usize, c: Color3) -> Framebuffer {
+        let mut fb = Framebuffer::new(w, h);
+        for p in fb.pixels.iter_mut() {
+            *p = c;
+        }
+        fb
+    }
+
+    #[test]
+    fn parses_effects_and_parameters() {
+        assert!(matches!("bloom".parse(), Ok(PostEffect::Bloom(b)) if b == Bloom::default()));
+        assert!(matches!("vignette:strength=0.5, falloff=3".parse(), Ok(PostEffect::Vignette(v)) if v == Vignette::new(0.5, 3.0)));
+        match "grade:lift=0.1,gain=1.2/1/0.8".parse() {
+            Ok(PostEffect::Grade(g)) => {
+                assert_eq!((g.lift.r, g.lift.b, g.gamma.g), (0.1, 0.1, 1.0));
+                assert_eq!((g.gain.r, g.gain.g, g.gain.b), (1.2, 1.0, 0.8));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!("grain:seed=7".parse(), Ok(PostEffect::Grain(g)) if g == Grain::new(0.05, 7)));
+        assert!("blur".parse::<PostEffect>().is_err());
+        assert!("bloom:strength=1".parse::<PostEffect>().is_err());
+        assert!("grade:gain=1/2".parse::<PostEffect>().is_err());
+        assert!("vignette:strength".parse::<PostEffect>().is_err());
+        assert!("aberration:strength=1".parse::<PostEffect>().is_err());
+        assert!("aberration:strength=-0.1".parse::<PostEffect>().is_err());
+        assert_eq!(ChromaticAberration::new(1.5).strength, MAX_ABERRATION);
+    }
+
+    #[test]
+    fn bloom_spreads_only_bright_light() {
+        let mut fb = flat(21, 21, Color3::gray(0.5));
+        PostEffect::Bloom(Bloom::default()).apply(&mut fb);
+        assert!(fb.pixels.iter().all(|p| p.g == 0.5));
+
+        let center = fb.index(10, 10);
+        fb.pixels[center] = Color3::gray(100.0);
+        let bloom = Bloom::new(1.0, 1.0, 0.1);
+        PostEffect::Bloom(bloom).apply(&mut fb);
+        assert!(fb.get(12, 10).g > 0.5 && fb.get(10, 8).g > 0.5);
+        assert!(fb.get(11, 10).g > fb.get(13, 10).g);
+        // The glow carries the light above the threshold
+        let added: f32 = fb.pixels.iter().map(|p| p.g).sum::<f32>() - 0.5 * 440.0 - 100.0;
+        assert!((added - 99.0).abs() <= 0.5, "{}", added);
+    }
+
+    #[test]
+    fn lens_effects_leave_the_center_alone() {
+        let mut fb = flat(32, 32, Color3::gray(1.0));
+        PostEffect::Vignette(Vignette::default()).apply(&mut fb);
+        assert!(fb.get(16, 16).g > 0.99);
+        assert!(fb.get(0, 0).g < fb.get(8, 8).g && fb.get(8, 8).g < fb.get(16, 16).g);
+
+        // A gray image has nothing to fringe; a white square does
+        let mut gray = flat(32, 32, Color3::gray(0.25));
+        let aberration = PostEffect::ChromaticAberration(ChromaticAberration::new(0.05));
+        aberration.apply(&mut gray);
+        assert!(gray.pixels.iter().all(|p| (p.r - 0.25).abs() <= 1.0e-6 && (p.b - 0.25).abs() <= 1.0e-6));
+        let mut square = flat(32, 32, Color3::gray(0.0));
+        for y in 4..12 {
+            for x in 4..12 {
+                square.pixels[y * 32 + x] = Color3::gray(1.0);
+            }
+        }
+        aberration.apply(&mut square);
+        let edge = square.get(4, 8);
+        assert!(edge.r != edge.b && edge.g == 1.0, "{:?}", edge);
+    }
+
+    #[test]
+    fn grading_defaults_to_identity() {
+        let grade = ColorGrade::default();
+        for &v in [0.0, 0.18, 1.0, 5.0].iter() {
+            assert_eq!(grade.grade(Color3::gray(v)).g, v);
+        }
+        let lifted = ColorGrade { lift: Color3::gray(0.1), ..ColorGrade::default() };
+        assert!((lifted.grade(Color3::gray(0.0)).r - 0.1).abs() <= 1.0e-6);
+        assert_eq!(lifted.grade(Color3::gray(1.0)).r, 1.0);
+        let brighter = ColorGrade { gamma: Color3::gray(2.0), ..ColorGrade::default() };
+        assert!(brighter.grade(Color3::gray(0.25)).r > 0.49);
+    }
+}
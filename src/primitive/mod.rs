@@ -1,7 +1,79 @@
 mod sphere;
 pub use sphere::Sphere;
 
-use super::{Hit, Ray};
+use super::{Hit, Ray, Material};
+use crate::math::*;
+use std::sync::Arc as Shared;
+
+/**
+ * Something rays can hit
+ *
+ * Queries take the range of distances along the ray to consider, inclusive at both ends. Only
+ * `nearest_intersection` is required; the other queries are built on it, and primitives can
+ * override them with something faster.
+ */
 pub trait Primitive {
+    /// The nearest hit in front of the ray's origin
     fn nearest_intersection(&self, ray: &Ray) -> Option<Hit>;
-}
\ No newline at end of file
+
+    /// The material the primitive is made of, if it has just one. Only primitives that report it get a material ID.
+    fn material(&self) -> Option<&Shared<Material>> {
+        None
+    }
+
+    /// The nearest hit at a distance in [t_min, t_max]. The default only finds hits in front of the ray's origin.
+    fn nearest_intersection_within(&self, ray: &Ray, t_min: Scalar, t_max: Scalar) -> Option<Hit> {
+        if t_min <= 0.0 {
+            return self.nearest_intersection(ray).filter(|h| h.distance <= t_max);
+        }
+        // Start the ray at t_min, so that hits before it can't hide the ones after
+        let ahead = ray.continued(ray.at(t_min), ray.direction, ray.medium.clone());
+        let mut hit = self.nearest_intersection(&ahead)?;
+        hit.distance += t_min;
+        Some(hit).filter(|h| h.distance <= t_max)
+    }
+
+    /// Whether the ray hits anything within `t_max`, for occlusion tests
+    fn intersects(&self, ray: &Ray, t_max: Scalar) -> bool {
+        self.nearest_intersection_within(ray, 0.0, t_max).is_some()
+    }
+
+    /// Every hit at a distance in [t_min, t_max], nearest first, such as both sides of a closed surface
+    fn all_intersections(&self, ray: &Ray, t_min: Scalar, t_max: Scalar) -> Vec<Hit> {
+        let mut hits = vec![];
+        let mut t = t_min;
+        while let Some(hit) = self.nearest_intersection_within(ray, t, t_max) {
+            // Step just past each hit so it isn't found again
+            t = hit.distance + consts::EPSILON * hit.distance.abs().max(1.0);
+            hits.push(hit);
+        }
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm as glm;
+
+    /// A sphere that only answers the required query, to exercise the provided ones
+    struct Minimal(Sphere);
+    impl Primitive for Minimal {
+        fn nearest_intersection(&self, ray: &Ray) -> Option<Hit> {
+            self.0.nearest_intersection(ray)
+        }
+    }
+
+    #[test]
+    fn provided_queries_find_hits_past_t_min() {
+        let minimal = Minimal(Sphere::new(glm::vec3(0.0, 0.0, 5.0), 1.0, &Shared::new(Material::default())));
+        let ray = Ray::new(glm::zero(), *consts::FORWARD);
+        let far = minimal.nearest_intersection_within(&ray, 4.5, Scalar::INFINITY).unwrap();
+        assert!((far.distance - 6.0).abs() <= 1.0e-4);
+        assert!(minimal.nearest_intersection_within(&ray, 0.0, 3.0).is_none());
+        let all: Vec<Scalar> = minimal.all_intersections(&ray, 0.0, 10.0).iter().map(|h| h.distance).collect();
+        assert_eq!(all.len(), 2);
+        assert!((all[0] - 4.0).abs() <= 1.0e-4 && (all[1] - 6.0).abs() <= 1.0e-4);
+        assert!(minimal.intersects(&ray, 4.5) && !minimal.intersects(&ray, 3.5));
+    }
+}
@@ -1,5 +1,5 @@
 use crate::math::*;
-use crate::{Ray, Hit, Material};
+use crate::{Ray, Hit, Material, Medium};
 use nalgebra_glm as glm;
 use super::Primitive;
 use std::sync::Arc as Shared;
@@ -9,6 +9,9 @@ pub struct Sphere {
     pub center: Vec3,
     pub radius: Scalar,
     pub material: Shared<Material>,
+    /// Fills the inside of the sphere. Starts out as the material's `interior_medium`, built once here rather
+    /// than for every ray that enters.
+    pub interior: Option<Shared<Medium>>,
 }
 impl Sphere {
     pub fn new(center: Vec3, radius: Scalar, material: &Shared<Material>) -> Self {
@@ -16,44 +19,89 @@ impl Sphere {
             center: center,
             radius: radius,
             material: material.clone(),
+            interior: material.interior_medium().map(Shared::new),
         }
     }
-}
-impl Primitive for Sphere {
-    fn nearest_intersection(&self, ray: &Ray) -> Option<Hit> {
+
+    pub fn with_interior(mut self, medium: &Shared<Medium>) -> Self {
+        self.interior = Some(medium.clone());
+        self
+    }
+
+    /// Distances along the ray to where it crosses the sphere, nearest first
+    fn roots(&self, ray: &Ray) -> Option<(Scalar, Scalar)> {
         let translated = ray.origin - self.center;
         // a = 1 because ray.direction is normalized
         let b = glm::dot(&translated, &(2.0*ray.direction));
         let c = glm::length2(&translated) - self.radius*self.radius;
-        let mut d = b*b - 4.0*c; // discriminant
-
-        if d >= 0.0 {
-            d = d.sqrt();
-            let mut dist1 = (-b + d) / 2.0;
-            let mut dist2 = (-b - d) / 2.0;
-            if dist1 > dist2 {
-                std::mem::swap(&mut dist1, &mut dist2);
-            }
-
-            // Final collision distance: closest point in front of the ray
-            let dist = if dist1 >= 0.0 { dist1 } else { dist2 };
-            if dist >= 0.0 {
-                Some(
-                    Hit::new(dist, glm::normalize(&(ray.at(dist) - self.center)), &self.material)
-                )
-            } else {
-                None
-            }
-        } else {
-            None
+        let d = b*b - 4.0*c; // discriminant
+        if d < 0.0 {
+            return None;
         }
+        let d = d.sqrt();
+        Some(((-b - d) / 2.0, (-b + d) / 2.0))
+    }
+
+    fn hit_at(&self, ray: &Ray, dist: Scalar) -> Hit {
+        let normal = glm::normalize(&(ray.at(dist) - self.center));
+        let mut hit = Hit::new(dist, normal, &self.material);
+        hit.uv = Self::uv(&normal);
+        hit.interior = self.interior.clone();
+        // Around the y axis, undefined at the poles
+        hit.tangent = Some(Vec3::new(-normal.z, 0.0, normal.x)).filter(|t| glm::length2(t) > 0.0);
+        let sin_theta = (normal.x * normal.x + normal.z * normal.z).sqrt();
+        if sin_theta > 0.0 {
+            let r = self.radius;
+            let dpdu = Vec3::new(-normal.z, 0.0, normal.x) * (2.0 * consts::PI * r);
+            let dpdv = Vec3::new(normal.y * normal.x / sin_theta, -sin_theta, normal.y * normal.z / sin_theta) * (consts::PI * r);
+            hit.uv_derivatives = Some((dpdu, dpdv));
+        }
+        hit
+    }
+
+    /// Spherical coordinates of a point on the unit sphere: u wraps around the y axis, v runs from the top pole to the bottom
+    fn uv(n: &Vec3) -> Vec2 {
+        let u = 0.5 + n.z.atan2(n.x) / (2.0 * consts::PI);
+        let v = glm::clamp_scalar(n.y, -1.0, 1.0).acos() / consts::PI;
+        Vec2::new(u, v)
+    }
+}
+impl Primitive for Sphere {
+    fn nearest_intersection(&self, ray: &Ray) -> Option<Hit> {
+        self.nearest_intersection_within(ray, 0.0, Scalar::INFINITY)
+    }
+
+    fn nearest_intersection_within(&self, ray: &Ray, t_min: Scalar, t_max: Scalar) -> Option<Hit> {
+        // Closest point in range along the ray
+        let (dist1, dist2) = self.roots(ray)?;
+        [dist1, dist2].iter()
+            .find(|t| (t_min..=t_max).contains(*t))
+            .map(|&t| self.hit_at(ray, t))
+    }
+
+    fn intersects(&self, ray: &Ray, t_max: Scalar) -> bool {
+        self.roots(ray).is_some_and(|(dist1, dist2)| (0.0..=t_max).contains(&dist1) || (0.0..=t_max).contains(&dist2))
+    }
+
+    fn all_intersections(&self, ray: &Ray, t_min: Scalar, t_max: Scalar) -> Vec<Hit> {
+        match self.roots(ray) {
+            Some((dist1, dist2)) => [dist1, dist2].iter()
+                .filter(|t| (t_min..=t_max).contains(*t))
+                .map(|&t| self.hit_at(ray, t))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn material(&self) -> Option<&Shared<Material>> {
+        Some(&self.material)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::math::*;
-    use crate::{Ray, Material};
+    use crate::{Ray, RayDifferential, Material, Color3};
     use super::{Primitive, Sphere};
     use nalgebra_glm as glm;
     use std::sync::Arc as Shared;
@@ -96,4 +144,64 @@ mod tests {
         assert!((hit.distance - 10.0).abs() <= consts::EPSILON);
         assert!(glm::distance(&hit.normal, &Vec3::new(0.0, 0.0, -1.0)) <= consts::EPSILON);
     }
+
+    #[test]
+    fn sphere_uv_poles_and_seam() {
+        let sphere = Sphere::new(*consts::ORIGIN, 1.0, &Shared::new(Material::default()));
+        let top = sphere.nearest_intersection(&Ray::new(Vec3::new(0.0, 2.0, 0.0), *consts::DOWN)).unwrap();
+        assert!(top.uv.y.abs() <= consts::EPSILON);
+        let front = sphere.nearest_intersection(&Ray::new(Vec3::new(0.0, 0.0, -2.0), *consts::FORWARD)).unwrap();
+        assert!((front.uv.x - 0.25).abs() <= consts::EPSILON);
+        assert!((front.uv.y - 0.5).abs() <= consts::EPSILON);
+    }
+
+    #[test]
+    fn sphere_queries_respect_the_range() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -2.0), *consts::FORWARD);
+        let sphere = Sphere::new(*consts::ORIGIN, 1.0, &Shared::new(Material::default()));
+        let hits = sphere.all_intersections(&ray, 0.0, Scalar::INFINITY);
+        assert_eq!(hits.len(), 2);
+        assert!((hits[0].distance - 1.0).abs() <= consts::EPSILON && (hits[1].distance - 3.0).abs() <= consts::EPSILON);
+        assert_eq!(sphere.all_intersections(&ray, 2.0, 10.0).len(), 1);
+
+        // Starting past the front skips to the back
+        let back = sphere.nearest_intersection_within(&ray, 1.5, 10.0).unwrap();
+        assert!((back.distance - 3.0).abs() <= consts::EPSILON);
+        assert!(sphere.nearest_intersection_within(&ray, 0.0, 0.5).is_none());
+        assert!(sphere.intersects(&ray, 1.5));
+        assert!(!sphere.intersects(&ray, 0.5));
+    }
+
+    #[test]
+    fn sphere_footprint_from_differentials() {
+        // Rays 0.01 apart, hitting the side of the sphere at the equator, where u runs along x
+        let sphere = Sphere::new(*consts::ORIGIN, 1.0, &Shared::new(Material::default()));
+        let origin = Vec3::new(0.0, 0.0, -2.0);
+        let ray = Ray::new(origin, *consts::FORWARD).with_differential(Some(RayDifferential {
+            rx_origin: origin + Vec3::new(0.01, 0.0, 0.0),
+            rx_direction: *consts::FORWARD,
+            ry_origin: origin + Vec3::new(0.0, 0.01, 0.0),
+            ry_direction: *consts::FORWARD,
+        }));
+        let hit = sphere.nearest_intersection(&ray).unwrap();
+        let footprint = hit.footprint_of(&ray).unwrap();
+        assert!((footprint.dpdx - Vec3::new(0.01, 0.0, 0.0)).norm() <= 1.0e-6);
+        // The equator is 2 pi long in u, and pole to pole is pi in v, with v increasing downwards
+        assert!((footprint.duvdx.x.abs() - 0.01 / (2.0 * consts::PI)).abs() <= 1.0e-6, "{:?}", footprint);
+        assert!(footprint.duvdx.y.abs() <= 1.0e-6);
+        assert!((footprint.duvdy.y + 0.01 / consts::PI).abs() <= 1.0e-6, "{:?}", footprint);
+        assert!(hit.footprint_of(&Ray::new(origin, *consts::FORWARD)).is_none());
+    }
+
+    #[test]
+    fn tinted_glass_shares_one_interior() {
+        let glass = Material { transmission: 1.0, transmittance: Color3::new(0.5, 1.0, 1.0), ..Material::default() };
+        let sphere = Sphere::new(*consts::ORIGIN, 1.0, &Shared::new(glass));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, -2.0), *consts::FORWARD);
+        let hits = sphere.all_intersections(&ray, 0.0, 10.0);
+        assert_eq!(hits.len(), 2);
+        let interiors: Vec<_> = hits.iter().map(|h| h.interior.clone().unwrap()).collect();
+        assert!(Shared::ptr_eq(&interiors[0], &interiors[1]));
+        assert!(Sphere::new(*consts::ORIGIN, 1.0, &Shared::new(Material::default())).interior.is_none());
+    }
 }
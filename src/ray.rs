@@ -1,20 +1,57 @@
 use crate::math::*;
-use crate::Material;
+use crate::{Material, Medium};
+use nalgebra_glm as glm;
+#[cfg(feature="spectral")]
+use crate::spectrum::Wavelengths;
 use std::sync::Arc as Shared;
 
 #[derive(Debug)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// The medium the ray travels through, if any
+    pub medium: Option<Shared<Medium>>,
+    /// The wavelengths the ray's path carries, or None to have the integrator choose them
+    #[cfg(feature="spectral")]
+    pub wavelengths: Option<Wavelengths>,
+    /// The rays one pixel over, for working out how much of a surface the ray covers
+    pub differential: Option<RayDifferential>,
 }
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
         Ray {
             origin: origin,
             direction: direction,
+            medium: None,
+            #[cfg(feature="spectral")]
+            wavelengths: None,
+            differential: None,
         }
     }
 
+    /// A ray carrying on along the same path from somewhere else, through `medium`. Differentials are left behind,
+    /// since they depend on how the path turned.
+    pub fn continued(&self, origin: Vec3, direction: Vec3, medium: Option<Shared<Medium>>) -> Self {
+        Ray {
+            origin,
+            direction,
+            medium,
+            #[cfg(feature="spectral")]
+            wavelengths: self.wavelengths,
+            differential: None,
+        }
+    }
+
+    pub fn with_differential(mut self, differential: Option<RayDifferential>) -> Self {
+        self.differential = differential;
+        self
+    }
+
+    pub fn in_medium(mut self, medium: Option<Shared<Medium>>) -> Self {
+        self.medium = medium;
+        self
+    }
+
     pub fn at(&self, distance: Scalar) -> Vec3 {
         self.origin + self.direction*distance
     }
@@ -25,7 +62,18 @@ pub struct Hit {
     pub distance: Scalar,
     pub normal: Vec3,
     pub material: Shared<Material>,
-    // TODO: more information (primitive id, etc)
+    /// Surface parameterization, in [0, 1] for primitives that define one
+    pub uv: Vec2,
+    /// Index of the primitive in `World::primitives`. Filled in by `World::cast`.
+    pub object_id: usize,
+    /// Direction of increasing u along the surface, for primitives that define one
+    pub tangent: Option<Vec3>,
+    /// The medium inside the primitive, for primitives that enclose one
+    pub interior: Option<Shared<Medium>>,
+    /// Partial derivatives of the position with respect to u and v, for primitives that define them
+    pub uv_derivatives: Option<(Vec3, Vec3)>,
+    /// How much of the surface the ray covers, if it carried differentials. Filled in by `World::cast`.
+    pub footprint: Option<Footprint>,
 }
 impl Hit {
     pub fn new(distance: Scalar, normal: Vec3, material: &Shared<Material>) -> Self {
@@ -33,6 +81,120 @@ impl Hit {
             distance: distance,
             normal: normal,
             material: material.clone(),
+            uv: Vec2::new(0.0, 0.0),
+            object_id: 0,
+            tangent: None,
+            interior: None,
+            uv_derivatives: None,
+            footprint: None,
+        }
+    }
+
+    /**
+     * Where the ray's differentials meet the plane tangent to the hit, and how far that is in uv
+     *
+     * Follows Igehy, "Tracing Ray Differentials". None if the ray has no differentials, or they run parallel to
+     * the surface.
+     */
+    pub fn footprint_of(&self, ray: &Ray) -> Option<Footprint> {
+        let rd = ray.differential.as_ref()?;
+        let point = ray.at(self.distance);
+        let plane = glm::dot(&self.normal, &point);
+        let offset = |origin: &Vec3, direction: &Vec3| {
+            let cos = glm::dot(&self.normal, direction);
+            if cos.abs() <= consts::EPSILON {
+                return None;
+            }
+            let t = (plane - glm::dot(&self.normal, origin)) / cos;
+            Some(origin + direction * t - point)
+        };
+        let dpdx = offset(&rd.rx_origin, &rd.rx_direction)?;
+        let dpdy = offset(&rd.ry_origin, &rd.ry_direction)?;
+
+        // Least squares fit of the offsets to the uv derivatives
+        let (duvdx, duvdy) = match self.uv_derivatives {
+            Some((dpdu, dpdv)) => {
+                let (a, b, c) = (glm::dot(&dpdu, &dpdu), glm::dot(&dpdu, &dpdv), glm::dot(&dpdv, &dpdv));
+                let det = a * c - b * b;
+                if det.abs() <= consts::EPSILON * a * c {
+                    (Vec2::zeros(), Vec2::zeros())
+                } else {
+                    let solve = |d: &Vec3| {
+                        let (pu, pv) = (glm::dot(&dpdu, d), glm::dot(&dpdv, d));
+                        Vec2::new(c * pu - b * pv, a * pv - b * pu) / det
+                    };
+                    (solve(&dpdx), solve(&dpdy))
+                }
+            }
+            None => (Vec2::zeros(), Vec2::zeros()),
+        };
+        Some(Footprint { dpdx, dpdy, duvdx, duvdy })
+    }
+}
+
+/**
+ * The origins and directions of two rays offset from a main ray by one pixel in x and in y
+ *
+ * Tracking them alongside the main ray shows how quickly its footprint grows, which textures use to choose how
+ * much to blur.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct RayDifferential {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
+}
+impl RayDifferential {
+    /// Moves the offset rays towards or away from `ray` by a factor, e.g. to cover a fraction of a pixel per sample
+    pub fn scaled(&self, ray: &Ray, scale: Scalar) -> Self {
+        RayDifferential {
+            rx_origin: ray.origin + (self.rx_origin - ray.origin) * scale,
+            rx_direction: ray.direction + (self.rx_direction - ray.direction) * scale,
+            ry_origin: ray.origin + (self.ry_origin - ray.origin) * scale,
+            ry_direction: ray.direction + (self.ry_direction - ray.direction) * scale,
         }
     }
+
+    /**
+     * The differentials of a ray leaving a hit in `direction`, after specular reflection or refraction, or going
+     * straight through
+     *
+     * The surface is treated as flat across the footprint, so curved mirrors and lenses spread or focus the
+     * footprint less than they should.
+     */
+    pub fn scattered(&self, incoming: &Vec3, normal: &Vec3, footprint: &Footprint, point: &Vec3, direction: &Vec3) -> Option<Self> {
+        let cos_i = glm::dot(incoming, normal);
+        let cos_o = glm::dot(direction, normal);
+        let bend = |d: &Vec3| -> Option<Vec3> {
+            if cos_i * cos_o > 0.0 {
+                // Straight on through, or refracted: Snell's law with the ratio of the main ray's sines
+                let sin_i = (1.0 - cos_i * cos_i).max(0.0).sqrt();
+                let sin_o = (1.0 - cos_o * cos_o).max(0.0).sqrt();
+                if sin_i <= consts::EPSILON {
+                    return Some(*d);
+                }
+                let facing = if cos_i < 0.0 { *normal } else { -normal };
+                let refracted = glm::refract_vec(&glm::normalize(d), &facing, sin_o / sin_i);
+                Some(refracted).filter(|r| glm::length2(r) > 0.0)
+            } else {
+                Some(d - normal * (2.0 * glm::dot(d, normal)))
+            }
+        };
+        Some(RayDifferential {
+            rx_origin: point + footprint.dpdx,
+            rx_direction: bend(&self.rx_direction)?,
+            ry_origin: point + footprint.dpdy,
+            ry_direction: bend(&self.ry_direction)?,
+        })
+    }
+}
+
+/// How far across a surface the footprint of a ray reaches, from one pixel over in x and in y
+#[derive(Debug, Copy, Clone)]
+pub struct Footprint {
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub duvdx: Vec2,
+    pub duvdy: Vec2,
 }
\ No newline at end of file
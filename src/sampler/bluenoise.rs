@@ -0,0 +1,145 @@
+use crate::math::*;
+use super::{Sampler, hash_keys, permute};
+use super::halton::wrap;
+use lazy_static::lazy_static;
+
+/// Width and height of the tiled mask
+const SIZE: usize = 64;
+/// Width of the Gaussian used to measure how clustered the mask's points are
+const SIGMA: f32 = 1.5;
+
+lazy_static! {
+    static ref MASK: Vec<u32> = void_and_cluster(SIZE, 0x5eed);
+}
+
+/**
+ * Values from a tiled blue noise mask, so that neighbouring pixels get very different values
+ *
+ * The error left at low sample counts looks like fine, even grain rather than clumps. Each dimension
+ * reads the mask at a different toroidal offset, and successive samples add multiples of the golden
+ * ratio (a Cranley-Patterson rotation) so each pixel's values stay well spread over time.
+ */
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BlueNoise {
+    pub seed: u32,
+}
+impl BlueNoise {
+    pub fn new(seed: u32) -> Self {
+        BlueNoise {
+            seed,
+        }
+    }
+}
+impl Sampler for BlueNoise {
+    fn sample(&self, pixel: (usize, usize), index: usize, dimension: usize) -> Scalar {
+        const GOLDEN: f64 = 0.618_033_988_749_894_9;
+        let key = hash_keys(self.seed, &[dimension]) as usize;
+        let x = (pixel.0 + key) % SIZE;
+        let y = (pixel.1 + (key >> 16)) % SIZE;
+        let rank = MASK[y * SIZE + x] as f64;
+        let v = (rank + 0.5) / (SIZE * SIZE) as f64 + index as f64 * GOLDEN;
+        wrap(v - v.floor())
+    }
+}
+
+/**
+ * Ranks every cell of a size x size torus with Ulichney's void-and-cluster method
+ *
+ * Starting from a few well spread points, cells are ranked by repeatedly removing the point in the tightest
+ * cluster (for ranks below the starting count) or filling the largest void (for ranks above it).
+ * Filling voids all the way up, rather than switching to clusters of empty cells past the halfway mark,
+ * is a common simplification which makes little visible difference.
+ */
+fn void_and_cluster(size: usize, seed: u32) -> Vec<u32> {
+    let n = size * size;
+    let kernel: Vec<f32> = (0..n).map(|i| {
+        let dx = (i % size).min(size - i % size) as f32;
+        let dy = (i / size).min(size - i / size) as f32;
+        (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+    }).collect();
+
+    let toggle = |points: &mut Vec<bool>, energy: &mut Vec<f32>, i: usize| {
+        points[i] = !points[i];
+        let sign = if points[i] { 1.0 } else { -1.0 };
+        let (x, y) = (i % size, i / size);
+        for (j, e) in energy.iter_mut().enumerate() {
+            let dx = (j % size + size - x) % size;
+            let dy = (j / size + size - y) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    // Highest energy among points, or lowest among empty cells. Ties go to the first cell, to stay deterministic.
+    let tightest_cluster = |points: &[bool], energy: &[f32]| {
+        (0..n).filter(|&i| points[i]).fold(None, |best: Option<usize>, i| match best {
+            Some(b) if energy[b] >= energy[i] => Some(b),
+            _ => Some(i),
+        }).unwrap()
+    };
+    let largest_void = |points: &[bool], energy: &[f32]| {
+        (0..n).filter(|&i| !points[i]).fold(None, |best: Option<usize>, i| match best {
+            Some(b) if energy[b] <= energy[i] => Some(b),
+            _ => Some(i),
+        }).unwrap()
+    };
+
+    // A random initial pattern of a tenth of the cells, relaxed until moving the worst point doesn't help
+    let mut points = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let initial = n / 10;
+    for k in 0..initial {
+        toggle(&mut points, &mut energy, permute(k as u32, n as u32, seed) as usize);
+    }
+    loop {
+        let cluster = tightest_cluster(&points, &energy);
+        toggle(&mut points, &mut energy, cluster);
+        let void = largest_void(&points, &energy);
+        toggle(&mut points, &mut energy, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0u32; n];
+    let (mut removing, mut removing_energy) = (points.clone(), energy.clone());
+    for r in (0..initial).rev() {
+        let cluster = tightest_cluster(&removing, &removing_energy);
+        toggle(&mut removing, &mut removing_energy, cluster);
+        rank[cluster] = r as u32;
+    }
+    for r in initial..n {
+        let void = largest_void(&points, &energy);
+        toggle(&mut points, &mut energy, void);
+        rank[void] = r as u32;
+    }
+    rank
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_ranks_every_cell_once() {
+        let mut seen = vec![false; SIZE * SIZE];
+        for &r in MASK.iter() {
+            seen[r as usize] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+    }
+
+    #[test]
+    fn neighbours_differ() {
+        // Blue noise has little low-frequency energy, so adjacent cells are much further apart than white noise would be
+        let n = (SIZE * SIZE) as f64;
+        let mut total = 0.0;
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let a = MASK[y * SIZE + x] as f64;
+                let b = MASK[y * SIZE + (x + 1) % SIZE] as f64;
+                total += (a - b).abs() / n;
+            }
+        }
+        // White noise would average a third
+        assert!(total / n > 0.36);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::math::*;
+use super::{Sampler, hash_keys, to_unit};
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+/**
+ * The Halton sequence, with dimension d in the radical inverse base of the d-th prime
+ *
+ * Every pixel gets its own random toroidal shift (a Cranley-Patterson rotation) of the same sequence.
+ * Dimensions past the table of primes fall back to independent random values.
+ */
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Halton {
+    pub seed: u32,
+}
+impl Halton {
+    pub fn new(seed: u32) -> Self {
+        Halton {
+            seed,
+        }
+    }
+}
+impl Sampler for Halton {
+    fn sample(&self, pixel: (usize, usize), index: usize, dimension: usize) -> Scalar {
+        let key = hash_keys(self.seed, &[pixel.0, pixel.1, dimension]);
+        if dimension >= PRIMES.len() {
+            return to_unit(hash_keys(key, &[index]));
+        }
+        let x = radical_inverse(PRIMES[dimension], index as u64) + to_unit(key) as f64;
+        wrap(x - x.floor())
+    }
+}
+
+/// The digits of i in the given base, mirrored about the radix point
+fn radical_inverse(base: u32, mut i: u64) -> f64 {
+    let base = base as u64;
+    let inv = 1.0 / base as f64;
+    let mut reversed = 0u64;
+    let mut scale = 1.0;
+    while i > 0 {
+        reversed = reversed * base + i % base;
+        scale *= inv;
+        i /= base;
+    }
+    reversed as f64 * scale
+}
+
+/// Rounds a value in [0, 1) to a Scalar that is still below one
+pub(super) fn wrap(x: f64) -> Scalar {
+    let x = x as Scalar;
+    if x >= 1.0 { 0.0 } else { x }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::radical_inverse;
+
+    #[test]
+    fn radical_inverse_mirrors_digits() {
+        let base2: Vec<f64> = (0..5).map(|i| radical_inverse(2, i)).collect();
+        assert_eq!(base2, vec![0.0, 0.5, 0.25, 0.75, 0.125]);
+        assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() <= 1.0e-12);
+    }
+}
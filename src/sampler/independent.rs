@@ -0,0 +1,20 @@
+use crate::math::*;
+use super::{Sampler, hash_keys, to_unit};
+
+/// Uncorrelated pseudorandom values. Converges the slowest, but makes no assumptions about sample counts.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Independent {
+    pub seed: u32,
+}
+impl Independent {
+    pub fn new(seed: u32) -> Self {
+        Independent {
+            seed,
+        }
+    }
+}
+impl Sampler for Independent {
+    fn sample(&self, pixel: (usize, usize), index: usize, dimension: usize) -> Scalar {
+        to_unit(hash_keys(self.seed, &[pixel.0, pixel.1, index, dimension]))
+    }
+}
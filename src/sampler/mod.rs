@@ -0,0 +1,161 @@
+mod independent;
+mod stratified;
+mod halton;
+mod sobol;
+mod bluenoise;
+pub use independent::Independent;
+pub use stratified::Stratified;
+pub use halton::Halton;
+pub use sobol::Sobol;
+pub use bluenoise::BlueNoise;
+
+use crate::math::*;
+
+/**
+ * A source of sample points in the unit hypercube
+ *
+ * Each value is a pure function of the seed, the pixel, the sample index within the pixel and the
+ * dimension, so images come out bit-identical however the work is split between threads.
+ * Dimensions are handed out in order by a `SampleStream`: the first two position the camera ray within its pixel.
+ */
+pub trait Sampler {
+    /// Component `dimension` of sample `index` in pixel (x, y), in [0, 1)
+    fn sample(&self, pixel: (usize, usize), index: usize, dimension: usize) -> Scalar;
+
+    /// Components `dimension` and `dimension + 1` together, for samplers that stratify them jointly
+    fn sample_2d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> (Scalar, Scalar) {
+        (self.sample(pixel, index, dimension), self.sample(pixel, index, dimension + 1))
+    }
+}
+
+/// Consecutive dimensions of one sample in one pixel
+pub struct SampleStream<'a> {
+    sampler: &'a (dyn Sampler + Send + Sync),
+    pixel: (usize, usize),
+    index: usize,
+    dimension: usize,
+}
+impl<'a> SampleStream<'a> {
+    pub fn new(sampler: &'a (dyn Sampler + Send + Sync), pixel: (usize, usize), index: usize) -> Self {
+        SampleStream {
+            sampler,
+            pixel,
+            index,
+            dimension: 0,
+        }
+    }
+
+    pub fn next_1d(&mut self) -> Scalar {
+        let x = self.sampler.sample(self.pixel, self.index, self.dimension);
+        self.dimension += 1;
+        x
+    }
+
+    pub fn next_2d(&mut self) -> (Scalar, Scalar) {
+        let xy = self.sampler.sample_2d(self.pixel, self.index, self.dimension);
+        self.dimension += 2;
+        xy
+    }
+}
+
+/// PCG-based integer hash, from Jarzynski and Olano's "Hash Functions for GPU Rendering"
+pub(crate) fn hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
+
+/// Hashes a seed together with any number of keys
+pub(crate) fn hash_keys(seed: u32, keys: &[usize]) -> u32 {
+    keys.iter().fold(hash(seed), |h, &k| hash(h ^ k as u32))
+}
+
+/// Maps the high 24 bits of an integer to [0, 1), which is exact at either precision
+#[inline]
+pub(crate) fn to_unit(x: u32) -> Scalar {
+    (x >> 8) as Scalar / (1u32 << 24) as Scalar
+}
+
+/// Element i of a pseudorandom permutation of 0..len chosen by the seed, from Kensler's "Correlated Multi-Jittered Sampling"
+pub(crate) fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    // Cycle-walk until the hashed index falls back inside the range
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc as Shared;
+
+    fn samplers() -> Vec<Shared<dyn Sampler + Send + Sync>> {
+        vec![
+            Shared::new(Independent::new(7)),
+            Shared::new(Stratified::new(7, 16)),
+            Shared::new(Halton::new(7)),
+            Shared::new(Sobol::new(7)),
+            Shared::new(BlueNoise::new(7)),
+        ]
+    }
+
+    #[test]
+    fn samples_are_in_range_and_repeatable() {
+        for sampler in samplers() {
+            for &pixel in [(0, 0), (5, 3), (1000, 700)].iter() {
+                for index in 0..40 {
+                    for dimension in 0..70 {
+                        let x = sampler.sample(pixel, index, dimension);
+                        assert!((0.0..1.0).contains(&x));
+                        assert_eq!(x, sampler.sample(pixel, index, dimension));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn seeds_decorrelate() {
+        let a = Sobol::new(1);
+        let b = Sobol::new(2);
+        assert!((0..16).any(|i| a.sample((3, 4), i, 0) != b.sample((3, 4), i, 0)));
+    }
+
+    #[test]
+    fn permute_is_a_permutation() {
+        for &len in [1, 7, 64, 100].iter() {
+            let mut seen = vec![false; len as usize];
+            for i in 0..len {
+                seen[permute(i, len, 0xdead_beef) as usize] = true;
+            }
+            assert!(seen.iter().all(|&s| s));
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use crate::math::*;
+use super::{Sampler, hash_keys, to_unit};
+use lazy_static::lazy_static;
+
+/// Degree, polynomial coefficients and initial direction numbers for dimensions 1 onwards, from Joe and Kuo's new-joe-kuo-6.21201.
+/// Dimension 0 is the van der Corput sequence.
+const PRIMITIVES: [(u32, u32, &[u32]); 15] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+];
+const DIMENSIONS: usize = PRIMITIVES.len() + 1;
+
+lazy_static! {
+    /// 32 direction numbers per dimension, as binary fractions
+    static ref DIRECTIONS: Vec<[u32; 32]> = {
+        let mut table = vec![[0u32; 32]; DIMENSIONS];
+        for (k, v) in table[0].iter_mut().enumerate() {
+            *v = 1 << (31 - k);
+        }
+        for (d, &(s, a, m)) in PRIMITIVES.iter().enumerate() {
+            let v = &mut table[d + 1];
+            let s = s as usize;
+            for k in 0..32 {
+                v[k] = if k < s {
+                    m[k] << (31 - k)
+                } else {
+                    let mut x = v[k - s] ^ (v[k - s] >> s);
+                    for j in 1..s {
+                        if (a >> (s - 1 - j)) & 1 == 1 {
+                            x ^= v[k - j];
+                        }
+                    }
+                    x
+                };
+            }
+        }
+        table
+    };
+}
+
+/**
+ * The Sobol sequence, Owen-scrambled per pixel
+ *
+ * Scrambling keeps the sequence's stratification, so any power-of-two prefix of a pixel's samples is
+ * well distributed. Dimensions past the table of direction numbers fall back to independent random values.
+ */
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Sobol {
+    pub seed: u32,
+}
+impl Sobol {
+    pub fn new(seed: u32) -> Self {
+        Sobol {
+            seed,
+        }
+    }
+}
+impl Sampler for Sobol {
+    fn sample(&self, pixel: (usize, usize), index: usize, dimension: usize) -> Scalar {
+        let key = hash_keys(self.seed, &[pixel.0, pixel.1, dimension]);
+        if dimension >= DIMENSIONS {
+            return to_unit(hash_keys(key, &[index]));
+        }
+        to_unit(owen_scramble(sobol(dimension, index as u32), key))
+    }
+}
+
+/// Unscrambled component of point i, as a 32-bit binary fraction
+fn sobol(dimension: usize, mut i: u32) -> u32 {
+    let v = &DIRECTIONS[dimension];
+    let mut x = 0;
+    let mut k = 0;
+    while i != 0 {
+        if i & 1 == 1 {
+            x ^= v[k];
+        }
+        i >>= 1;
+        k += 1;
+    }
+    x
+}
+
+/// Hash-based nested uniform scrambling, from Burley's "Practical Hash-based Owen Scrambling".
+/// Each bit is flipped depending only on the bits above it, which keeps elementary intervals intact.
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the first 2^m points of the 2D projection put one point in every elementary interval of that area
+    fn is_net(points: &[(u32, u32)], m: u32) -> bool {
+        (0..=m).all(|bits_x| {
+            let bits_y = m - bits_x;
+            let mut cells = vec![0; 1 << m];
+            for &(x, y) in points {
+                let cx = x.checked_shr(32 - bits_x).unwrap_or(0);
+                let cy = y.checked_shr(32 - bits_y).unwrap_or(0);
+                cells[((cy << bits_x) | cx) as usize] += 1;
+            }
+            cells.iter().all(|&c| c == 1)
+        })
+    }
+
+    #[test]
+    fn scrambled_points_are_stratified() {
+        let m = 6;
+        // The first two dimensions together form a (0, m, 2)-net
+        let points: Vec<(u32, u32)> = (0..1 << m)
+            .map(|i| (owen_scramble(sobol(0, i), 17), owen_scramble(sobol(1, i), 99)))
+            .collect();
+        assert!(is_net(&points, m));
+        // And every dimension alone puts one point in each of 2^m intervals
+        for d in 0..DIMENSIONS {
+            let mut cells = vec![0; 1 << m];
+            for i in 0..1 << m {
+                cells[(owen_scramble(sobol(d, i), 5) >> (32 - m)) as usize] += 1;
+            }
+            assert!(cells.iter().all(|&c| c == 1), "dimension {}", d);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::math::*;
+use super::{Sampler, hash_keys, permute, to_unit};
+
+/**
+ * Jittered samples, one per stratum of a grid sized for the expected sample count
+ *
+ * Each pixel and dimension visits the strata in its own shuffled order. Samples past `samples_per_pixel`
+ * start another round of the strata with a fresh shuffle, so progressive renders keep converging.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct Stratified {
+    pub seed: u32,
+    pub samples_per_pixel: usize,
+}
+impl Stratified {
+    pub fn new(seed: u32, samples_per_pixel: usize) -> Self {
+        Stratified {
+            seed,
+            samples_per_pixel: samples_per_pixel.max(1),
+        }
+    }
+
+    /// Which stratum sample `index` falls in, out of `strata`, and the value to jitter within it
+    fn stratum(&self, pixel: (usize, usize), index: usize, dimension: usize, strata: usize) -> (usize, u32) {
+        let round = index / strata;
+        let shuffle = hash_keys(self.seed, &[pixel.0, pixel.1, dimension, round]);
+        let stratum = permute((index % strata) as u32, strata as u32, shuffle) as usize;
+        (stratum, hash_keys(shuffle, &[index]))
+    }
+}
+impl Sampler for Stratified {
+    fn sample(&self, pixel: (usize, usize), index: usize, dimension: usize) -> Scalar {
+        let n = self.samples_per_pixel;
+        let (stratum, jitter) = self.stratum(pixel, index, dimension, n);
+        (stratum as Scalar + to_unit(jitter)) / n as Scalar
+    }
+
+    fn sample_2d(&self, pixel: (usize, usize), index: usize, dimension: usize) -> (Scalar, Scalar) {
+        // The squarest grid with at least as many cells as samples
+        let nx = (self.samples_per_pixel as f64).sqrt().floor().max(1.0) as usize;
+        let ny = self.samples_per_pixel.div_ceil(nx);
+        let (stratum, jitter) = self.stratum(pixel, index, dimension, nx * ny);
+        let jitter_y = super::hash(jitter);
+        (
+            ((stratum % nx) as Scalar + to_unit(jitter)) / nx as Scalar,
+            ((stratum / nx) as Scalar + to_unit(jitter_y)) / ny as Scalar,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_stratum_is_hit_once() {
+        let sampler = Stratified::new(3, 16);
+        let mut cells = [0; 16];
+        for i in 0..16 {
+            let (x, y) = sampler.sample_2d((9, 2), i, 0);
+            cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&c| c == 1));
+
+        let mut strata = [0; 16];
+        for i in 16..32 {
+            strata[(sampler.sample((9, 2), i, 2) * 16.0) as usize] += 1;
+        }
+        assert!(strata.iter().all(|&c| c == 1));
+    }
+}
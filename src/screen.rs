@@ -1,76 +1,705 @@
 use crate::math::*;
-use crate::{Camera, World, Ray, Color3};
-use nalgebra_glm as glm;
+use crate::{Camera, World, Ray, ToneMapper, Framebuffer, AovSelection, ColorSpace, Denoiser, PostEffect};
+use crate::control::{RenderControl, Rendered, RenderHandle};
+use crate::integrator;
+use crate::sampler::{Sampler, SampleStream, Sobol};
+use crate::filter::{Filter, BoxFilter};
+use crate::film::{Film, PixelStats};
+use crate::world::MaterialIds;
+use crate::tile::{self, Rect, TileOrder};
 use rgb::RGB8;
 #[cfg(feature="parallel")]
 use rayon::prelude::*;
 
-use std::iter::Iterator as Iter;
+use std::ops::Range;
+use std::sync::{Arc as Shared, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
+/**
+ * When to stop a progressive render
+ *
+ * The render stops as soon as any of the set limits is reached, and always runs at least one pass.
+ * With no limits set, it stops after a single pass.
+ */
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct StopCondition {
+    /// Samples per pixel
+    pub samples: Option<usize>,
+    pub time: Option<Duration>,
+    /// Mean relative standard error of the pixels, e.g. 0.01 for 1%
+    pub noise: Option<f32>,
+}
+impl StopCondition {
+    pub fn samples(n: usize) -> Self {
+        StopCondition { samples: Some(n), ..Default::default() }
+    }
+    pub fn time(budget: Duration) -> Self {
+        StopCondition { time: Some(budget), ..Default::default() }
+    }
+    pub fn noise(target: f32) -> Self {
+        StopCondition { noise: Some(target), ..Default::default() }
+    }
+
+    fn is_met(&self, info: &PassInfo) -> bool {
+        if self.samples.is_none() && self.time.is_none() && self.noise.is_none() {
+            return true;
+        }
+        self.samples.is_some_and(|n| info.samples_per_pixel >= n)
+            || self.time.is_some_and(|t| info.elapsed >= t)
+            || self.noise.is_some_and(|n| info.noise <= n)
+    }
+
+    /// Best guess at the number of passes a render will take, from its state after its latest pass.
+    /// Assumes the time per pass stays the same and noise falls with the square root of the sample count.
+    fn expected_passes(&self, info: &PassInfo) -> usize {
+        let done = info.samples_per_pixel;
+        if self.samples.is_none() && self.time.is_none() && self.noise.is_none() {
+            return 1;
+        }
+        let mut estimates = vec![];
+        if let Some(n) = self.samples {
+            estimates.push(n as f64);
+        }
+        if done > 0 {
+            if let Some(t) = self.time {
+                let elapsed = info.elapsed.as_secs_f64();
+                if elapsed > 0.0 {
+                    estimates.push(done as f64 * t.as_secs_f64() / elapsed);
+                }
+            }
+            if let Some(target) = self.noise {
+                if info.noise.is_finite() && target > 0.0 {
+                    let ratio = (info.noise / target) as f64;
+                    estimates.push(done as f64 * ratio * ratio);
+                }
+            }
+        }
+        let estimate = estimates.into_iter().fold(f64::INFINITY, f64::min);
+        if estimate.is_finite() {
+            (estimate.ceil() as usize).max(done + 1)
+        } else {
+            done + 1
+        }
+    }
+}
+
+/**
+ * Settings for spending samples where the image is noisiest
+ *
+ * Every pixel gets at least `min_samples`. After that, a pixel keeps getting samples until its
+ * estimated relative error drops to `threshold` or it reaches `max_samples`.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveSampling {
+    pub min_samples: usize,
+    pub max_samples: usize,
+    /// Relative standard error of the pixel mean, e.g. 0.01 for 1%
+    pub threshold: f32,
+}
+impl AdaptiveSampling {
+    pub fn new(min_samples: usize, max_samples: usize, threshold: f32) -> Self {
+        // At least two samples are needed to estimate variance
+        let min_samples = min_samples.max(2);
+        AdaptiveSampling {
+            min_samples,
+            max_samples: max_samples.max(min_samples),
+            threshold,
+        }
+    }
+
+    fn needs_samples(&self, stats: &PixelStats) -> bool {
+        let n = stats.count as usize;
+        n < self.min_samples || (n < self.max_samples && stats.relative_error() > self.threshold)
+    }
+}
+
+/// Which samples to take in the pixels of a tile
+#[derive(Clone)]
+enum Schedule<'a> {
+    /// The same range of sample indices in every pixel
+    Uniform(Range<usize>),
+    /// A single sample index, only in pixels flagged in a mask covering the whole image
+    Masked(usize, &'a [bool]),
+    /// Keep sampling each pixel until it converges
+    Adaptive(AdaptiveSampling),
+}
+
+/// State of a progressive render after a pass has been accumulated
+#[derive(Debug, Copy, Clone)]
+pub struct PassInfo {
+    /// The most samples any pixel has received
+    pub samples_per_pixel: usize,
+    /// Pixels that will receive more samples in the next pass. Only less than the image size with adaptive sampling.
+    pub active_pixels: usize,
+    pub elapsed: Duration,
+    /// Mean relative standard error of the pixels; infinite until every pixel has two samples
+    pub noise: f32,
+}
+
+#[derive(Clone)]
 pub struct Screen {
     pub width: usize,
     pub height: usize,
+    /// Applied by `render` when converting the HDR buffer to 8-bit output
+    pub tone_mapper: ToneMapper,
+    /// Extra render passes to produce alongside the beauty image
+    pub aovs: AovSelection,
+    /// Used by `render`, `render_hdr` and `render_tiles` unless adaptive sampling is enabled
+    pub samples_per_pixel: usize,
+    /// Replaces the fixed sample count with per-pixel sample counts driven by variance.
+    /// Progressive renders also stop once every pixel has converged.
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Width and height of the square tiles the image is split into for rendering
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// Only pixels inside this region are rendered, if set. Everything else is left black and transparent.
+    pub region: Option<Rect>,
+    /// Where in each pixel the camera rays go, and the random numbers for everything after
+    pub sampler: Shared<dyn Sampler + Send + Sync>,
+    /// Weighs each sample into the pixels around it. The default box keeps samples to the pixel they were taken in.
+    pub filter: Shared<dyn Filter + Send + Sync>,
+    /// Most surfaces a path may scatter off before it is ended
+    pub max_bounces: usize,
+    /// Cleans up rendered images, including each pass of progressive renders. Renders the normal and albedo AOVs
+    /// to guide it, whether or not they are selected.
+    pub denoiser: Option<Denoiser>,
+    /// Lens and film effects, applied in order to the HDR image after denoising
+    pub post: Vec<PostEffect>,
 }
 impl Screen {
     pub fn new(w: usize, h: usize) -> Self {
         Screen {
             width: w,
             height: h,
+            tone_mapper: ToneMapper::default(),
+            aovs: AovSelection::none(),
+            samples_per_pixel: 1,
+            adaptive: None,
+            tile_size: 32,
+            tile_order: TileOrder::Scanline,
+            region: None,
+            sampler: Shared::new(Sobol::new(0)),
+            filter: Shared::new(BoxFilter::default()),
+            max_bounces: 4,
+            denoiser: None,
+            post: vec![],
         }
     }
 
-    /// Generates rays in a scanline fashion, left to right then top to bottom.
-    /// Returned as either a standard iterator or rayon parallel iterator of Rays, depending on the enabled features.
-    // TODO: antialiasing
-    fn primary_rays(&self, camera: &Camera) -> impl Iter<Item = Ray> {
+    /// The AOVs to render: those selected, and the ones the denoiser needs
+    fn aov_selection(&self) -> AovSelection {
+        match self.denoiser {
+            Some(_) => AovSelection { normal: true, albedo: true, ..self.aovs },
+            None => self.aovs,
+        }
+    }
+
+    /// Denoises a rendered image, if enabled, and drops the AOVs that were only rendered for that.
+    /// Then applies the post-processing effects.
+    fn finish(&self, fb: Framebuffer) -> Framebuffer {
+        let mut fb = match &self.denoiser {
+            Some(denoiser) => {
+                let mut fb = denoiser.denoise(&fb);
+                if !self.aovs.normal {
+                    fb.aovs.normal = None;
+                }
+                if !self.aovs.albedo {
+                    fb.aovs.albedo = None;
+                }
+                fb
+            }
+            None => fb,
+        };
+        for effect in self.post.iter() {
+            effect.apply(&mut fb);
+        }
+        fb
+    }
+
+    /// The region of interest, clipped to the image
+    fn render_region(&self) -> Rect {
+        let full = Rect::new(0, 0, self.width, self.height);
+        match &self.region {
+            Some(r) => full.intersection(r),
+            None => full,
+        }
+    }
+
+    /// Generates a ray through pixel (px, py), where (0, 0) is the top left.
+    /// The offset is the position within the pixel, with (0.5, 0.5) at its center.
+    /// Differentials reach to the neighbouring pixels, narrowed as samples per pixel go up.
+    fn primary_ray(&self, camera: &Camera, px: usize, py: usize, offset: (Scalar, Scalar)) -> Ray {
         // Pixel size
         let dx =  2.0 / (self.width as Scalar);
         let dy = -2.0 / (self.height as Scalar);
-        // Offset
-        let ox = -1.0 + dx / 2.0;
-        let oy =  1.0 - dy / 2.0;
-        // Need to create a copy of these too, so they can be safely moved into the closure
-        let w = self.width;
-        let c = camera.clone();
 
-        let it = (0..self.width*self.height).into_iter();
-        it.map(move |i| {
-            let px = i % w;
-            let py = i / w;
-            let x = ox + dx * (px as Scalar);
-            let y = oy + dy * (py as Scalar);
+        let x = -1.0 + dx * (px as Scalar + offset.0);
+        let y =  1.0 + dy * (py as Scalar + offset.1);
 
-            c.primary_ray(x, y)
-        })
+        let ray = camera.primary_ray_differential(x, y, (dx, dy));
+        let scale = (1.0 / (self.samples_per_pixel.max(1) as Scalar).sqrt()).max(0.125);
+        let differential = ray.differential.map(|d| d.scaled(&ray, scale));
+        ray.with_differential(differential)
     }
 
-    // TODO: trace rays instead of only casting
-    pub fn render(&self, camera: &Camera, world: &World) -> Vec<RGB8> {
-        // floating-point intermediate format, 0-1
-        let black = Color3::new(0.0, 0.0, 0.0);
-        let mut pixels = vec![black; self.width * self.height];
-        let rays = self.primary_rays(camera);
+    /// Traces samples for every pixel in a tile, as scheduled.
+    /// Stops between pixels if the render is cancelled, leaving the rest of the tile empty.
+    fn render_tile(&self, camera: &Camera, world: &World, tile: &Rect, schedule: &Schedule, materials: &MaterialIds,
+                   control: &RenderControl) -> Film {
+        let mut film = Film::new(*tile, &self.render_region(), &self.aov_selection(), &self.filter);
+        for ty in 0..tile.height {
+            for tx in 0..tile.width {
+                if control.is_cancelled() {
+                    return film;
+                }
+                let (px, py) = (tile.x + tx, tile.y + ty);
+                let pixel = py * self.width + px;
+                let i = ty * tile.width + tx;
+                let sample = |film: &mut Film, s: usize| {
+                    let mut stream = SampleStream::new(&*self.sampler, (px, py), s);
+                    let offset = stream.next_2d();
+                    let ray = self.primary_ray(camera, px, py, offset);
+                    let position = (px as Scalar + offset.0, py as Scalar + offset.1);
+                    film.add_sample(i, position, &integrator::trace(world, &ray, &mut stream, self.max_bounces), materials);
+                };
+                match schedule {
+                    Schedule::Uniform(samples) => {
+                        for s in samples.clone() {
+                            sample(&mut film, s);
+                        }
+                    }
+                    Schedule::Masked(s, active) => {
+                        if active[pixel] {
+                            sample(&mut film, *s);
+                        }
+                    }
+                    Schedule::Adaptive(adaptive) => {
+                        while adaptive.needs_samples(&film.stats[i]) {
+                            let s = film.stats[i].count as usize;
+                            sample(&mut film, s);
+                        }
+                    }
+                }
+                control.advance(1);
+            }
+        }
+        film
+    }
+
+    /// Renders every tile of the region of interest into the film, calling `on_tile` as each one finishes.
+    /// Tiles are started in `tile_order`. In parallel builds, each render thread takes the next tile in that order
+    /// as it frees up, so tiles finish in roughly that order.
+    /// Each pixel visited counts as one unit of progress.
+    fn render_samples<F>(&self, camera: &Camera, world: &World, film: &mut Film, schedule: Schedule, control: &RenderControl,
+                         on_tile: &F)
+        where F: Fn(&Rect) + Sync {
+        let materials = world.material_ids();
+        let tiles = tile::tiles(&self.render_region(), self.tile_size, self.tile_order);
+        let film = Mutex::new(film);
+        let next = AtomicUsize::new(0);
 
+        // Rayon may run the jobs in any order, so each one claims the next unstarted tile rather than its own index
         #[cfg(feature="parallel")]
-        let it = rays.zip_eq(pixels.as_mut_slice().into_par_iter());
+        let it = (0..tiles.len()).into_par_iter();
         #[cfg(not(feature="parallel"))]
-        let it = rays.zip(pixels.as_mut_slice().into_iter()); // TODO: zip_eq for std iterators?
-
-        let dir_to_light = glm::normalize(&Vec3::new(1.0, 1.0, 1.0)); // mock directional light
-        it.for_each(|(r, p): (Ray, &mut Color3)| {
-            if let Some(hit) = world.cast(&r) {
-                let color = hit.material.shade(&r, &hit.normal, &dir_to_light);
-                if color.r <= 1.0 && color.g <= 1.0 && color.b <= 1.0 {
-                    *p = color;
-                } else {
-                    dbg!(color);
+        let it = 0..tiles.len();
+        it.for_each(|_| {
+            if control.is_cancelled() {
+                return;
+            }
+            let tile = &tiles[next.fetch_add(1, Ordering::Relaxed)];
+            let rendered = self.render_tile(camera, world, tile, &schedule, &materials, control);
+            film.lock().unwrap().merge(&rendered);
+            if !control.is_cancelled() {
+                on_tile(tile);
+            }
+        });
+    }
+
+    /**
+     * Renders successive one-sample-per-pixel passes into an accumulation buffer until `stop` is met
+     *
+     * After each pass, `on_pass` is called with the current image and the render's progress.
+     * Returns the final image.
+     */
+    pub fn render_progressive<F>(&self, camera: &Camera, world: &World, stop: &StopCondition, on_pass: F) -> Framebuffer
+        where F: FnMut(&Framebuffer, &PassInfo) {
+        self.render_progressive_controlled(camera, world, stop, &RenderControl::new(), on_pass).into_image()
+    }
+
+    /**
+     * Like `render_progressive`, but reports progress through `control` and stops early if it is cancelled
+     *
+     * A cancelled render returns the passes accumulated so far, with some pixels of the interrupted pass
+     * having one more sample than the rest. `on_pass` is not called for the interrupted pass.
+     */
+    pub fn render_progressive_controlled<F>(&self, camera: &Camera, world: &World, stop: &StopCondition,
+                                            control: &RenderControl, mut on_pass: F) -> Rendered
+        where F: FnMut(&Framebuffer, &PassInfo) {
+        let start = Instant::now();
+        let full = Rect::new(0, 0, self.width, self.height);
+        let mut film = Film::new(full, &full, &self.aov_selection(), &self.filter);
+        let region = self.render_region();
+        let mut active = vec![false; self.width * self.height];
+        let mut pass = 0;
+        let first = PassInfo { samples_per_pixel: 0, active_pixels: region.area(), elapsed: Duration::from_secs(0), noise: f32::INFINITY };
+        control.start(region.area().saturating_mul(self.max_passes(stop.expected_passes(&first))));
+        loop {
+            match &self.adaptive {
+                Some(adaptive) => {
+                    for (a, s) in active.iter_mut().zip(film.stats.iter()) {
+                        *a = adaptive.needs_samples(s);
+                    }
+                    self.render_samples(camera, world, &mut film, Schedule::Masked(pass, &active), control, &|_| {});
                 }
-            } else {
-                *p = black;
+                None => self.render_samples(camera, world, &mut film, Schedule::Uniform(pass..pass + 1), control, &|_| {}),
+            }
+            if control.is_cancelled() {
+                return Rendered::Cancelled(self.finish(film.to_framebuffer(world.color_space)));
+            }
+            pass += 1;
+
+            let active_pixels = match &self.adaptive {
+                Some(adaptive) => film.stats.iter()
+                    .enumerate()
+                    .filter(|&(i, s)| region.contains(i % self.width, i / self.width) && adaptive.needs_samples(s))
+                    .count(),
+                None => region.area(),
+            };
+            let fb = self.finish(film.to_framebuffer(world.color_space));
+            let info = PassInfo {
+                samples_per_pixel: pass,
+                active_pixels,
+                elapsed: start.elapsed(),
+                noise: film.noise(),
+            };
+            on_pass(&fb, &info);
+            if active_pixels == 0 || stop.is_met(&info) {
+                control.mark_complete();
+                return Rendered::Complete(fb);
             }
+            control.set_total(region.area().saturating_mul(self.max_passes(stop.expected_passes(&info))));
+        }
+    }
+
+    /// Caps an estimated number of progressive passes at the adaptive sample limit
+    fn max_passes(&self, passes: usize) -> usize {
+        match &self.adaptive {
+            Some(adaptive) => passes.min(adaptive.max_samples),
+            None => passes,
+        }
+    }
+
+    /**
+     * Renders each tile to completion, calling `on_tile` as each one finishes
+     *
+     * Every pixel gets `samples_per_pixel` samples, or as many as `adaptive` decides if it is set.
+     *
+     * In parallel builds `on_tile` is called from the render threads, in no particular order.
+     */
+    pub fn render_tiles<F>(&self, camera: &Camera, world: &World, on_tile: F) -> Framebuffer
+        where F: Fn(&Rect) + Sync {
+        self.render_controlled(camera, world, &RenderControl::new(), on_tile).into_image()
+    }
+
+    /**
+     * Like `render_tiles`, but reports progress through `control` and stops early if it is cancelled
+     *
+     * Progress counts finished pixels, so with adaptive sampling it runs ahead of the remaining time.
+     * A cancelled render returns the pixels finished so far; `on_tile` is only called for complete tiles.
+     */
+    pub fn render_controlled<F>(&self, camera: &Camera, world: &World, control: &RenderControl, on_tile: F) -> Rendered
+        where F: Fn(&Rect) + Sync {
+        let full = Rect::new(0, 0, self.width, self.height);
+        let mut film = Film::new(full, &full, &self.aov_selection(), &self.filter);
+        let schedule = match &self.adaptive {
+            Some(adaptive) => Schedule::Adaptive(*adaptive),
+            None => Schedule::Uniform(0..self.samples_per_pixel),
+        };
+        control.start(self.render_region().area());
+        self.render_samples(camera, world, &mut film, schedule, control, &on_tile);
+        if control.is_cancelled() {
+            Rendered::Cancelled(self.finish(film.to_framebuffer(world.color_space)))
+        } else {
+            control.mark_complete();
+            Rendered::Complete(self.finish(film.to_framebuffer(world.color_space)))
+        }
+    }
+
+    /// Starts rendering tiles on a background thread, returning a handle to watch, cancel or wait for the render
+    pub fn spawn(&self, camera: &Camera, world: Shared<World>) -> RenderHandle {
+        let control = Shared::new(RenderControl::new());
+        let (screen, camera, shared) = (self.clone(), camera.clone(), control.clone());
+        let thread = thread::spawn(move || screen.render_controlled(&camera, &world, &shared, |_| {}));
+        RenderHandle::new(control, thread)
+    }
+
+    /// Starts a progressive render on a background thread. `on_pass` is called from that thread.
+    pub fn spawn_progressive<F>(&self, camera: &Camera, world: Shared<World>, stop: StopCondition, on_pass: F) -> RenderHandle
+        where F: FnMut(&Framebuffer, &PassInfo) + Send + 'static {
+        let control = Shared::new(RenderControl::new());
+        let (screen, camera, shared) = (self.clone(), camera.clone(), control.clone());
+        let thread = thread::spawn(move || screen.render_progressive_controlled(&camera, &world, &stop, &shared, on_pass));
+        RenderHandle::new(control, thread)
+    }
+
+    /// Renders linear, unbounded radiance with `samples_per_pixel` samples (or adaptively), along with coverage
+    /// from whether each primary ray hit anything and whichever AOVs are selected in `aovs`
+    pub fn render_hdr(&self, camera: &Camera, world: &World) -> Framebuffer {
+        self.render_tiles(camera, world, |_| {})
+    }
+
+    /// Renders, then tone maps and sRGB-encodes the result to 8 bits per channel
+    pub fn render(&self, camera: &Camera, world: &World) -> Vec<RGB8> {
+        let fb = self.render_hdr(camera, world);
+        self.tone_mapper.to_srgb8_image(&fb.in_color_space(ColorSpace::LinearSrgb).pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Screen, StopCondition, AdaptiveSampling};
+    use crate::Denoiser;
+    use crate::control::RenderControl;
+    use crate::AovSelection;
+    use crate::tile::{Rect, TileOrder};
+    use std::sync::Mutex;
+    use crate::primitive::Sphere;
+    use crate::{Camera, World, Material, Light, Color3};
+    use nalgebra_glm as glm;
+    use std::sync::Arc as Shared;
+
+    fn scene() -> (Camera, World) {
+        let sphere = Sphere::new(glm::vec3(0.0, 0.0, -3.0), 1.0, &Shared::new(Material::default()));
+        let world = World {
+            primitives: vec![Box::new(sphere)],
+            lights: vec![Light::directional(glm::vec3(1.0, 1.0, 1.0), Color3::gray(3.0))],
+            ..World::default()
+        };
+        (Camera::default(), world)
+    }
+
+    #[test]
+    fn progressive_stops_after_sample_count() {
+        let (camera, world) = scene();
+        let screen = Screen::new(16, 9);
+        let mut passes = vec![];
+        let fb = screen.render_progressive(&camera, &world, &StopCondition::samples(4), |_, info| {
+            passes.push(info.samples_per_pixel);
         });
+        assert_eq!(passes, vec![1, 2, 3, 4]);
+        assert_eq!(fb.pixels.len(), 16 * 9);
+    }
+
+    #[test]
+    fn progressive_stops_at_noise_target() {
+        let (camera, world) = scene();
+        let screen = Screen::new(16, 9);
+        let stop = StopCondition { samples: Some(10_000), noise: Some(0.05), ..Default::default() };
+        let mut last = None;
+        screen.render_progressive(&camera, &world, &stop, |_, info| last = Some(*info));
+        let last = last.unwrap();
+        assert!(last.noise <= 0.05);
+        assert!(last.samples_per_pixel < 10_000);
+    }
 
-        // convert to u8, 0-255
-        pixels.into_iter().map(|v| v.to_rgb8()).collect()
+    #[test]
+    fn denoising_keeps_only_the_selected_aovs() {
+        let (camera, world) = scene();
+        let mut screen = Screen::new(16, 9);
+        screen.aovs.albedo = true;
+        let noisy = screen.render_hdr(&camera, &world);
+        screen.denoiser = Some(Denoiser::default());
+        let clean = screen.render_hdr(&camera, &world);
+        assert!(clean.aovs.albedo.is_some() && clean.aovs.normal.is_none());
+        assert!(noisy.pixels.iter().zip(clean.pixels.iter()).any(|(a, b)| a.r != b.r));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn textures_survive_denoising() {
+        use crate::{Background, Framebuffer};
+        // Eight stripes around the sphere
+        let mut stripes = Framebuffer::new(16, 1);
+        for x in 0..16 {
+            stripes.set(x, 0, Color3::gray(if (x / 2) % 2 == 0 { 0.9 } else { 0.1 }));
+        }
+        let (camera, mut world) = scene();
+        let texture = world.textures.add_image(stripes);
+        let matte = Material::new(1.0, 0.0, Color3::gray(1.0), Color3::gray(0.0), Color3::gray(0.0), 1.5);
+        let material = Material { albedo_texture: Some(texture), ..matte };
+        world.primitives = vec![Box::new(Sphere::new(glm::vec3(0.0, 0.0, -3.0), 1.0, &Shared::new(material)))];
+        world.background = Background::Constant(Color3::gray(1.0));
+        let mut screen = Screen::new(48, 48);
+        screen.aovs.albedo = true;
+        screen.denoiser = Some(Denoiser::default());
+        let fb = screen.render_hdr(&camera, &world);
+
+        // The albedo AOV holds the stripes, and the denoiser keeps them
+        let albedo = fb.aovs.albedo.as_ref().unwrap();
+        let mean = |bright: bool| {
+            let on: Vec<f32> = (0..fb.pixels.len())
+                .filter(|&i| albedo[i].r > 0.0 && (albedo[i].r > 0.5) == bright)
+                .map(|i| fb.pixels[i].luminance())
+                .collect();
+            assert!(!on.is_empty());
+            on.iter().sum::<f32>() / on.len() as f32
+        };
+        assert!(mean(true) > 2.5 * mean(false), "{} {}", mean(true), mean(false));
+    }
+
+    #[test]
+    fn tile_order_does_not_change_the_image() {
+        let (camera, world) = scene();
+        let mut screen = Screen::new(40, 30);
+        screen.samples_per_pixel = 2;
+        screen.tile_size = 7;
+        let scanline = screen.render_hdr(&camera, &world);
+        screen.tile_order = TileOrder::Hilbert;
+        let hilbert = screen.render_hdr(&camera, &world);
+        for (a, b) in scanline.pixels.iter().zip(hilbert.pixels.iter()) {
+            assert_eq!((a.r, a.g, a.b), (b.r, b.g, b.b));
+        }
+    }
+
+    #[test]
+    fn samplers_do_not_depend_on_tiling() {
+        use crate::sampler::{Independent, Stratified, Halton, Sobol, BlueNoise};
+        let (camera, world) = scene();
+        let mut screen = Screen::new(24, 16);
+        screen.samples_per_pixel = 3;
+        let samplers: Vec<Shared<dyn crate::sampler::Sampler + Send + Sync>> = vec![
+            Shared::new(Independent::new(1)),
+            Shared::new(Stratified::new(1, 3)),
+            Shared::new(Halton::new(1)),
+            Shared::new(Sobol::new(1)),
+            Shared::new(BlueNoise::new(1)),
+        ];
+        for sampler in samplers {
+            screen.sampler = sampler;
+            screen.tile_size = 5;
+            let small = screen.render_hdr(&camera, &world);
+            screen.tile_size = 64;
+            let whole = screen.render_hdr(&camera, &world);
+            assert!(small.pixels.iter().zip(whole.pixels.iter()).all(|(a, b)| (a.r, a.g, a.b) == (b.r, b.g, b.b)));
+        }
+    }
+
+    #[test]
+    fn filters_splat_across_tiles() {
+        use crate::filter::{Filter, GaussianFilter, MitchellFilter, LanczosFilter};
+        let (camera, world) = scene();
+        let mut screen = Screen::new(24, 16);
+        screen.samples_per_pixel = 2;
+        let sharp = screen.render_hdr(&camera, &world);
+        let filters: Vec<Shared<dyn Filter + Send + Sync>> = vec![
+            Shared::new(GaussianFilter::default()),
+            Shared::new(MitchellFilter::default()),
+            Shared::new(LanczosFilter::default()),
+        ];
+        for filter in filters {
+            screen.filter = filter;
+            screen.tile_size = 5;
+            let small = screen.render_hdr(&camera, &world);
+            screen.tile_size = 64;
+            let whole = screen.render_hdr(&camera, &world);
+            assert!(small.pixels.iter().zip(whole.pixels.iter()).all(|(a, b)| (a.r, a.g, a.b) == (b.r, b.g, b.b)));
+            assert_eq!(small.alpha, whole.alpha);
+            assert!(sharp.pixels.iter().zip(whole.pixels.iter()).any(|(a, b)| (a.r - b.r).abs() > 1.0e-3));
+        }
+    }
+
+    #[test]
+    fn region_of_interest() {
+        let (camera, world) = scene();
+        let mut screen = Screen::new(40, 30);
+        screen.tile_size = 8;
+        screen.region = Some(Rect::new(10, 10, 20, 100));
+        let finished = Mutex::new(vec![]);
+        let fb = screen.render_tiles(&camera, &world, |tile| finished.lock().unwrap().push(*tile));
+
+        // Clipped to 20x20 at (10, 10): 3x3 tiles of at most 8 pixels
+        let finished = finished.into_inner().unwrap();
+        assert_eq!(finished.len(), 9);
+        assert_eq!(finished.iter().map(|t| t.area()).sum::<usize>(), 20 * 20);
+        let alpha = fb.alpha.as_ref().unwrap();
+        assert_eq!(alpha[fb.index(20, 20)], 1.0);
+        assert_eq!(alpha[fb.index(5, 20)], 0.0);
+    }
+
+    #[test]
+    fn adaptive_limits_stay_ordered() {
+        let adaptive = AdaptiveSampling::new(0, 1, 0.01);
+        assert_eq!((adaptive.min_samples, adaptive.max_samples), (2, 2));
+    }
+
+    #[test]
+    fn adaptive_sampling_skips_flat_background() {
+        let (camera, world) = scene();
+        let mut screen = Screen::new(32, 18);
+        screen.adaptive = Some(AdaptiveSampling::new(4, 64, 0.02));
+        screen.aovs = AovSelection { sample_count: true, ..AovSelection::none() };
+        let fb = screen.render_hdr(&camera, &world);
+
+        let counts = fb.aovs.sample_count.as_ref().unwrap();
+        // Empty background never varies, so it stops at the minimum; the sphere's silhouette doesn't
+        assert_eq!(counts[fb.index(0, 0)], 4);
+        assert!(counts.iter().all(|c| (4..=64).contains(c)));
+        assert!(counts.iter().any(|&c| c > 4));
+    }
+
+    #[test]
+    fn cancelled_render_keeps_finished_pixels() {
+        let (camera, world) = scene();
+        let mut screen = Screen::new(64, 64);
+        screen.tile_size = 8;
+        screen.aovs = AovSelection { sample_count: true, ..AovSelection::none() };
+        let control = RenderControl::new();
+        let rendered = screen.render_controlled(&camera, &world, &control, |_| control.cancel());
+
+        assert!(rendered.is_cancelled());
+        assert!(control.progress() < 1.0);
+        let counts = rendered.image().aovs.sample_count.as_ref().unwrap();
+        assert!(counts.contains(&1));
+        assert!(counts.contains(&0));
+    }
+
+    #[test]
+    fn handle_reports_completion() {
+        let (camera, world) = scene();
+        let handle = Screen::new(16, 9).spawn(&camera, Shared::new(world));
+        let control = handle.control().clone();
+        let rendered = handle.join();
+        assert!(!rendered.is_cancelled());
+        assert!(control.is_complete());
+        assert_eq!(control.progress(), 1.0);
+        assert_eq!(control.eta(), Some(std::time::Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn handle_cancels_from_another_thread() {
+        let (camera, world) = scene();
+        let handle = Screen::new(16, 9).spawn_progressive(&camera, Shared::new(world), StopCondition::samples(1_000_000), |_, _| {});
+        while handle.progress() == 0.0 {
+            std::thread::yield_now();
+        }
+        assert!(handle.eta().is_some());
+        handle.cancel();
+        let rendered = handle.join();
+        assert!(rendered.is_cancelled());
+        assert_eq!(rendered.image().pixels.len(), 16 * 9);
+    }
+
+    #[test]
+    fn adaptive_progressive_converges() {
+        let (camera, world) = scene();
+        let mut screen = Screen::new(16, 9);
+        screen.adaptive = Some(AdaptiveSampling::new(2, 16, 0.05));
+        let mut last = None;
+        screen.render_progressive(&camera, &world, &StopCondition::samples(1000), |_, info| last = Some(*info));
+        // Every pixel hits its cap or its threshold long before the sample limit
+        assert_eq!(last.unwrap().active_pixels, 0);
+        assert!(last.unwrap().samples_per_pixel <= 16);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::math::*;
+use crate::{Color3, Framebuffer, EnvironmentMap, ColorSpace};
+use crate::bsdf::Frame;
+use nalgebra_glm as glm;
+
+/// Luminance of the sun's disk above the atmosphere, in kcd/m^2
+const SUN_LUMINANCE: f32 = 1.6e6;
+/// Size of the table used to importance sample the sky
+const TABLE_WIDTH: usize = 64;
+const TABLE_HEIGHT: usize = 32;
+
+/// Coefficients of the Perez sky luminance distribution
+#[derive(Debug, Copy, Clone)]
+struct Perez {
+    a: Scalar,
+    b: Scalar,
+    c: Scalar,
+    d: Scalar,
+    e: Scalar,
+}
+impl Perez {
+    /// Relative brightness at angle `theta` from the zenith and `gamma` from the sun
+    fn eval(&self, cos_theta: Scalar, gamma: Scalar) -> Scalar {
+        let cos_gamma = gamma.cos();
+        (1.0 + self.a * (self.b / cos_theta).exp()) * (1.0 + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+/**
+ * A clear daytime sky from the analytic model of Preetham et al., "A Practical Analytic Model for Daylight"
+ *
+ * Includes the sun as a small, very bright disk, so the sky lights the scene like an environment map. Radiance is
+ * in kcd/m^2 times `intensity`. The sun is placed by its elevation above the horizon and its azimuth, measured from
+ * -z towards +x; y is up. Turbidity describes the haze, from about 2 for a very clear sky to 10 for a hazy one.
+ */
+#[derive(Debug, Clone)]
+pub struct Sky {
+    sun_direction: Vec3,
+    turbidity: Scalar,
+    /// Luminance and chromaticity at the zenith
+    zenith: (Scalar, Scalar, Scalar),
+    perez: [Perez; 3],
+    /// Color of the sun after passing through the atmosphere
+    sun_color: Color3,
+    /// Scales the radiance of both the sky and the sun
+    pub intensity: f32,
+    /// Radiance below the horizon
+    pub ground: Color3,
+    /// Angular radius of the sun's disk, in radians
+    pub sun_radius: Scalar,
+    /// The sky without the sun, for sampling directions
+    table: EnvironmentMap,
+    /// How often `sample` picks the sun rather than the sky
+    sun_probability: Scalar,
+}
+impl Sky {
+    /// Angles are in radians
+    pub fn new(sun_elevation: Scalar, sun_azimuth: Scalar, turbidity: Scalar) -> Self {
+        let elevation = sun_elevation.clamp(0.0, consts::FRAC_PI_2);
+        let sun_direction = Vec3::new(elevation.cos() * sun_azimuth.sin(), elevation.sin(), -elevation.cos() * sun_azimuth.cos());
+        let t = turbidity.max(1.0);
+        let theta_s = consts::FRAC_PI_2 - elevation;
+
+        let perez = [
+            Perez { a: 0.1787 * t - 1.4630, b: -0.3554 * t + 0.4275, c: -0.0227 * t + 5.3251, d: 0.1206 * t - 2.5771, e: -0.0670 * t + 0.3703 },
+            Perez { a: -0.0193 * t - 0.2592, b: -0.0665 * t + 0.0008, c: -0.0004 * t + 0.2125, d: -0.0641 * t - 0.8989, e: -0.0033 * t + 0.0452 },
+            Perez { a: -0.0167 * t - 0.2608, b: -0.0950 * t + 0.0092, c: -0.0079 * t + 0.2102, d: -0.0441 * t - 1.6537, e: -0.0109 * t + 0.0529 },
+        ];
+        let chi = (4.0 / 9.0 - t / 120.0) * (consts::PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (s1, s2, s3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let x = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s1)
+            + t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s1 + 0.00394)
+            + (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s1 + 0.25886);
+        let y = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s1)
+            + t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s1 + 0.00516)
+            + (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s1 + 0.26688);
+
+        let mut sky = Sky {
+            sun_direction,
+            turbidity: t,
+            zenith: (luminance.max(0.0), x, y),
+            perez,
+            sun_color: sun_transmittance(theta_s, t),
+            intensity: 1.0,
+            ground: Color3::gray(0.0),
+            sun_radius: 0.00465,
+            table: EnvironmentMap::new(Framebuffer::new(0, 0)),
+            sun_probability: 0.0,
+        };
+
+        // Tabulate the sky alone, and weigh it against the sun by the light each gives off
+        let mut image = Framebuffer::new(TABLE_WIDTH, TABLE_HEIGHT);
+        let mut sky_power = 0.0;
+        for y in 0..TABLE_HEIGHT {
+            for x in 0..TABLE_WIDTH {
+                let uv = Vec2::new((x as Scalar + 0.5) / TABLE_WIDTH as Scalar, (y as Scalar + 0.5) / TABLE_HEIGHT as Scalar);
+                let c = sky.sky_radiance(&sky.table.direction_at(&uv));
+                let solid_angle = 2.0 * consts::PI * consts::PI * (uv.y * consts::PI).sin() / (TABLE_WIDTH * TABLE_HEIGHT) as Scalar;
+                sky_power += c.luminance() as Scalar * solid_angle;
+                image.set(x, y, c);
+            }
+        }
+        let sun_power = (sky.sun_color.luminance() * SUN_LUMINANCE) as Scalar * cone_solid_angle(sky.sun_radius);
+        sky.sun_probability = (sun_power / (sun_power + sky_power)).clamp(0.1, 0.9);
+        sky.table = EnvironmentMap::new(image);
+        sky
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    pub fn turbidity(&self) -> Scalar {
+        self.turbidity
+    }
+
+    /// Radiance of the sky alone, without `intensity`
+    fn sky_radiance(&self, direction: &Vec3) -> Color3 {
+        if direction.y <= 0.0 {
+            return Color3::gray(0.0);
+        }
+        // Keep the Perez exponent finite right at the horizon
+        let cos_theta = direction.y.max(1.0e-2);
+        let gamma = glm::dot(direction, &self.sun_direction).clamp(-1.0, 1.0).acos();
+        let cos_theta_s = self.sun_direction.y;
+        let theta_s = cos_theta_s.clamp(-1.0, 1.0).acos();
+        let relative = |i: usize| self.perez[i].eval(cos_theta, gamma) / self.perez[i].eval(1.0, theta_s);
+        let luminance = self.zenith.0 * relative(0);
+        let x = self.zenith.1 * relative(1);
+        let y = self.zenith.2 * relative(2);
+        xyy_to_rgb(x, y, luminance)
+    }
+
+    fn in_sun(&self, direction: &Vec3) -> bool {
+        glm::dot(direction, &self.sun_direction) >= self.sun_radius.cos()
+    }
+
+    /// Radiance seen looking in `direction`, in `color_space`. The ground color is taken to be in that space already.
+    pub fn radiance(&self, direction: &Vec3, color_space: ColorSpace) -> Color3 {
+        if direction.y <= 0.0 {
+            return self.ground;
+        }
+        // The model gives linear sRGB
+        let mut c = self.sky_radiance(direction);
+        if self.in_sun(direction) {
+            c += self.sun_color * SUN_LUMINANCE;
+        }
+        ColorSpace::LinearSrgb.convert(c, color_space) * self.intensity
+    }
+
+    /// A direction towards the sun or the sky, with its density. `u` is a uniformly distributed point in the unit square.
+    pub(crate) fn sample(&self, u: (Scalar, Scalar)) -> Option<(Vec3, Scalar)> {
+        let direction = if u.0 < self.sun_probability {
+            let u0 = u.0 / self.sun_probability;
+            let cos_max = self.sun_radius.cos();
+            let cos_theta = 1.0 - u0 * (1.0 - cos_max);
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * consts::PI * u.1;
+            Frame::from_normal(&self.sun_direction).to_world(&Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+        } else {
+            let u0 = (u.0 - self.sun_probability) / (1.0 - self.sun_probability);
+            self.table.sample((u0.min(1.0 - consts::EPSILON), u.1))?.0
+        };
+        let pdf = self.pdf(&direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((direction, pdf))
+    }
+
+    pub(crate) fn pdf(&self, direction: &Vec3) -> Scalar {
+        let sun = if self.in_sun(direction) { 1.0 / cone_solid_angle(self.sun_radius) } else { 0.0 };
+        self.sun_probability * sun + (1.0 - self.sun_probability) * self.table.pdf(direction)
+    }
+}
+
+fn cone_solid_angle(radius: Scalar) -> Scalar {
+    2.0 * consts::PI * (1.0 - radius.cos())
+}
+
+/// CIE xyY to linear sRGB, with negative components clamped
+fn xyy_to_rgb(x: Scalar, y: Scalar, luminance: Scalar) -> Color3 {
+    if y <= 0.0 {
+        return Color3::gray(0.0);
+    }
+    let cx = x / y * luminance;
+    let cz = (1.0 - x - y) / y * luminance;
+    let cy = luminance;
+    Color3::new(
+        (3.2406 * cx - 1.5372 * cy - 0.4986 * cz).max(0.0) as f32,
+        (-0.9689 * cx + 1.8758 * cy + 0.0415 * cz).max(0.0) as f32,
+        (0.0557 * cx - 0.2040 * cy + 1.0570 * cz).max(0.0) as f32,
+    )
+}
+
+/// Fraction of sunlight that makes it through the atmosphere, per channel, from Rayleigh and aerosol scattering
+/// along the relative air mass of Kasten and Young
+fn sun_transmittance(theta_s: Scalar, turbidity: Scalar) -> Color3 {
+    let degrees = theta_s.to_degrees().min(93.0);
+    let air_mass = 1.0 / (theta_s.cos().max(0.0) + 0.50572 * (96.07995 - degrees).powf(-1.6364));
+    // Angstrom's turbidity coefficient and wavelength exponent
+    let beta = 0.04608 * turbidity - 0.04586;
+    let channel = |lambda_um: Scalar| {
+        let rayleigh = 0.008735 * lambda_um.powf(-4.08);
+        let aerosol = beta * lambda_um.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp() as f32
+    };
+    Color3::new(channel(0.680), channel(0.550), channel(0.440))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{Sampler, Sobol};
+
+    #[test]
+    fn sky_is_blue_and_the_sunset_is_red() {
+        let sky = Sky::new(0.8, 0.5, 3.0);
+        let zenith = sky.radiance(&Vec3::new(0.0, 1.0, 0.0), ColorSpace::LinearSrgb);
+        assert!(zenith.b > zenith.r && zenith.luminance() > 0.0, "{:?}", zenith);
+        assert!(sky.radiance(&sky.sun_direction(), ColorSpace::LinearSrgb).luminance() > 1000.0 * zenith.luminance());
+        assert_eq!(sky.radiance(&Vec3::new(0.0, -1.0, 0.0), ColorSpace::LinearSrgb).r, 0.0);
+
+        let noon = sun_transmittance(0.1, 3.0);
+        let dusk = sun_transmittance(1.5, 3.0);
+        assert!(dusk.r / dusk.b > noon.r / noon.b);
+    }
+
+    #[test]
+    fn samples_match_the_pdf() {
+        let sky = Sky::new(0.3, -1.0, 4.0);
+        let sampler = Sobol::new(2);
+        let mut sun = 0;
+        for i in 0..256 {
+            let (d, pdf) = sky.sample(sampler.sample_2d((0, 0), i, 0)).unwrap();
+            assert!((sky.pdf(&d) - pdf).abs() <= 1.0e-3 * pdf);
+            if sky.in_sun(&d) {
+                sun += 1;
+            }
+        }
+        assert!(sun > 0);
+    }
+}
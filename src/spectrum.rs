@@ -0,0 +1,222 @@
+//! Spectra sampled at a few wavelengths at a time, for spectral rendering
+
+use crate::math::*;
+use crate::{Color3, ColorSpace};
+use lazy_static::lazy_static;
+
+/// Shortest wavelength rendered, in nm
+pub const LAMBDA_MIN: Scalar = 360.0;
+/// Longest wavelength rendered, in nm
+pub const LAMBDA_MAX: Scalar = 830.0;
+/// Wavelengths at the middle of the red, green and blue ranges, where per-channel measurements apply, in nm
+pub const CHANNEL_WAVELENGTHS: [Scalar; 3] = [630.0, 532.0, 465.0];
+
+/// Reflectance spectra of Smits, "An RGB-to-Spectrum Conversion for Reflectances", in 10 bins from 380 to 720 nm
+const SMITS_MIN: Scalar = 380.0;
+const SMITS_MAX: Scalar = 720.0;
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Integral of the CIE luminance matching function over the rendered wavelengths
+const CIE_Y_INTEGRAL: Scalar = 106.856895;
+
+lazy_static! {
+    /// Linear sRGB of a spectrum that's 1 everywhere, by which results are divided so that white stays white
+    static ref WHITE: Color3 = {
+        let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        let xyz = (0..steps).fold(Vec3::zeros(), |sum, i| sum + cie_xyz(LAMBDA_MIN + i as Scalar + 0.5))
+            / CIE_Y_INTEGRAL;
+        xyz_to_rgb(&xyz)
+    };
+}
+
+/// The CIE 1931 color matching functions, from the multi-lobe Gaussian fit of Wyman et al.,
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions"
+pub fn cie_xyz(lambda: Scalar) -> Vec3 {
+    let g = |mu: Scalar, below: Scalar, above: Scalar| {
+        let t = (lambda - mu) / if lambda < mu { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// CIE XYZ to linear sRGB
+pub fn xyz_to_rgb(xyz: &Vec3) -> Color3 {
+    Color3::new(
+        (3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z) as f32,
+        (-0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z) as f32,
+        (0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z) as f32,
+    )
+}
+
+/**
+ * The value at `lambda` of a smooth spectrum with the given linear sRGB color
+ *
+ * Follows Smits: the smallest component is white, and the rest is made of the spectra of the secondary and then
+ * the primary colors. Colors brighter than 1 are scaled down to fit and back up again, so emission works too.
+ */
+pub fn upsample(c: &Color3, lambda: Scalar) -> f32 {
+    let scale = c.r.max(c.g).max(c.b);
+    if scale <= 0.0 {
+        return 0.0;
+    }
+    let (r, g, b) = (c.r.max(0.0) / scale, c.g.max(0.0) / scale, c.b.max(0.0) / scale);
+    let bin = (((lambda - SMITS_MIN) / (SMITS_MAX - SMITS_MIN) * 10.0).max(0.0) as usize).min(9);
+    let (cyan, magenta, yellow) = (SMITS_CYAN[bin], SMITS_MAGENTA[bin], SMITS_YELLOW[bin]);
+    let (red, green, blue) = (SMITS_RED[bin], SMITS_GREEN[bin], SMITS_BLUE[bin]);
+    let value = if r <= g && r <= b {
+        r + if g <= b { (g - r) * cyan + (b - g) * blue } else { (b - r) * cyan + (g - b) * green }
+    } else if g <= r && g <= b {
+        g + if r <= b { (r - g) * magenta + (b - r) * blue } else { (b - g) * magenta + (r - b) * red }
+    } else {
+        b + if r <= g { (r - b) * yellow + (g - r) * green } else { (g - b) * yellow + (r - g) * red }
+    };
+    value.max(0.0) * scale
+}
+
+/**
+ * The wavelengths a path carries, for hero wavelength sampling (Wilkie et al., "Hero Wavelength Spectral Sampling")
+ *
+ * The first, the hero, is chosen at random, and the others are spread evenly from it around the rendered range.
+ * Colors along the path hold one value per wavelength instead of red, green and blue.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct Wavelengths {
+    pub lambda: [Scalar; 3],
+    /// The space of the colors that are upsampled, and of the results
+    pub color_space: ColorSpace,
+}
+impl Wavelengths {
+    /// `u` is uniformly distributed in [0, 1)
+    pub fn sample(u: Scalar) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = u * range;
+        let at = |i: usize| LAMBDA_MIN + (hero + i as Scalar * range / 3.0) % range;
+        Wavelengths {
+            lambda: [at(0), at(1), at(2)],
+            color_space: ColorSpace::LinearSrgb,
+        }
+    }
+
+    pub fn in_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    pub fn hero(&self) -> Scalar {
+        self.lambda[0]
+    }
+
+    /// Values at each wavelength of the spectrum with a given color. Colors outside the sRGB gamut are clipped to it.
+    pub fn upsample(&self, c: &Color3) -> Color3 {
+        let c = &self.color_space.convert(*c, ColorSpace::LinearSrgb);
+        Color3::new(upsample(c, self.lambda[0]), upsample(c, self.lambda[1]), upsample(c, self.lambda[2]))
+    }
+
+    /// Values at each wavelength of a quantity known at `CHANNEL_WAVELENGTHS`, interpolated linearly in between
+    pub fn interpolate(&self, c: &Color3) -> Color3 {
+        let at = |lambda: Scalar| {
+            let [r, g, b] = CHANNEL_WAVELENGTHS;
+            if lambda >= g {
+                c.g + (c.r - c.g) * ((lambda - g) / (r - g)).min(1.0) as f32
+            } else {
+                c.b + (c.g - c.b) * ((lambda - b) / (g - b)).max(0.0) as f32
+            }
+        };
+        Color3::new(at(self.lambda[0]), at(self.lambda[1]), at(self.lambda[2]))
+    }
+
+    /// Color of a spectrum sampled at these wavelengths, as an estimate of the whole spectrum's color
+    pub fn to_rgb(&self, values: &Color3) -> Color3 {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let samples = [values.r, values.g, values.b];
+        let xyz = self.lambda.iter().zip(samples.iter())
+            .fold(Vec3::zeros(), |sum, (&lambda, &v)| sum + cie_xyz(lambda) * v as Scalar)
+            * (range / 3.0 / CIE_Y_INTEGRAL);
+        ColorSpace::LinearSrgb.convert(xyz_to_rgb(&xyz) / *WHITE, self.color_space)
+    }
+}
+
+/// Describes how the index of refraction of a dielectric varies with wavelength, which splits white light into colors
+#[derive(Debug, Copy, Clone)]
+pub enum Dispersion {
+    /// n = a + b / lambda^2, with lambda in micrometers
+    Cauchy { a: Scalar, b: Scalar },
+    /// n^2 = 1 + sum of b lambda^2 / (lambda^2 - c), with lambda in micrometers
+    Sellmeier { b: [Scalar; 3], c: [Scalar; 3] },
+}
+impl Dispersion {
+    /// Schott N-BK7, a common optical glass
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    /// Dense flint glass, which disperses strongly
+    pub fn flint() -> Self {
+        Dispersion::Cauchy { a: 1.7280, b: 0.01342 }
+    }
+
+    /// Index of refraction at a wavelength in nm
+    pub fn ior(&self, lambda: Scalar) -> Scalar {
+        let um2 = (lambda / 1000.0) * (lambda / 1000.0);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / um2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: Scalar = b.iter().zip(c.iter()).map(|(b, c)| b * um2 / (um2 - c)).sum();
+                (1.0 + sum).max(1.0).sqrt()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_survive_the_round_trip() {
+        for &c in [Color3::gray(0.5), Color3::new(0.8, 0.2, 0.1), Color3::new(0.1, 0.6, 0.3), Color3::new(0.2, 0.3, 0.9)].iter() {
+            // Average many sets of wavelengths spread evenly over the range
+            let n = 470;
+            let rgb = (0..n).map(|i| {
+                let w = Wavelengths::sample((i as Scalar + 0.5) / n as Scalar);
+                w.to_rgb(&w.upsample(&c))
+            }).fold(Color3::gray(0.0), |sum, c| sum + c) / n as f32;
+            let error = (rgb.r - c.r).abs().max((rgb.g - c.g).abs()).max((rgb.b - c.b).abs());
+            assert!(error <= 0.06, "{:?} became {:?}", c, rgb);
+        }
+
+        // Working in another space gives the same colors, just expressed differently
+        let srgb = Color3::new(0.8, 0.2, 0.1);
+        let c = ColorSpace::LinearSrgb.convert(srgb, ColorSpace::AcesCg);
+        let w = Wavelengths::sample(0.3);
+        let (plain, aces) = (w.to_rgb(&w.upsample(&srgb)), w.in_color_space(ColorSpace::AcesCg));
+        let back = ColorSpace::AcesCg.convert(aces.to_rgb(&aces.upsample(&c)), ColorSpace::LinearSrgb);
+        assert!((back.r - plain.r).abs() <= 1.0e-3 && (back.b - plain.b).abs() <= 1.0e-3, "{:?} {:?}", back, plain);
+    }
+
+    #[test]
+    fn glass_bends_blue_light_more() {
+        let bk7 = Dispersion::bk7();
+        // The d line of helium
+        assert!((bk7.ior(587.6) - 1.5168).abs() <= 1.0e-3, "{}", bk7.ior(587.6));
+        assert!(bk7.ior(450.0) > bk7.ior(650.0));
+        assert!(Dispersion::flint().ior(450.0) > Dispersion::flint().ior(650.0));
+
+        let w = Wavelengths::sample(0.5);
+        let metal = w.interpolate(&Color3::new(1.0, 2.0, 3.0));
+        assert!(w.lambda.iter().all(|l| (LAMBDA_MIN..LAMBDA_MAX).contains(l)));
+        assert!(metal.r >= 1.0 && metal.r <= 3.0);
+    }
+}
@@ -0,0 +1,590 @@
+use crate::math::*;
+use crate::{Color3, ColorSpace, Framebuffer, Footprint};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc as Shared, Mutex, OnceLock, RwLock};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Width and height of the square tiles that textures are cached in, in texels
+const TILE_SIZE: usize = 32;
+/// Size of a texel in a tile store: three little-endian f32s
+const TEXEL_BYTES: usize = 12;
+/// What failed textures look like, so they stand out
+const MISSING: Color3 = Color3 { r: 1.0, g: 0.0, b: 1.0 };
+
+/// Numbers tile stores, so that no two share a file
+static NEXT_STORE: AtomicUsize = AtomicUsize::new(0);
+
+/// Refers to a texture added to a `TextureCache`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TextureId(usize);
+
+#[derive(Debug, Clone)]
+enum Source {
+    File(PathBuf),
+    Image(Shared<Framebuffer>),
+}
+
+#[derive(Debug)]
+struct Texture {
+    source: Source,
+    color_space: ColorSpace,
+    /// Set by the first lookup, which any others wait for, to the stored pyramid or why it couldn't be read
+    pyramid: OnceLock<Result<Pyramid, (io::ErrorKind, String)>>,
+    /// The texture's tiles in memory
+    tiles: Mutex<Tiles>,
+}
+
+/// Mip level, and tile column and row
+type TileKey = (usize, usize, usize);
+
+#[derive(Debug)]
+struct Tile {
+    width: usize,
+    pixels: Vec<Color3>,
+}
+impl Tile {
+    fn bytes(&self) -> usize {
+        self.pixels.len() * std::mem::size_of::<Color3>()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Tiles {
+    /// Each tile, with when it was last used
+    map: HashMap<TileKey, (Shared<Tile>, u64)>,
+    /// Tiles by when they were last used, oldest first
+    order: BTreeSet<(u64, TileKey)>,
+}
+impl Tiles {
+    fn touch(&mut self, key: &TileKey, clock: u64) -> Option<Shared<Tile>> {
+        let (tile, used) = self.map.get_mut(key)?;
+        self.order.remove(&(*used, *key));
+        *used = clock;
+        self.order.insert((clock, *key));
+        Some(tile.clone())
+    }
+
+    fn insert(&mut self, key: TileKey, tile: Shared<Tile>, clock: u64) {
+        self.map.insert(key, (tile, clock));
+        self.order.insert((clock, key));
+    }
+
+    fn oldest(&self) -> Option<u64> {
+        self.order.iter().next().map(|&(used, _)| used)
+    }
+
+    /// Drops the least recently used tile, returning the memory freed
+    fn drop_oldest(&mut self) -> usize {
+        match self.order.pop_first() {
+            Some((_, key)) => self.map.remove(&key).map_or(0, |(tile, _)| tile.bytes()),
+            None => 0,
+        }
+    }
+}
+
+/**
+ * A texture's mip pyramid, written out tile by tile to a scratch file so that any one tile can be read back alone
+ *
+ * Tiles are stored level by level and row-major within a level, so where each starts follows from the level's size.
+ * The file is removed when the pyramid is dropped.
+ */
+#[derive(Debug)]
+struct Pyramid {
+    /// Size of each level, finest first
+    levels: Vec<(usize, usize)>,
+    /// Where each level starts in the file, in bytes
+    offsets: Vec<u64>,
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+impl Pyramid {
+    fn write(levels: &[Framebuffer]) -> io::Result<Self> {
+        let path = std::env::temp_dir()
+            .join(format!("raytracer-{}-{}.tiles", std::process::id(), NEXT_STORE.fetch_add(1, Ordering::Relaxed)));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        // Made first so that the file is removed again if writing fails
+        let mut pyramid = Pyramid {
+            levels: levels.iter().map(|l| (l.width, l.height)).collect(),
+            offsets: Vec::with_capacity(levels.len()),
+            path,
+            file: Mutex::new(None),
+        };
+        let mut out = BufWriter::new(file);
+        let mut offset = 0;
+        for level in levels {
+            pyramid.offsets.push(offset);
+            for ty in 0..level.height.div_ceil(TILE_SIZE) {
+                for tx in 0..level.width.div_ceil(TILE_SIZE) {
+                    for c in cut_tile(level, tx, ty).pixels {
+                        out.write_all(&c.r.to_le_bytes())?;
+                        out.write_all(&c.g.to_le_bytes())?;
+                        out.write_all(&c.b.to_le_bytes())?;
+                    }
+                }
+            }
+            offset += (level.pixels.len() * TEXEL_BYTES) as u64;
+        }
+        *pyramid.file.get_mut().unwrap() = Some(out.into_inner().map_err(|e| e.into_error())?);
+        Ok(pyramid)
+    }
+
+    fn read_tile(&self, (level, tx, ty): TileKey) -> io::Result<Tile> {
+        let (w, h) = self.levels[level];
+        let (x0, y0) = (tx * TILE_SIZE, ty * TILE_SIZE);
+        let width = TILE_SIZE.min(w - x0);
+        let height = TILE_SIZE.min(h - y0);
+        // Past the full rows of tiles above, then the tiles to the left in this row
+        let offset = self.offsets[level] + ((y0 * w + x0 * height) * TEXEL_BYTES) as u64;
+        let mut bytes = vec![0; width * height * TEXEL_BYTES];
+        {
+            let mut file = self.file.lock().unwrap();
+            let file = file.as_mut().ok_or_else(|| io::Error::other("texture tile store is closed"))?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut bytes)?;
+        }
+        let float = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        let pixels = bytes.chunks_exact(TEXEL_BYTES).map(|t| Color3::new(float(&t[0..4]), float(&t[4..8]), float(&t[8..12]))).collect();
+        Ok(Tile { width, pixels })
+    }
+}
+impl Drop for Pyramid {
+    fn drop(&mut self) {
+        // Closed first, as some systems won't remove open files
+        if let Ok(file) = self.file.get_mut() {
+            file.take();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// The four texels around a point in one mip level, and how to weigh them
+struct Bilinear {
+    level: usize,
+    xs: [usize; 2],
+    ys: [usize; 2],
+    fx: f32,
+    fy: f32,
+}
+impl Bilinear {
+    fn new(level: usize, (w, h): (usize, usize), uv: &Vec2) -> Self {
+        let x = uv.x * w as Scalar - 0.5;
+        let y = uv.y * h as Scalar - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let wrap = |v: Scalar, n: usize| (v as i64).rem_euclid(n as i64) as usize;
+        Bilinear {
+            level,
+            xs: [wrap(x0, w), wrap(x0 + 1.0, w)],
+            ys: [wrap(y0, h), wrap(y0 + 1.0, h)],
+            fx: (x - x0) as f32,
+            fy: (y - y0) as f32,
+        }
+    }
+
+    fn tiles(&self) -> [TileKey; 4] {
+        let key = |x: usize, y: usize| (self.level, x / TILE_SIZE, y / TILE_SIZE);
+        [key(self.xs[0], self.ys[0]), key(self.xs[1], self.ys[0]), key(self.xs[0], self.ys[1]), key(self.xs[1], self.ys[1])]
+    }
+
+    fn filter<F: Fn(usize, usize, usize) -> Color3>(&self, texel: F) -> Color3 {
+        let ([xa, xb], [ya, yb]) = (self.xs, self.ys);
+        let top = texel(self.level, xa, ya).mix(&texel(self.level, xb, ya), self.fx);
+        let bottom = texel(self.level, xa, yb).mix(&texel(self.level, xb, yb), self.fx);
+        top.mix(&bottom, self.fy)
+    }
+}
+
+/// How well the cache is doing
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TextureStats {
+    /// Tile lookups that found the tile in memory
+    pub hits: u64,
+    /// Tile lookups that read the tile back from its texture's scratch file
+    pub misses: u64,
+    /// Times a texture was read from its file or image and written out as tiles
+    pub loads: u64,
+    /// Memory held by cached tiles, and by any pyramid being built
+    pub bytes: usize,
+}
+
+/**
+ * Loads textures when they are first used, and keeps the parts in use in memory
+ *
+ * Each texture is read from disk once, on first lookup, filtered into a mip pyramid and written out in tiles to a
+ * scratch file in the system's temporary directory, which is removed along with the cache. Tiles are then read
+ * from there one at a time as lookups need them, and kept while they fit in `budget` bytes, after which the least
+ * recently used are dropped. The whole pyramid is in memory only while it's being written, and counts towards the
+ * budget until then.
+ *
+ * Each texture has its own lock, taken once per lookup for all the tiles it touches, and held while a missing tile
+ * is read so that it's only read once. The tiles themselves are handed out without holding it.
+ *
+ * PNG and Radiance HDR files are supported. Each texture is tagged with the color space its file is in, and
+ * lookups convert to the space asked for. PNGs are decoded from the sRGB curve, so they should hold colors.
+ */
+#[derive(Debug)]
+pub struct TextureCache {
+    /// Most memory tiles may take up, in bytes
+    pub budget: usize,
+    textures: RwLock<Vec<Shared<Texture>>>,
+    /// Ticks once per lookup, to tell which tiles were used least recently
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    loads: AtomicU64,
+    bytes: AtomicUsize,
+}
+impl TextureCache {
+    pub fn new(budget: usize) -> Self {
+        TextureCache {
+            budget,
+            textures: RwLock::new(Vec::new()),
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            loads: AtomicU64::new(0),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Adds a texture file, without loading it yet. Fails if the file is missing or of an unknown format.
+    pub fn add<P: AsRef<Path>>(&self, path: P, color_space: ColorSpace) -> io::Result<TextureId> {
+        let path = path.as_ref();
+        if !path.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("texture file not found: {}", path.display())));
+        }
+        if format_of(path).is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("unsupported texture format (expected .png or .hdr): {}", path.display())));
+        }
+        Ok(self.push(Source::File(path.to_path_buf()), color_space))
+    }
+
+    /// Adds an image already in memory, such as one generated by a program
+    pub fn add_image(&self, image: Framebuffer) -> TextureId {
+        let color_space = image.color_space;
+        self.push(Source::Image(Shared::new(image)), color_space)
+    }
+
+    fn push(&self, source: Source, color_space: ColorSpace) -> TextureId {
+        let mut textures = self.textures.write().unwrap();
+        textures.push(Shared::new(Texture { source, color_space, pyramid: OnceLock::new(), tiles: Mutex::default() }));
+        TextureId(textures.len() - 1)
+    }
+
+    /// Loads a texture now rather than on first use, reporting why it can't be read
+    pub fn load(&self, id: TextureId) -> io::Result<()> {
+        self.pyramid(&self.texture(id)).map(|_| ())
+    }
+
+    /// Size of the full-resolution image, loading it if need be
+    pub fn size(&self, id: TextureId) -> io::Result<(usize, usize)> {
+        Ok(self.pyramid(&self.texture(id))?.levels[0])
+    }
+
+    pub fn stats(&self) -> TextureStats {
+        TextureStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            loads: self.loads.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /**
+     * The filtered color at `uv`, in `color_space`
+     *
+     * The footprint picks the mip levels to blend between, so distant and grazing surfaces don't alias; without one
+     * the full-resolution image is sampled bilinearly. Textures repeat outside [0, 1]. Textures that can't be
+     * loaded are magenta.
+     */
+    pub fn lookup(&self, id: TextureId, uv: &Vec2, footprint: Option<&Footprint>, color_space: ColorSpace) -> Color3 {
+        let texture = self.texture(id);
+        let levels = match self.pyramid(&texture) {
+            Ok(pyramid) => &pyramid.levels,
+            Err(_) => return MISSING,
+        };
+        let (width, height) = (levels[0].0 as Scalar, levels[0].1 as Scalar);
+        let texels = footprint.map_or(0.0, |f| {
+            (f.duvdx.x * width).abs()
+                .max((f.duvdx.y * height).abs())
+                .max((f.duvdy.x * width).abs())
+                .max((f.duvdy.y * height).abs())
+        });
+        let lod = if texels > 1.0 { texels.log2().min((levels.len() - 1) as Scalar) } else { 0.0 };
+        let fine = lod.floor() as usize;
+        let t = (lod - fine as Scalar) as f32;
+        let fine_tap = Bilinear::new(fine, levels[fine], uv);
+        let coarse_tap = if t > 0.0 { Some(Bilinear::new(fine + 1, levels[fine + 1], uv)) } else { None };
+
+        // Neighbouring texels are usually in the same tile
+        let mut keys = [(0, 0, 0); 8];
+        let mut count = 0;
+        for key in Some(&fine_tap).into_iter().chain(&coarse_tap).flat_map(|tap| tap.tiles()) {
+            if !keys[..count].contains(&key) {
+                keys[count] = key;
+                count += 1;
+            }
+        }
+        let keys = &keys[..count];
+        let tiles = self.tiles(&texture, keys);
+        let texel = |level: usize, x: usize, y: usize| {
+            let key = (level, x / TILE_SIZE, y / TILE_SIZE);
+            let tile = &tiles[keys.iter().position(|k| *k == key).unwrap()];
+            tile.pixels[(y % TILE_SIZE) * tile.width + x % TILE_SIZE]
+        };
+        let mut c = fine_tap.filter(texel);
+        if let Some(coarse) = &coarse_tap {
+            c = c.mix(&coarse.filter(texel), t);
+        }
+        texture.color_space.convert(c, color_space)
+    }
+
+    fn texture(&self, id: TextureId) -> Shared<Texture> {
+        self.textures.read().unwrap()[id.0].clone()
+    }
+
+    /// A texture's stored pyramid, reading the texture the first time
+    fn pyramid<'a>(&self, texture: &'a Texture) -> io::Result<&'a Pyramid> {
+        texture.pyramid
+            .get_or_init(|| self.read(texture).map_err(|e| (e.kind(), e.to_string())))
+            .as_ref()
+            .map_err(|(kind, error)| io::Error::new(*kind, error.clone()))
+    }
+
+    /// Tiles of a loaded texture, taking its lock once and reading back any that aren't in memory
+    fn tiles(&self, texture: &Texture, keys: &[TileKey]) -> Vec<Shared<Tile>> {
+        let pyramid = match texture.pyramid.get() {
+            Some(Ok(pyramid)) => pyramid,
+            _ => return keys.iter().map(|_| missing_tile()).collect(),
+        };
+        let clock = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut added = 0;
+        let mut tiles = texture.tiles.lock().unwrap();
+        let found = keys.iter().map(|key| {
+            if let Some(tile) = tiles.touch(key, clock) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return tile;
+            }
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            match pyramid.read_tile(*key) {
+                Ok(tile) => {
+                    let tile = Shared::new(tile);
+                    added += tile.bytes();
+                    tiles.insert(*key, tile.clone(), clock);
+                    tile
+                }
+                // Only happens if the scratch file was removed from under us
+                Err(_) => missing_tile(),
+            }
+        }).collect();
+        drop(tiles);
+        if added > 0 && self.bytes.fetch_add(added, Ordering::Relaxed) + added > self.budget {
+            self.evict();
+        }
+        found
+    }
+
+    /// Drops the least recently used tiles across all textures until the cache is back within its budget
+    fn evict(&self) {
+        let textures = self.textures.read().unwrap().clone();
+        while self.bytes.load(Ordering::Relaxed) > self.budget {
+            // Only one texture is locked at a time
+            let oldest = textures.iter()
+                .filter_map(|texture| Some((texture.tiles.lock().unwrap().oldest()?, texture)))
+                .min_by_key(|&(used, _)| used);
+            let freed = match oldest {
+                Some((_, texture)) => texture.tiles.lock().unwrap().drop_oldest(),
+                None => break,
+            };
+            self.bytes.fetch_sub(freed, Ordering::Relaxed);
+        }
+    }
+
+    /// Reads a texture, builds its pyramid and writes it out in tiles
+    fn read(&self, texture: &Texture) -> io::Result<Pyramid> {
+        let image = match &texture.source {
+            Source::File(path) => {
+                let read = match format_of(path) {
+                    Some(Format::Png) => crate::image::read_png(path),
+                    Some(Format::Hdr) => crate::image::read_hdr(path),
+                    None => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported format")),
+                };
+                read.map_err(|e| io::Error::new(e.kind(), format!("can't read texture {}: {}", path.display(), e)))?
+            }
+            Source::Image(image) => (**image).clone(),
+        };
+        if image.width == 0 || image.height == 0 || image.pixels.len() != image.width * image.height {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "texture has no pixels"));
+        }
+        let levels = pyramid(image);
+        // Make room for the whole pyramid while it's in memory
+        let bytes = levels.iter().map(|l| l.pixels.len() * std::mem::size_of::<Color3>()).sum();
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.evict();
+        let stored = Pyramid::write(&levels);
+        drop(levels);
+        self.bytes.fetch_sub(bytes, Ordering::Relaxed);
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        stored
+    }
+}
+impl Default for TextureCache {
+    /// A cache with a budget of 256 MiB
+    fn default() -> Self {
+        TextureCache::new(256 << 20)
+    }
+}
+
+fn missing_tile() -> Shared<Tile> {
+    Shared::new(Tile { width: 1, pixels: vec![MISSING; TILE_SIZE * TILE_SIZE] })
+}
+
+enum Format {
+    Png,
+    Hdr,
+}
+
+fn format_of(path: &Path) -> Option<Format> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some(Format::Png),
+        "hdr" | "pic" => Some(Format::Hdr),
+        _ => None,
+    }
+}
+
+/// Halves the image until it's a single texel, averaging 2x2 blocks. Odd edges reuse their last row or column.
+fn pyramid(image: Framebuffer) -> Vec<Framebuffer> {
+    let mut levels = vec![image];
+    loop {
+        let last = levels.last().unwrap();
+        if last.width == 1 && last.height == 1 {
+            break;
+        }
+        let (w, h) = (last.width.div_ceil(2), last.height.div_ceil(2));
+        let mut next = Framebuffer::new(w, h);
+        for y in 0..h {
+            for x in 0..w {
+                let at = |dx: usize, dy: usize| last.get((2 * x + dx).min(last.width - 1), (2 * y + dy).min(last.height - 1));
+                next.set(x, y, (at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) * 0.25);
+            }
+        }
+        levels.push(next);
+    }
+    levels
+}
+
+fn cut_tile(level: &Framebuffer, tx: usize, ty: usize) -> Tile {
+    let (x0, y0) = (tx * TILE_SIZE, ty * TILE_SIZE);
+    let width = TILE_SIZE.min(level.width - x0);
+    let height = TILE_SIZE.min(level.height - y0);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in y0..y0 + height {
+        pixels.extend_from_slice(&level.pixels[level.index(x0, y)..level.index(x0 + width, y)]);
+    }
+    Tile { width, pixels }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Black and white stripes one texel wide
+    fn stripes(size: usize) -> Framebuffer {
+        let mut image = Framebuffer::new(size, size);
+        for y in 0..size {
+            for x in 0..size {
+                image.set(x, y, Color3::gray((x % 2) as f32));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn distant_stripes_blur_to_gray() {
+        let textures = TextureCache::default();
+        let id = textures.add_image(stripes(64));
+        let center = |x: usize| Vec2::new((x as Scalar + 0.5) / 64.0, 0.5);
+        assert_eq!(textures.lookup(id, &center(1), None, ColorSpace::LinearSrgb).r, 1.0);
+        assert_eq!(textures.lookup(id, &center(2), None, ColorSpace::LinearSrgb).r, 0.0);
+
+        // Each pixel covers eight texels
+        let far = Footprint {
+            dpdx: Vec3::zeros(),
+            dpdy: Vec3::zeros(),
+            duvdx: Vec2::new(8.0 / 64.0, 0.0),
+            duvdy: Vec2::new(0.0, 8.0 / 64.0),
+        };
+        let c = textures.lookup(id, &center(1), Some(&far), ColorSpace::LinearSrgb);
+        assert!((c.r - 0.5).abs() <= 1.0e-4, "{:?}", c);
+        // Repeats outside [0, 1]
+        assert_eq!(textures.lookup(id, &Vec2::new(1.0 + 1.5 / 64.0, 0.5), None, ColorSpace::LinearSrgb).r, 1.0);
+    }
+
+    #[test]
+    fn least_recently_used_tiles_are_dropped() {
+        // Room for a few tiles out of the 16 of the finest level
+        let budget = 4 * TILE_SIZE * TILE_SIZE * std::mem::size_of::<Color3>();
+        let textures = TextureCache::new(budget);
+        let id = textures.add_image(stripes(4 * TILE_SIZE));
+        let at = |tx: usize| Vec2::new((tx * TILE_SIZE) as Scalar + 1.5, 1.0) / (4 * TILE_SIZE) as Scalar;
+        textures.lookup(id, &at(0), None, ColorSpace::LinearSrgb);
+        assert!(textures.stats().bytes <= budget);
+        let loads = textures.stats().loads;
+
+        // The tile just used stays, and the others are read back alone when needed
+        for tx in [3, 3, 1, 2, 0, 1].iter() {
+            assert_eq!(textures.lookup(id, &at(*tx), None, ColorSpace::LinearSrgb).r, 1.0);
+        }
+        let stats = textures.stats();
+        assert!(stats.hits > 0 && stats.misses >= 4 && stats.bytes <= budget);
+        assert_eq!(stats.loads, loads);
+
+        // The scratch file goes with the cache
+        let path = textures.texture(id).pyramid.get().unwrap().as_ref().unwrap().path.clone();
+        assert!(path.is_file());
+        drop(textures);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn textures_are_loaded_once_between_threads() {
+        let textures = TextureCache::default();
+        let id = textures.add_image(stripes(3 * TILE_SIZE));
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let textures = &textures;
+                scope.spawn(move || {
+                    for j in 0..50 {
+                        let uv = Vec2::new((i * 50 + j) as Scalar / 200.0, j as Scalar / 50.0);
+                        textures.lookup(id, &uv, None, ColorSpace::LinearSrgb);
+                    }
+                });
+            }
+        });
+        let stats = textures.stats();
+        assert_eq!(stats.loads, 1);
+        // Each of the nine tiles of the finest level was read back once
+        assert_eq!(stats.misses, 9);
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        let textures = TextureCache::default();
+        let error = textures.add("no/such/texture.png", ColorSpace::LinearSrgb).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("no/such/texture.png"));
+
+        // Tagged textures convert to the space asked for
+        let mut image = Framebuffer::new(1, 1);
+        image.set(0, 0, Color3::new(0.2, 0.4, 0.6));
+        image.color_space = ColorSpace::AcesCg;
+        let id = textures.add_image(image);
+        let c = textures.lookup(id, &Vec2::new(0.5, 0.5), None, ColorSpace::LinearSrgb);
+        let expected = ColorSpace::AcesCg.convert(Color3::new(0.2, 0.4, 0.6), ColorSpace::LinearSrgb);
+        assert!((c.r - expected.r).abs() <= 1.0e-5 && (c.b - expected.b).abs() <= 1.0e-5);
+    }
+}
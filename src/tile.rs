@@ -0,0 +1,159 @@
+use std::cmp::Ordering;
+
+/// An axis-aligned block of pixels, with (0, 0) at the top left of the image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[inline]
+    pub fn area(&self) -> usize {
+        self.width * self.height
+    }
+
+    #[inline]
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    /// The overlap of the two rectangles, which may be empty
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+}
+
+/// The order in which tiles are handed out to render threads
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Rows of tiles, left to right then top to bottom
+    Scanline,
+    /// Outwards from the center of the region, so the middle of the image resolves first
+    Spiral,
+    /// Along a Hilbert curve, which keeps consecutive tiles adjacent
+    Hilbert,
+}
+
+/// Splits a region into tiles of at most size x size pixels, ordered for rendering
+pub fn tiles(region: &Rect, size: usize, order: TileOrder) -> Vec<Rect> {
+    let size = size.max(1);
+    let cols = region.width.div_ceil(size);
+    let rows = region.height.div_ceil(size);
+
+    let mut cells: Vec<(usize, usize)> = (0..rows).flat_map(|r| (0..cols).map(move |c| (c, r))).collect();
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            // Rings of increasing distance from the center, each walked by angle
+            let cx = (cols as f64 - 1.0) / 2.0;
+            let cy = (rows as f64 - 1.0) / 2.0;
+            let key = |&(c, r): &(usize, usize)| {
+                let dx = c as f64 - cx;
+                let dy = r as f64 - cy;
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            cells.sort_by(|a, b| {
+                let (ka, kb) = (key(a), key(b));
+                ka.partial_cmp(&kb).unwrap_or(Ordering::Equal)
+            });
+        }
+        TileOrder::Hilbert => {
+            let n = cols.max(rows).next_power_of_two();
+            cells.sort_by_key(|&(c, r)| hilbert_index(n, c, r));
+        }
+    }
+
+    cells.into_iter()
+        .map(|(c, r)| {
+            let x = region.x + c * size;
+            let y = region.y + r * size;
+            Rect::new(x, y, size.min(region.x + region.width - x), size.min(region.y + region.height - y))
+        })
+        .collect()
+}
+
+/// Distance along the Hilbert curve filling an n by n grid, where n is a power of two
+fn hilbert_index(n: usize, mut x: usize, mut y: usize) -> usize {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = ((x & s) > 0) as usize;
+        let ry = ((y & s) > 0) as usize;
+        d += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant so the curve's orientation carries on into it
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn covers_exactly(region: &Rect, tiles: &[Rect]) -> bool {
+        let mut covered = vec![0; region.area()];
+        for t in tiles {
+            for y in t.y..t.y + t.height {
+                for x in t.x..t.x + t.width {
+                    covered[(y - region.y) * region.width + (x - region.x)] += 1;
+                }
+            }
+        }
+        covered.iter().all(|&c| c == 1)
+    }
+
+    #[test]
+    fn every_order_covers_the_region_once() {
+        let region = Rect::new(3, 5, 70, 45);
+        for &order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert].iter() {
+            let t = tiles(&region, 16, order);
+            assert_eq!(t.len(), 5 * 3);
+            assert!(covers_exactly(&region, &t), "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn spiral_starts_in_the_center() {
+        let t = tiles(&Rect::new(0, 0, 50, 50), 10, TileOrder::Spiral);
+        assert_eq!((t[0].x, t[0].y), (20, 20));
+    }
+
+    #[test]
+    fn hilbert_steps_are_adjacent() {
+        let t = tiles(&Rect::new(0, 0, 64, 64), 8, TileOrder::Hilbert);
+        for pair in t.windows(2) {
+            let dx = (pair[0].x as isize - pair[1].x as isize).abs();
+            let dy = (pair[0].y as isize - pair[1].y as isize).abs();
+            assert_eq!(dx + dy, 8);
+        }
+    }
+
+    #[test]
+    fn intersection_clips() {
+        let a = Rect::new(0, 0, 10, 10);
+        assert_eq!(a.intersection(&Rect::new(5, 8, 10, 10)), Rect::new(5, 8, 5, 2));
+        assert_eq!(a.intersection(&Rect::new(20, 0, 5, 5)).area(), 0);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::Color3;
+use nalgebra_glm as glm;
+use rgb::RGB8;
+
+/// Curves used to compress unbounded linear radiance into [0, 1]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapOperator {
+    /// Hard clip, per channel. Only useful for images that are already in range.
+    Clamp,
+    /// Reinhard's L / (1 + L), applied to luminance so that hue is preserved
+    Reinhard,
+    /// Reinhard with a white point: luminance at or above it maps to 1. White points below 0.001 count as 0.001.
+    ReinhardExtended(f32),
+    /// Narkowicz's curve fit of the ACES filmic reference rendering and output transforms
+    AcesFilmic,
+}
+
+/**
+ * The stage between the HDR framebuffer and 8-bit output
+ *
+ * Linear radiance is scaled by the exposure, compressed by the operator, then encoded with the sRGB OETF.
+ */
+#[derive(Debug, Copy, Clone)]
+pub struct ToneMapper {
+    /// Exposure adjustment in stops, applied before the operator
+    pub exposure: f32,
+    pub operator: ToneMapOperator,
+}
+impl ToneMapper {
+    pub fn new(exposure: f32, operator: ToneMapOperator) -> Self {
+        ToneMapper {
+            exposure,
+            operator,
+        }
+    }
+
+    /// Maps linear HDR radiance to linear values in [0, 1]
+    pub fn map(&self, c: Color3) -> Color3 {
+        let c = c * self.exposure.exp2();
+        match self.operator {
+            ToneMapOperator::Clamp => c.clamped(),
+            ToneMapOperator::Reinhard => {
+                let l = c.luminance();
+                if l <= 0.0 {
+                    return Color3::gray(0.0);
+                }
+                (c * (1.0 / (1.0 + l))).clamped()
+            }
+            ToneMapOperator::ReinhardExtended(white) => {
+                // Also catches NaN, which max discards
+                let white = white.max(MIN_WHITE);
+                let l = c.luminance();
+                if l <= 0.0 {
+                    return Color3::gray(0.0);
+                }
+                let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+                (c * (mapped / l)).clamped()
+            }
+            ToneMapOperator::AcesFilmic => Color3::new(aces(c.r), aces(c.g), aces(c.b)),
+        }
+    }
+
+    /// Tone maps, then applies the sRGB OETF and rounds to 8 bits
+    pub fn to_srgb8(&self, c: Color3) -> RGB8 {
+        self.map(c).to_srgb8()
+    }
+
+    pub fn to_srgb8_image(&self, pixels: &[Color3]) -> Vec<RGB8> {
+        pixels.iter().map(|&c| self.to_srgb8(c)).collect()
+    }
+}
+
+/// Smallest white point for extended Reinhard, which would divide by zero at 0
+const MIN_WHITE: f32 = 1.0e-3;
+
+fn aces(x: f32) -> f32 {
+    const A: f32 = 2.51;
+    const B: f32 = 0.03;
+    const C: f32 = 2.43;
+    const D: f32 = 0.59;
+    const E: f32 = 0.14;
+    let x = x.max(0.0);
+    glm::clamp_scalar((x * (A * x + B)) / (x * (C * x + D) + E), 0.0, 1.0)
+}
+
+/// ACES filmic at neutral exposure
+impl Default for ToneMapper {
+    fn default() -> Self {
+        ToneMapper::new(0.0, ToneMapOperator::AcesFilmic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ToneMapper, ToneMapOperator};
+    use crate::Color3;
+    use crate::color::{linear_to_srgb, srgb_to_linear};
+
+    #[test]
+    fn srgb_roundtrip() {
+        for i in 0..=255 {
+            let x = i as f32 / 255.0;
+            assert!((srgb_to_linear(linear_to_srgb(x)) - x).abs() <= 1.0e-5);
+        }
+    }
+
+    #[test]
+    fn srgb_encoding_rounds() {
+        // 18% gray encodes to 117.98 in sRGB, which should round up rather than truncate
+        let rgb = Color3::gray(0.18).to_srgb8();
+        assert_eq!(rgb.r, 118);
+        assert_eq!(Color3::gray(1.0).to_srgb8().g, 255);
+        assert_eq!(Color3::gray(-1.0).to_srgb8().b, 0);
+    }
+
+    #[test]
+    fn operators_stay_in_range() {
+        let operators = [
+            ToneMapOperator::Clamp,
+            ToneMapOperator::Reinhard,
+            ToneMapOperator::ReinhardExtended(4.0),
+            ToneMapOperator::AcesFilmic,
+        ];
+        for &op in operators.iter() {
+            let tm = ToneMapper::new(1.0, op);
+            for &v in [0.0, 0.01, 0.5, 1.0, 10.0, 1.0e6].iter() {
+                let c = tm.map(Color3::new(v, v * 0.5, v * 0.25));
+                assert!(c.r >= 0.0 && c.r <= 1.0, "{:?} {:?}", op, c);
+                assert!(c.g >= 0.0 && c.g <= 1.0, "{:?} {:?}", op, c);
+                assert!(c.b >= 0.0 && c.b <= 1.0, "{:?} {:?}", op, c);
+            }
+        }
+    }
+
+    #[test]
+    fn reinhard_extended_white_point() {
+        let tm = ToneMapper::new(0.0, ToneMapOperator::ReinhardExtended(4.0));
+        assert!((tm.map(Color3::gray(4.0)).r - 1.0).abs() <= 1.0e-5);
+        let degenerate = ToneMapper::new(0.0, ToneMapOperator::ReinhardExtended(0.0));
+        for &v in [0.0, 0.5, 100.0].iter() {
+            assert!(degenerate.map(Color3::gray(v)).r.is_finite());
+        }
+    }
+}
@@ -1,25 +1,151 @@
 use crate::primitive::Primitive;
 use crate::ray::{Ray, Hit};
+use crate::math::*;
+use crate::{Material, Light, Background, Medium, ColorSpace, TextureCache};
 use ord_subset::OrdSubsetIterExt;
+use std::collections::HashMap;
+use std::sync::Arc as Shared;
 
 pub struct World {
     pub primitives: Vec<Box<dyn Primitive + Send + Sync>>,
-    // TODO: lights
+    pub lights: Vec<Light>,
+    /// Seen by rays that miss everything, and lights the scene from all around
+    pub background: Background,
+    /// Fills the space around the primitives, such as fog
+    pub medium: Option<Shared<Medium>>,
+    /// The space that material, light and background colors are given in, and that rendering happens in.
+    /// Images and colors from elsewhere must be converted into it first.
+    pub color_space: ColorSpace,
+    /// The textures that materials refer to
+    pub textures: Shared<TextureCache>,
     // TODO: acceleration data structure
 }
 impl World {
     pub fn cast(&self, r: &Ray) -> Option<Hit> {
+        self.cast_filtered(r, 0.0, Scalar::INFINITY, &|_| true)
+    }
+
+    /**
+     * The nearest hit at a distance in [t_min, t_max] that `filter` accepts
+     *
+     * Rejected hits are looked past, so filters can ignore whole primitives by `object_id`, or cut holes in
+     * surfaces, such as leaves from an alpha mask.
+     */
+    pub fn cast_filtered(&self, r: &Ray, t_min: Scalar, t_max: Scalar, filter: &dyn Fn(&Hit) -> bool) -> Option<Hit> {
         self.primitives.iter()
-            .filter_map(|p| p.nearest_intersection(&r))
+            .enumerate()
+            .filter_map(|(i, p)| {
+                let mut t = t_min;
+                while let Some(mut h) = p.nearest_intersection_within(r, t, t_max) {
+                    h.object_id = i;
+                    if filter(&h) {
+                        return Some(h);
+                    }
+                    t = h.distance + consts::EPSILON * h.distance.abs().max(1.0);
+                }
+                None
+            })
             .ord_subset_min_by_key(|h| h.distance)
+            .map(|mut h| {
+                h.footprint = h.footprint_of(r);
+                h
+            })
+    }
+
+    /// Every hit at a distance in [t_min, t_max] that `filter` accepts, nearest first
+    pub fn all_intersections(&self, r: &Ray, t_min: Scalar, t_max: Scalar, filter: &dyn Fn(&Hit) -> bool) -> Vec<Hit> {
+        let mut hits: Vec<Hit> = self.primitives.iter()
+            .enumerate()
+            .flat_map(|(i, p)| p.all_intersections(r, t_min, t_max).into_iter().map(move |mut h| {
+                h.object_id = i;
+                h
+            }))
+            .filter(|h| filter(h))
+            .collect();
+        hits.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(std::cmp::Ordering::Equal));
+        hits
+    }
+
+    /// Every distinct material, in order of first use by `primitives`. Used to assign material IDs.
+    pub fn materials(&self) -> Vec<Shared<Material>> {
+        let mut materials: Vec<Shared<Material>> = vec![];
+        for m in self.primitives.iter().filter_map(|p| p.material()) {
+            if !materials.iter().any(|known| Shared::ptr_eq(known, m)) {
+                materials.push(m.clone());
+            }
+        }
+        materials
+    }
+
+    /// The index of each material in `materials`, to look up once per render
+    pub(crate) fn material_ids(&self) -> MaterialIds {
+        MaterialIds(self.materials().iter().enumerate().map(|(i, m)| (Shared::as_ptr(m) as usize, i)).collect())
+    }
+
+    /// Whether anything blocks the segment from the ray's origin out to `max_distance` along it
+    pub fn occluded(&self, r: &Ray, max_distance: Scalar) -> bool {
+        self.primitives.iter().any(|p| p.intersects(r, max_distance))
     }
 
-    // TODO: pub fn trace(...)
+    /// The nearest light with a surface that the ray hits, with the distance to it
+    pub fn cast_lights(&self, r: &Ray) -> Option<(&Light, Scalar)> {
+        self.lights.iter()
+            .filter_map(|l| l.intersect(r).map(|t| (l, t)))
+            .ord_subset_min_by_key(|&(_, t)| t)
+    }
+}
+/// Material IDs, keyed by the address of the material
+pub(crate) struct MaterialIds(HashMap<usize, usize>);
+impl MaterialIds {
+    pub fn get(&self, material: &Shared<Material>) -> Option<usize> {
+        self.0.get(&(Shared::as_ptr(material) as usize)).copied()
+    }
 }
+
 impl Default for World {
     fn default() -> Self {
         World {
             primitives: vec![],
+            lights: vec![],
+            background: Background::default(),
+            medium: None,
+            color_space: ColorSpace::LinearSrgb,
+            textures: Shared::new(TextureCache::default()),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::World;
+    use crate::math::*;
+    use crate::primitive::Sphere;
+    use crate::{Ray, Material};
+    use std::sync::Arc as Shared;
+
+    #[test]
+    fn filters_look_past_rejected_hits() {
+        let material = Shared::new(Material::default());
+        let world = World {
+            primitives: vec![
+                Box::new(Sphere::new(Vec3::new(0.0, 0.0, 3.0), 1.0, &material)),
+                Box::new(Sphere::new(Vec3::new(0.0, 0.0, 6.0), 1.0, &material)),
+            ],
+            ..World::default()
+        };
+        let ray = Ray::new(*consts::ORIGIN, *consts::FORWARD);
+        assert_eq!(world.cast(&ray).unwrap().object_id, 0);
+        let behind = world.cast_filtered(&ray, 0.0, Scalar::INFINITY, &|h| h.object_id != 0).unwrap();
+        assert_eq!(behind.object_id, 1);
+        assert!((behind.distance - 5.0).abs() <= 1.0e-6);
+        // Only the far side of each sphere
+        let backs = world.cast_filtered(&ray, 0.0, Scalar::INFINITY, &|h| h.distance > 3.0).unwrap();
+        assert!((backs.distance - 4.0).abs() <= 1.0e-6);
+        assert!(world.cast_filtered(&ray, 0.0, 1.5, &|_| true).is_none());
+
+        let all = world.all_intersections(&ray, 0.0, Scalar::INFINITY, &|_| true);
+        let distances: Vec<Scalar> = all.iter().map(|h| h.distance.round()).collect();
+        assert_eq!(distances, vec![2.0, 4.0, 5.0, 7.0]);
+        assert!(world.occluded(&ray, 2.5) && !world.occluded(&ray, 1.5));
+    }
+}